@@ -0,0 +1,42 @@
+// Replaces the sections of the panel the server reports as changed, so the page doesn't have to
+// be reloaded as a whole.
+(function () {
+    if (!window.EventSource) {
+        return;
+    }
+
+    // panels of profiles are served at /p/<name>
+    var profile = window.location.pathname.match(/^\/p\/([A-Za-z0-9_-]+)/);
+    var source = new EventSource(profile ? "/events?profile=" + profile[1] : "/events");
+    var disconnected = false;
+
+    source.addEventListener("section", function (event) {
+        var update = JSON.parse(event.data);
+        var element = document.getElementById(update.section);
+        if (element) {
+            element.outerHTML = update.html;
+        }
+
+        // e.g. the night theme starting at sunset
+        var replaced = document.getElementById(update.section);
+        if (replaced && replaced.hasAttribute("data-body-class")) {
+            document.body.className = replaced.getAttribute("data-body-class");
+        }
+    });
+
+    // sent when this page missed updates
+    source.addEventListener("reload", function () {
+        window.location.reload();
+    });
+
+    source.addEventListener("error", function () {
+        disconnected = true;
+    });
+
+    // changes while the connection was lost weren't received
+    source.addEventListener("open", function () {
+        if (disconnected) {
+            window.location.reload();
+        }
+    });
+})();
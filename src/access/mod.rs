@@ -0,0 +1,311 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::IpAddr;
+
+use axum::http::{HeaderMap, Method};
+use axum::http::header::{AUTHORIZATION, COOKIE};
+
+use crate::secrets;
+
+/// Cookie a device's token is kept in after it was sent once in the query.
+pub(crate) const TOKEN_COOKIE: &str = "panel_token";
+
+/// What a display device may do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    /// Show the panel and read the API.
+    ReadOnly,
+    /// Also complete todos and add entries.
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Device {
+    pub name: String,
+    token: String,
+    pub role: Role,
+}
+
+/// Who may use the panel, read from `data/access.conf`. Without that file everybody may do
+/// everything, as before.
+///
+/// - `allow=<address or network>`, e.g. `192.168.1.0/24`, limits the clients to those addresses
+/// - `device=<name>,<token>,<ro|rw>` requires a token from every client
+/// - `admin=<user>,<password>` requires HTTP Basic authentication for the admin pages
+///
+/// Each of them is optional and applies on its own.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AccessConfig {
+    pub devices: Vec<Device>,
+    admin: Option<(String, String)>,
+    allowed: Vec<IpRange>,
+}
+
+/// Permission a request needs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    /// Stylesheets, scripts, icons and the liveness check.
+    Public,
+    Read,
+    Write,
+    Admin,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    /// Allowed, by the named device if it sent a token.
+    Allow(Option<String>),
+    /// The client's address isn't allowed.
+    Forbidden,
+    /// Credentials are missing or wrong, `true` if HTTP Basic is asked for.
+    Unauthorized(bool),
+}
+
+/// Credentials a request came with.
+#[derive(Debug, Default)]
+pub struct Credentials {
+    pub token: Option<String>,
+    pub basic: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct IpRange {
+    network: IpAddr,
+    prefix: u32,
+}
+
+impl AccessConfig {
+    pub(crate) fn new(path_config: &str) -> Result<Self, String> {
+        let file = File::open(path_config).map_err(|e| {
+            format!("Failed to open '{}': {}", path_config, e)
+        })?;
+
+        let mut config = AccessConfig::default();
+        let reader = BufReader::new(file);
+        for line_result in reader.lines() {
+            let line = line_result.map_err(|e| {
+                format!("Error reading '{}': {}", path_config, e)
+            })?;
+
+            let invalid = |expected: &str| format!("Expected '{}' in '{}', got '{}'", expected, path_config, line);
+            match line.split_once('=') {
+                Some(("device", value)) => {
+                    let parts: Vec<&str> = value.splitn(3, ',').map(|part| part.trim()).collect();
+                    let role = match parts.get(2) {
+                        Some(&"ro") => Role::ReadOnly,
+                        Some(&"rw") => Role::ReadWrite,
+                        _default => return Err(invalid("device=<name>,<token>,<ro|rw>")),
+                    };
+                    if parts[0].is_empty() || parts[1].is_empty() {
+                        return Err(invalid("device=<name>,<token>,<ro|rw>"));
+                    }
+                    config.devices.push(Device { name: parts[0].to_string(), token: secrets::resolve(parts[1])?, role });
+                },
+                Some(("admin", value)) => match value.split_once(',') {
+                    Some((user, password)) if !user.trim().is_empty() && !password.trim().is_empty() => {
+                        config.admin = Some((user.trim().to_string(), secrets::resolve(password)?));
+                    },
+                    _default => return Err(invalid("admin=<user>,<password>")),
+                },
+                Some(("allow", value)) => config.allowed.push(IpRange::parse(value.trim()).ok_or_else(|| invalid("allow=<address or network>"))?),
+                _default => (),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Decides about a request of `client` needing `permission`.
+    pub(crate) fn decide(&self, client: IpAddr, permission: Permission, credentials: &Credentials) -> Decision {
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|range| range.contains(client)) {
+            return Decision::Forbidden;
+        }
+        if permission == Permission::Public {
+            return Decision::Allow(None);
+        }
+
+        let is_admin = match (&self.admin, &credentials.basic) {
+            (Some((user, password)), Some((sent_user, sent_password))) => {
+                constant_time_eq(user, sent_user) & constant_time_eq(password, sent_password)
+            },
+            _default => false,
+        };
+        if is_admin {
+            return Decision::Allow(None);
+        }
+        if permission == Permission::Admin && self.admin.is_some() {
+            return Decision::Unauthorized(true);
+        }
+
+        if self.devices.is_empty() {
+            return Decision::Allow(None);
+        }
+        let device = credentials.token.as_ref()
+            .and_then(|token| self.devices.iter().find(|device| constant_time_eq(&device.token, token)));
+
+        match device {
+            Some(device) if permission == Permission::Read || device.role == Role::ReadWrite => Decision::Allow(Some(device.name.clone())),
+            // a read-only device, or none, might have an admin behind it
+            _default => Decision::Unauthorized(self.admin.is_some()),
+        }
+    }
+}
+
+/// The permission a request needs: writing for everything but reading, admin for the status
+/// page and its API, none for the liveness check.
+pub(crate) fn permission_of(method: &Method, path: &str) -> Permission {
+    if path.starts_with("/styles/") || path.starts_with("/scripts/") || path.starts_with("/icons/") || path == "/health" {
+        Permission::Public
+    } else if path == "/status" || path.starts_with("/status/") || path == "/api/v1/status" {
+        Permission::Admin
+    } else if method != Method::GET && method != Method::HEAD || path == "/add" {
+        Permission::Write
+    } else {
+        Permission::Read
+    }
+}
+
+impl Credentials {
+    /// The token of the query (`?token=`), a bearer header or the cookie, and HTTP Basic
+    /// credentials.
+    pub(crate) fn from_request(headers: &HeaderMap, query: Option<&str>) -> Self {
+        let authorization = headers.get(AUTHORIZATION).and_then(|value| value.to_str().ok()).unwrap_or_default();
+
+        let query_token = query_token(query);
+        let bearer_token = authorization.strip_prefix("Bearer ").map(|token| token.trim().to_string());
+        let cookie_token = headers.get_all(COOKIE).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == TOKEN_COOKIE)
+            .map(|(_, token)| token.to_string());
+
+        let basic = authorization.strip_prefix("Basic ")
+            .and_then(|encoded| openssl::base64::decode_block(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| decoded.split_once(':').map(|(user, password)| (user.to_string(), password.to_string())));
+
+        Credentials { token: query_token.or(bearer_token).or(cookie_token), basic }
+    }
+}
+
+/// The token of a query like `token=<token>&…`.
+pub(crate) fn query_token(query: Option<&str>) -> Option<String> {
+    query?.split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| *name == "token")
+        .map(|(_, token)| token.to_string())
+}
+
+impl IpRange {
+    // "192.168.1.20", "192.168.1.0/24" or "fd00::/8"
+    fn parse(text: &str) -> Option<Self> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix.parse::<u32>().ok()?)),
+            None => (text, None),
+        };
+        let network = address.parse::<IpAddr>().ok()?;
+        let bits = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(bits);
+
+        Some(IpRange { network, prefix }).filter(|_| prefix <= bits)
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        // IPv4 clients of a dual stack server show up as ::ffff:a.b.c.d
+        let address = match address {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+            v4 => v4,
+        };
+
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            },
+            _default => false,
+        }
+    }
+}
+
+// compares secrets without leaking how much of them matched through the time taken
+fn constant_time_eq(expected: &str, actual: &str) -> bool {
+    if expected.len() != actual.len() {
+        return false;
+    }
+    expected.bytes().zip(actual.bytes()).fold(0u8, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use axum::http::{HeaderMap, HeaderValue, Method};
+    use axum::http::header::{AUTHORIZATION, COOKIE};
+
+    use crate::access::{AccessConfig, Credentials, Decision, IpRange, Permission, permission_of};
+
+    #[test]
+    fn access_decisions() {
+        let config = AccessConfig::new("data/test/access_test.conf").unwrap();
+        let home: IpAddr = "192.168.1.23".parse().unwrap();
+        let token = |token: &str| Credentials { token: Some(token.to_string()), basic: None };
+
+        assert_eq!(config.decide("10.0.0.5".parse().unwrap(), Permission::Read, &token("kitchen-token")), Decision::Forbidden);
+        assert_eq!(config.decide(home, Permission::Public, &Credentials::default()), Decision::Allow(None));
+        assert_eq!(config.decide(home, Permission::Read, &Credentials::default()), Decision::Unauthorized(true));
+        assert_eq!(config.decide(home, Permission::Read, &token("kitchen-token")), Decision::Allow(Some("kitchen".to_string())));
+        assert_eq!(config.decide(home, Permission::Write, &token("kitchen-token")), Decision::Unauthorized(true));
+        assert_eq!(config.decide(home, Permission::Write, &token("phone-token")), Decision::Allow(Some("phone".to_string())));
+        assert_eq!(config.decide(home, Permission::Admin, &token("phone-token")), Decision::Unauthorized(true));
+
+        let admin = Credentials { token: None, basic: Some(("admin".to_string(), "geheim".to_string())) };
+        assert_eq!(config.decide(home, Permission::Admin, &admin), Decision::Allow(None));
+        let wrong = Credentials { token: None, basic: Some(("admin".to_string(), "falsch".to_string())) };
+        assert_eq!(config.decide(home, Permission::Admin, &wrong), Decision::Unauthorized(true));
+
+        // without a config everything is allowed
+        assert_eq!(AccessConfig::default().decide(home, Permission::Admin, &Credentials::default()), Decision::Allow(None));
+    }
+
+    #[test]
+    fn ip_ranges() {
+        let range = IpRange::parse("192.168.1.0/24").unwrap();
+        assert!(range.contains("192.168.1.200".parse().unwrap()));
+        assert!(range.contains("::ffff:192.168.1.7".parse().unwrap()));
+        assert!(!range.contains("192.168.2.1".parse().unwrap()));
+
+        assert!(IpRange::parse("fd00::/8").unwrap().contains("fd12:3456::1".parse().unwrap()));
+        assert!(IpRange::parse("0.0.0.0/0").unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert_eq!(IpRange::parse("192.168.1.0/33"), None);
+    }
+
+    #[test]
+    fn request_credentials() {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_static("theme=dark; panel_token=from-cookie"));
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Basic YWRtaW46Z2VoZWlt"));
+
+        let credentials = Credentials::from_request(&headers, Some("days=3&token=from-query"));
+        assert_eq!(credentials.token, Some("from-query".to_string()));
+        assert_eq!(credentials.basic, Some(("admin".to_string(), "geheim".to_string())));
+
+        assert_eq!(Credentials::from_request(&headers, None).token, Some("from-cookie".to_string()));
+    }
+
+    #[test]
+    fn permissions() {
+        assert_eq!(permission_of(&Method::GET, "/"), Permission::Read);
+        assert_eq!(permission_of(&Method::GET, "/styles/styles.css"), Permission::Public);
+        assert_eq!(permission_of(&Method::GET, "/add"), Permission::Write);
+        assert_eq!(permission_of(&Method::POST, "/todos/complete"), Permission::Write);
+        assert_eq!(permission_of(&Method::GET, "/status"), Permission::Admin);
+        assert_eq!(permission_of(&Method::GET, "/api/v1/status"), Permission::Admin);
+        assert_eq!(permission_of(&Method::GET, "/health"), Permission::Public);
+        assert_eq!(permission_of(&Method::GET, "/scripts/live.js"), Permission::Public);
+        assert_eq!(permission_of(&Method::GET, "/events"), Permission::Read);
+    }
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use json::{array, object, JsonValue};
+use log::{debug, error, warn};
+
+use crate::filesystem::FileSystemHandler;
+use crate::ics_feed;
+use crate::local_calendar;
+use crate::local_calendar::LocalCalendar;
+use crate::locale::Locale;
+use crate::webdav;
+use crate::webdav::calendar::Calendar;
+
+// colours of calendars that don't have one
+const PALETTE: [&str; 6] = ["#1E88E5", "#D81B60", "#43A047", "#FB8C00", "#8E24AA", "#00897B"];
+
+// e-ink displays tell calendars apart by these, see the `pattern-*` classes in styles.css
+const PATTERNS: [&str; 5] = ["solid", "striped", "dotted", "checked", "outline"];
+
+pub struct AgendaConfig {
+    // CalDAV calendars of webdav.conf
+    pub calendars: Vec<String>,
+    pub days: u32,
+    pub styles: Vec<StyleOverride>,
+    pub eink: bool,
+}
+
+/// Label and colour of a calendar set in the config, overriding those of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleOverride {
+    pub name: String,
+    pub label: String,
+    pub color: String,
+}
+
+/// How entries of a calendar are told apart from those of the others.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalendarStyle {
+    pub label: String,
+    pub color: String,
+    // shade of grey with the same brightness as the colour
+    pub grey: String,
+    pub pattern: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgendaDay {
+    pub date: NaiveDate,
+    pub entries: Vec<AgendaEntry>,
+}
+
+/// An event on one day. Events lasting several days have an entry on each of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgendaEntry {
+    pub summary: String,
+    pub location: String,
+    // start and end of the whole event in the display zone
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+    // all-day events and the days in between of timed events lasting several days
+    pub is_all_day: bool,
+    // e.g. "10:00 - 11:00", "ab 18:00" or "bis 12:00"
+    pub time: String,
+    // counted from 1
+    pub day_of_span: i64,
+    pub span_days: i64,
+    pub style: CalendarStyle,
+}
+
+impl AgendaConfig {
+    pub(crate) fn new(path_config: &str) -> Result<Self, String> {
+        let mut calendars: Vec<String> = Vec::new();
+        let mut days: u32 = 7;
+        let mut styles: Vec<StyleOverride> = Vec::new();
+        let mut eink: bool = false;
+
+        let file = File::open(path_config).map_err(|e| {
+            format!("Failed to open '{}': {}", path_config, e)
+        })?;
+
+        //read config
+        let reader = BufReader::new(file);
+        for line_result in reader.lines() {
+            let line = line_result.map_err(|e| {
+                format!("Error reading '{}': {}", path_config, e)
+            })?;
+
+            if let Some((key, value)) = line.split_once('=') {
+                match key {
+                    "calendars" => calendars = value.split(',')
+                        .map(|name| name.trim().to_string())
+                        .filter(|name| !name.is_empty())
+                        .collect(),
+                    "days" => days = value.trim().parse::<u32>().map_err(|e| {
+                        format!("Invalid number of days '{}' in '{}': {}", value, path_config, e)
+                    })?,
+                    "style" => {
+                        let parts: Vec<&str> = value.split(',').map(|part| part.trim()).collect();
+                        match parts.as_slice() {
+                            [name, label, color] => styles.push(StyleOverride {
+                                name: name.to_string(),
+                                label: label.to_string(),
+                                color: color.to_string(),
+                            }),
+                            _ => return Err(format!("Expected 'style=<name>,<label>,<colour>' in '{}', got '{}'", path_config, line)),
+                        }
+                    },
+                    "eink" => eink = value.trim() == "true",
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(AgendaConfig { calendars, days, styles, eink })
+    }
+
+    /// Style of the `index`th calendar. Empty labels and colours of an override keep those of
+    /// the source.
+    pub(crate) fn style_of(&self, calendar: &Calendar, index: usize) -> CalendarStyle {
+        let style_override = self.styles.iter().find(|style| style.name == calendar.name);
+
+        let label = style_override.map(|style| style.label.as_str())
+            .filter(|label| !label.is_empty())
+            .or(Some(calendar.display_name.as_str()).filter(|label| !label.is_empty()))
+            .unwrap_or(&calendar.name);
+
+        let color = style_override.map(|style| style.color.as_str())
+            .filter(|color| !color.is_empty())
+            .or(Some(calendar.color.as_str()).filter(|color| !color.is_empty()))
+            .unwrap_or(PALETTE[index % PALETTE.len()]);
+
+        CalendarStyle::new(label, color, index)
+    }
+}
+
+impl CalendarStyle {
+    pub(crate) fn new(label: &str, color: &str, index: usize) -> Self {
+        let color = normalise_color(color).unwrap_or_else(|| PALETTE[index % PALETTE.len()].to_string());
+
+        CalendarStyle {
+            label: label.to_string(),
+            grey: grey_of(&color),
+            color,
+            pattern: PATTERNS[index % PATTERNS.len()],
+        }
+    }
+}
+
+/// Downloads the CalDAV calendars of those names. Calendars that can't be read are left out.
+pub(crate) async fn read_caldav_calendars(names: &[String]) -> HashMap<String, Calendar> {
+    let mut calendars: HashMap<String, Calendar> = HashMap::new();
+
+    for name in names {
+        let calendar_name = name.clone();
+        // the WebDAV client is blocking
+        match tokio::task::spawn_blocking(move || webdav::read_calendar("webdav.conf", &calendar_name)).await {
+            Ok(Some(calendar)) => {
+                calendars.insert(name.clone(), calendar);
+            },
+            Ok(None) => warn!("Leaving calendar '{}' out of the agenda", name),
+            Err(e) => error!("Reading calendar '{}' failed: {}", name, e),
+        }
+    }
+    calendars
+}
+
+/// Downloads the ics feeds, none without a feed config.
+pub(crate) async fn read_feeds() -> Vec<Calendar> {
+    if !Path::new("data/feeds.conf").exists() {
+        debug!("No feeds configured");
+        return vec![];
+    }
+
+    match FileSystemHandler::new() {
+        Ok(filesystem_handler) => match ics_feed::read_calendars("data/feeds.conf", &filesystem_handler).await {
+            Ok(feeds) => feeds,
+            Err(e) => {
+                warn!("Leaving feeds out of the agenda: {}", e);
+                vec![]
+            }
+        },
+        Err(e) => {
+            error!("Failed to create FileSystemHandler: {}", e);
+            vec![]
+        }
+    }
+}
+
+/// Every calendar shown in the agenda: the configured ones of the downloaded CalDAV calendars,
+/// the ics feeds and the local calendars.
+pub(crate) fn collect_calendars(config: &AgendaConfig, caldav: &HashMap<String, Calendar>, feeds: &[Calendar], local_calendars: &Mutex<Vec<LocalCalendar>>) -> Vec<(Calendar, CalendarStyle)> {
+    let mut calendars: Vec<Calendar> = config.calendars.iter()
+        .filter_map(|name| caldav.get(name).cloned())
+        .collect();
+    calendars.extend_from_slice(feeds);
+    calendars.extend(local_calendar::read_calendars("data/local_calendars.conf", local_calendars));
+
+    calendars.into_iter()
+        .enumerate()
+        .map(|(index, calendar)| {
+            let style = config.style_of(&calendar, index);
+            (calendar, style)
+        })
+        .collect()
+}
+
+/// Events of all calendars from `from` on for `days` days, grouped by day.
+///
+/// Days are sorted and include days without events. Within a day all-day entries come first,
+/// then the entries by start. Cancelled events are left out.
+pub(crate) fn build_agenda(calendars: &[(Calendar, CalendarStyle)], from: NaiveDate, days: u32, locale: Locale) -> Vec<AgendaDay> {
+    let to = from + Duration::days(days as i64);
+    let mut agenda: Vec<AgendaDay> = (0..days as i64)
+        .map(|day| AgendaDay { date: from + Duration::days(day), entries: vec![] })
+        .collect();
+
+    for (calendar, style) in calendars {
+        let occurrences = calendar.occurrences(from.and_time(NaiveTime::default()), to.and_time(NaiveTime::default()));
+
+        for occurrence in occurrences.iter().filter(|occurrence| !occurrence.event.is_cancelled()) {
+            let first_day = occurrence.start.date();
+            let last_day = last_day_of(occurrence.start, occurrence.end);
+            let span_days = (last_day - first_day).num_days() + 1;
+
+            for day in agenda.iter_mut().filter(|day| day.date >= first_day && day.date <= last_day) {
+                let day_of_span = (day.date - first_day).num_days() + 1;
+                let is_all_day = occurrence.event.is_all_day || (day_of_span > 1 && day_of_span < span_days);
+
+                day.entries.push(AgendaEntry {
+                    summary: occurrence.event.summary.clone(),
+                    location: occurrence.event.location.clone(),
+                    start: occurrence.start,
+                    end: occurrence.end,
+                    is_all_day,
+                    time: time_label(occurrence.start, occurrence.end, is_all_day, day_of_span, span_days, locale),
+                    day_of_span,
+                    span_days,
+                    style: style.clone(),
+                });
+            }
+        }
+    }
+
+    for day in agenda.iter_mut() {
+        day.entries.sort_by(|a, b| b.is_all_day.cmp(&a.is_all_day)
+            .then_with(|| a.start.cmp(&b.start))
+            .then_with(|| a.summary.cmp(&b.summary)));
+    }
+    agenda
+}
+
+/// The agenda as served by the JSON API.
+pub(crate) fn to_json(agenda: &[AgendaDay]) -> JsonValue {
+    let mut days = array![];
+    for day in agenda {
+        let mut entries = array![];
+        for entry in &day.entries {
+            let _ = entries.push(object!{
+                summary: entry.summary.as_str(),
+                location: entry.location.as_str(),
+                start: entry.start.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                end: entry.end.format("%Y-%m-%dT%H:%M:%S").to_string(),
+                all_day: entry.is_all_day,
+                time: entry.time.as_str(),
+                day_of_span: entry.day_of_span,
+                span_days: entry.span_days,
+                calendar: entry.style.label.as_str(),
+                color: entry.style.color.as_str(),
+                grey: entry.style.grey.as_str(),
+                pattern: entry.style.pattern,
+            });
+        }
+        let _ = days.push(object!{
+            date: day.date.format("%Y-%m-%d").to_string(),
+            entries: entries,
+        });
+    }
+    days
+}
+
+// the end is exclusive, an event ending at midnight doesn't last into that day
+fn last_day_of(start: NaiveDateTime, end: NaiveDateTime) -> NaiveDate {
+    if end > start && end.time() == NaiveTime::default() {
+        (end - Duration::days(1)).date()
+    } else {
+        end.date().max(start.date())
+    }
+}
+
+fn time_label(start: NaiveDateTime, end: NaiveDateTime, is_all_day: bool, day_of_span: i64, span_days: i64, locale: Locale) -> String {
+    if is_all_day {
+        return String::new();
+    }
+
+    match (day_of_span, span_days) {
+        (_, 1) if start == end => start.format("%H:%M").to_string(),
+        (_, 1) => format!("{} - {}", start.format("%H:%M"), end.format("%H:%M")),
+        (1, _) => format!("{} {}", locale.text("ab"), start.format("%H:%M")),
+        _ => format!("{} {}", locale.text("bis"), end.format("%H:%M")),
+    }
+}
+
+// #RGB, #RRGGBB and #RRGGBBAA as sent by Nextcloud become #RRGGBB
+fn normalise_color(color: &str) -> Option<String> {
+    let hex = color.trim().strip_prefix('#')?;
+    if !hex.chars().all(|character| character.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    match hex.len() {
+        3 => Some(format!("#{}", hex.chars().flat_map(|character| [character, character]).collect::<String>())),
+        6 | 8 => Some(format!("#{}", &hex[..6])),
+        _ => None,
+    }.map(|color| color.to_uppercase())
+}
+
+fn grey_of(color: &str) -> String {
+    let channel = |range: std::ops::Range<usize>| u8::from_str_radix(&color[range], 16).unwrap_or_default() as f64;
+    let luminance = 0.299 * channel(1..3) + 0.587 * channel(3..5) + 0.114 * channel(5..7);
+
+    let grey = luminance.round() as u8;
+    format!("#{:02X}{:02X}{:02X}", grey, grey, grey)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use chrono::NaiveDate;
+
+    use crate::agenda::{AgendaConfig, build_agenda, CalendarStyle, normalise_color};
+    use crate::locale::Locale;
+    use crate::webdav::calendar::Calendar;
+
+    fn read_ics(name: &str) -> String {
+        let mut file = File::open(format!("data/test/{}", name)).unwrap();
+        let mut ics = String::new();
+        file.read_to_string(&mut ics).unwrap();
+        ics
+    }
+
+    #[test]
+    fn agenda_building() {
+        let mut waste = Calendar::new("abfall".to_string(), &[read_ics("vevent.ics")]);
+        waste.display_name = "Abfall".to_string();
+        waste.color = "#D09E6DFF".to_string();
+        let family = Calendar::new("family".to_string(), &[read_ics("vevent_trip.ics")]);
+
+        let config = AgendaConfig::new("data/test/agenda_test.conf").unwrap();
+        let waste_style = config.style_of(&waste, 0);
+        let family_style = config.style_of(&family, 1);
+        let calendars = vec![(waste, waste_style), (family, family_style)];
+
+        assert_eq!(calendars[0].1, CalendarStyle {
+            label: "Abfall".to_string(),
+            color: "#D09E6D".to_string(),
+            grey: "#A7A7A7".to_string(),
+            pattern: "solid",
+        });
+        assert_eq!(calendars[1].1.label, "Familie");
+        assert_eq!(calendars[1].1.pattern, "striped");
+
+        let agenda = build_agenda(&calendars, NaiveDate::from_ymd_opt(2022,7,25).unwrap(), 4, Locale::German);
+        let summaries: Vec<Vec<(&str, &str)>> = agenda.iter()
+            .map(|day| day.entries.iter().map(|entry| (entry.summary.as_str(), entry.time.as_str())).collect())
+            .collect();
+
+        // the trip runs from the 25th 18:00 to the 27th 12:00 and counts as all day in between,
+        // where it comes first as it started earlier
+        assert_eq!(summaries, vec![
+            vec![("Ausflug", "ab 18:00")],
+            vec![("Ausflug", ""), ("Restmülltonne, Biotonne, Altpapiertonne, Gelber Sack", ""), ("Zahnarzt", "10:00 - 10:30")],
+            vec![("Ausflug", "bis 12:00")],
+            vec![],
+        ]);
+        assert_eq!(agenda[1].entries[0].day_of_span, 2);
+        assert_eq!(agenda[1].entries[0].span_days, 3);
+    }
+
+    #[test]
+    fn color_normalisation() {
+        assert_eq!(normalise_color("#d09e6dff"), Some("#D09E6D".to_string()));
+        assert_eq!(normalise_color("#abc"), Some("#AABBCC".to_string()));
+        assert_eq!(normalise_color("red"), None);
+    }
+}
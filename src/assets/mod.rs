@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::{Component, Path};
+use std::sync::OnceLock;
+
+// directory whose files take precedence over the built-in ones, set once at the start
+static OVERRIDE_DIRECTORY: OnceLock<Option<String>> = OnceLock::new();
+
+/// Templates, styles, scripts and weather icons of the web interface compiled into the binary, by
+/// their path below the override directory. Weather icons are named after OpenWeather's icon codes
+/// and come in sets, `icons/<set>/<code>.svg`: coloured ones and monochrome ones for e-ink.
+const EMBEDDED: [(&str, &[u8]); 41] = [
+    ("templates/index.html", include_bytes!("../website/index.html")),
+    ("templates/add.html", include_bytes!("../website/add.html")),
+    ("templates/status.html", include_bytes!("../website/status.html")),
+    ("styles/styles.css", include_bytes!("../../data/styles/styles.css")),
+    ("scripts/live.js", include_bytes!("../../data/scripts/live.js")),
+    ("icons/colour/01d.svg", include_bytes!("../../data/icons/colour/clear_day.svg")),
+    ("icons/colour/01n.svg", include_bytes!("../../data/icons/colour/clear_night.svg")),
+    ("icons/colour/02d.svg", include_bytes!("../../data/icons/colour/few_clouds_day.svg")),
+    ("icons/colour/02n.svg", include_bytes!("../../data/icons/colour/few_clouds_night.svg")),
+    ("icons/colour/03d.svg", include_bytes!("../../data/icons/colour/clouds.svg")),
+    ("icons/colour/03n.svg", include_bytes!("../../data/icons/colour/clouds.svg")),
+    ("icons/colour/04d.svg", include_bytes!("../../data/icons/colour/clouds.svg")),
+    ("icons/colour/04n.svg", include_bytes!("../../data/icons/colour/clouds.svg")),
+    ("icons/colour/09d.svg", include_bytes!("../../data/icons/colour/shower_rain.svg")),
+    ("icons/colour/09n.svg", include_bytes!("../../data/icons/colour/shower_rain.svg")),
+    ("icons/colour/10d.svg", include_bytes!("../../data/icons/colour/rain_day.svg")),
+    ("icons/colour/10n.svg", include_bytes!("../../data/icons/colour/rain_night.svg")),
+    ("icons/colour/11d.svg", include_bytes!("../../data/icons/colour/thunderstorm.svg")),
+    ("icons/colour/11n.svg", include_bytes!("../../data/icons/colour/thunderstorm.svg")),
+    ("icons/colour/13d.svg", include_bytes!("../../data/icons/colour/snow.svg")),
+    ("icons/colour/13n.svg", include_bytes!("../../data/icons/colour/snow.svg")),
+    ("icons/colour/50d.svg", include_bytes!("../../data/icons/colour/mist.svg")),
+    ("icons/colour/50n.svg", include_bytes!("../../data/icons/colour/mist.svg")),
+    ("icons/mono/01d.svg", include_bytes!("../../data/icons/mono/clear_day.svg")),
+    ("icons/mono/01n.svg", include_bytes!("../../data/icons/mono/clear_night.svg")),
+    ("icons/mono/02d.svg", include_bytes!("../../data/icons/mono/few_clouds_day.svg")),
+    ("icons/mono/02n.svg", include_bytes!("../../data/icons/mono/few_clouds_night.svg")),
+    ("icons/mono/03d.svg", include_bytes!("../../data/icons/mono/clouds.svg")),
+    ("icons/mono/03n.svg", include_bytes!("../../data/icons/mono/clouds.svg")),
+    ("icons/mono/04d.svg", include_bytes!("../../data/icons/mono/clouds.svg")),
+    ("icons/mono/04n.svg", include_bytes!("../../data/icons/mono/clouds.svg")),
+    ("icons/mono/09d.svg", include_bytes!("../../data/icons/mono/shower_rain.svg")),
+    ("icons/mono/09n.svg", include_bytes!("../../data/icons/mono/shower_rain.svg")),
+    ("icons/mono/10d.svg", include_bytes!("../../data/icons/mono/rain_day.svg")),
+    ("icons/mono/10n.svg", include_bytes!("../../data/icons/mono/rain_night.svg")),
+    ("icons/mono/11d.svg", include_bytes!("../../data/icons/mono/thunderstorm.svg")),
+    ("icons/mono/11n.svg", include_bytes!("../../data/icons/mono/thunderstorm.svg")),
+    ("icons/mono/13d.svg", include_bytes!("../../data/icons/mono/snow.svg")),
+    ("icons/mono/13n.svg", include_bytes!("../../data/icons/mono/snow.svg")),
+    ("icons/mono/50d.svg", include_bytes!("../../data/icons/mono/mist.svg")),
+    ("icons/mono/50n.svg", include_bytes!("../../data/icons/mono/mist.svg")),
+];
+
+/// Makes files of `directory` take precedence over the built-in ones, e.g. a changed
+/// `templates/index.html` or additional fonts below `styles/`.
+pub(crate) fn set_override_directory(directory: Option<String>) {
+    let _ = OVERRIDE_DIRECTORY.set(directory);
+}
+
+/// The file of the override directory, or the built-in one.
+pub(crate) fn read(path: &str) -> Result<Vec<u8>, String> {
+    read_from(OVERRIDE_DIRECTORY.get().and_then(Option::as_deref), path)
+}
+
+pub(crate) fn read_to_string(path: &str) -> Result<String, String> {
+    String::from_utf8(read(path)?).map_err(|e| format!("'{}' isn't UTF-8: {}", path, e))
+}
+
+/// The content type of a file by its extension.
+pub(crate) fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).as_deref() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        Some("woff") => "font/woff",
+        Some("ttf") => "font/ttf",
+        _default => "application/octet-stream",
+    }
+}
+
+fn read_from(directory: Option<&str>, path: &str) -> Result<Vec<u8>, String> {
+    // only files below the directory
+    if path.is_empty() || !Path::new(path).components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(format!("Invalid asset path '{}'", path));
+    }
+
+    if let Some(directory) = directory {
+        let overridden = Path::new(directory).join(path);
+        if overridden.is_file() {
+            return fs::read(&overridden).map_err(|e| format!("Error reading '{}': {}", overridden.display(), e));
+        }
+    }
+
+    EMBEDDED.iter()
+        .find(|(embedded, _)| *embedded == path)
+        .map(|(_, content)| content.to_vec())
+        .ok_or_else(|| format!("No asset '{}'", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::assets::{content_type, read_from};
+
+    #[test]
+    fn embedded() {
+        let index = String::from_utf8(read_from(None, "templates/index.html").unwrap()).unwrap();
+        assert!(index.contains("#panel"));
+        assert!(read_from(None, "styles/styles.css").is_ok());
+        assert!(read_from(None, "styles/missing.css").is_err());
+        // the live updates of the panel need their script without a data directory
+        assert!(String::from_utf8(read_from(None, "scripts/live.js").unwrap()).unwrap().contains("EventSource"));
+        assert!(read_from(None, "icons/colour/10n.svg").unwrap().starts_with(b"<svg"));
+        assert!(read_from(None, "icons/mono/10n.svg").unwrap().starts_with(b"<svg"));
+    }
+
+    #[test]
+    fn overridden() {
+        let directory = std::env::temp_dir().join(format!("info_panel_assets_{}", std::process::id()));
+        fs::create_dir_all(directory.join("styles/fonts")).unwrap();
+        fs::write(directory.join("styles/styles.css"), "body { color: #000; }").unwrap();
+        fs::write(directory.join("styles/fonts/panel.woff2"), "font").unwrap();
+        let directory = directory.display().to_string();
+
+        assert_eq!(read_from(Some(&directory), "styles/styles.css").unwrap(), b"body { color: #000; }");
+        assert_eq!(read_from(Some(&directory), "styles/fonts/panel.woff2").unwrap(), b"font");
+        // files the directory doesn't have are built in
+        assert!(read_from(Some(&directory), "templates/index.html").is_ok());
+        assert!(read_from(Some(&directory), "styles/../../secret.conf").is_err());
+        assert!(read_from(Some(&directory), "/etc/passwd").is_err());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!(content_type("styles/styles.css"), "text/css; charset=utf-8");
+        assert_eq!(content_type("styles/fonts/Panel.WOFF2"), "font/woff2");
+        assert_eq!(content_type("scripts/live.js"), "text/javascript; charset=utf-8");
+        assert_eq!(content_type("scripts/README"), "application/octet-stream");
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Utc};
+
+use crate::secrets;
+
+// what is known about every data source since the start, by the key of its cache
+static SOURCES: OnceLock<Mutex<BTreeMap<String, SourceHealth>>> = OnceLock::new();
+
+static STARTED: OnceLock<DateTime<Utc>> = OnceLock::new();
+
+/// The state of a data source, e.g. a weather request, calendar or feed.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SourceHealth {
+    pub last_success: Option<DateTime<Utc>>,
+    /// Time and message of the last failure, also after a later success.
+    pub last_error: Option<(DateTime<Utc>, String)>,
+    /// When the source is read next, if it is read on a schedule.
+    pub next_refresh: Option<DateTime<Utc>>,
+    /// When the result shown was read.
+    pub cached_at: Option<DateTime<Utc>>,
+}
+
+/// Remembers the start of the panel, for its uptime.
+pub(crate) fn start() {
+    STARTED.get_or_init(Utc::now);
+}
+
+pub(crate) fn started() -> DateTime<Utc> {
+    *STARTED.get_or_init(Utc::now)
+}
+
+/// Records a request of `source`. Secrets are redacted from error messages.
+pub(crate) fn record<T>(source: &str, result: &Result<T, String>) {
+    let now = Utc::now();
+    update(source, |health| match result {
+        Ok(_) => {
+            health.last_success = Some(now);
+            health.cached_at = Some(now);
+        },
+        Err(e) => health.last_error = Some((now, secrets::redact(e))),
+    });
+}
+
+/// Records when the result shown of `source` was read, e.g. of a copy kept on disk.
+pub(crate) fn record_cached(source: &str, cached_at: Option<DateTime<Utc>>) {
+    update(source, |health| health.cached_at = cached_at);
+}
+
+pub(crate) fn schedule(source: &str, next_refresh: DateTime<Utc>) {
+    update(source, |health| health.next_refresh = Some(next_refresh));
+}
+
+/// All sources read since the start, by name.
+pub(crate) fn sources() -> Vec<(String, SourceHealth)> {
+    match SOURCES.get().map(|sources| sources.lock()) {
+        Some(Ok(sources)) => sources.iter().map(|(name, health)| (name.clone(), health.clone())).collect(),
+        _default => vec![],
+    }
+}
+
+/// Whether the source failed the last time it was read.
+pub(crate) fn is_failing(health: &SourceHealth) -> bool {
+    match (&health.last_error, health.last_success) {
+        (Some((failed, _)), Some(succeeded)) => *failed > succeeded,
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+/// The status of the panel and its sources, as served by `/api/v1/status`.
+pub(crate) fn to_json(sources: &[(String, SourceHealth)], quotas: json::JsonValue, now: DateTime<Utc>) -> json::JsonValue {
+    let time = |time: Option<DateTime<Utc>>| time.map(|time| time.to_rfc3339());
+
+    let sources: Vec<json::JsonValue> = sources.iter()
+        .map(|(name, health)| json::object!{
+            name: name.as_str(),
+            ok: !is_failing(health),
+            last_success: time(health.last_success),
+            last_error: health.last_error.as_ref().map(|(at, message)| json::object!{ time: at.to_rfc3339(), message: message.as_str() }),
+            next_refresh: time(health.next_refresh),
+            cache_age_seconds: health.cached_at.map(|cached_at| (now - cached_at).num_seconds()),
+        })
+        .collect();
+
+    json::object!{
+        version: env!("CARGO_PKG_VERSION"),
+        started: started().to_rfc3339(),
+        uptime_seconds: (now - started()).num_seconds(),
+        ok: !sources.iter().any(|source| source["ok"] == false),
+        sources: sources,
+        quotas: quotas,
+    }
+}
+
+fn update(source: &str, change: impl FnOnce(&mut SourceHealth)) {
+    if let Ok(mut sources) = SOURCES.get_or_init(|| Mutex::new(BTreeMap::new())).lock() {
+        change(sources.entry(source.to_string()).or_default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use crate::health::{is_failing, record, record_cached, schedule, SourceHealth, sources, to_json};
+
+    #[test]
+    fn recorded_sources() {
+        record::<()>("health_test_weather", &Err("Failed to reach https://example.org/?appid=0123abcd".to_string()));
+        let (_, health) = sources().into_iter().find(|(name, _)| name == "health_test_weather").unwrap();
+        assert!(is_failing(&health));
+        assert_eq!(health.last_error.unwrap().1, "Failed to reach https://example.org/?appid=***");
+
+        record("health_test_weather", &Ok(()));
+        let next_refresh = Utc::now() + Duration::minutes(10);
+        schedule("health_test_weather", next_refresh);
+        let (_, health) = sources().into_iter().find(|(name, _)| name == "health_test_weather").unwrap();
+        assert!(!is_failing(&health));
+        assert!(health.last_error.is_some());
+        assert_eq!(health.next_refresh, Some(next_refresh));
+
+        record_cached("health_test_feed", None);
+        assert!(sources().iter().any(|(name, health)| name == "health_test_feed" && *health == SourceHealth::default()));
+    }
+
+    #[test]
+    fn status_json() {
+        let now = Utc::now();
+        let health = SourceHealth {
+            last_success: Some(now - Duration::minutes(5)),
+            last_error: Some((now, "unreachable".to_string())),
+            next_refresh: None,
+            cached_at: Some(now - Duration::minutes(5)),
+        };
+
+        let status = to_json(&[("calendar_family".to_string(), health)], json::array![], now);
+        assert_eq!(status["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(status["ok"], false);
+        assert_eq!(status["sources"][0]["name"], "calendar_family");
+        assert_eq!(status["sources"][0]["last_error"]["message"], "unreachable");
+        assert_eq!(status["sources"][0]["cache_age_seconds"], 300);
+        assert!(status["sources"][0]["next_refresh"].is_null());
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+
+use crate::metrics;
+
+/// Timeouts and retries of all outbound requests, read from `data/http.conf`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpPolicy {
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    /// Attempts after the first one for transient failures.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for every further one.
+    pub backoff: Duration,
+    /// Longest wait between attempts. A longer `Retry-After` isn't waited for.
+    pub max_backoff: Duration,
+}
+
+static POLICY: OnceLock<HttpPolicy> = OnceLock::new();
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static BLOCKING_CLIENT: OnceLock<reqwest::blocking::Client> = OnceLock::new();
+
+impl Default for HttpPolicy {
+    fn default() -> Self {
+        HttpPolicy {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl HttpPolicy {
+    pub(crate) fn new(path_config: &str) -> Result<Self, String> {
+        let file = File::open(path_config).map_err(|e| {
+            format!("Failed to open '{}': {}", path_config, e)
+        })?;
+
+        let mut policy = HttpPolicy::default();
+        let reader = BufReader::new(file);
+        for line_result in reader.lines() {
+            let line = line_result.map_err(|e| {
+                format!("Error reading '{}': {}", path_config, e)
+            })?;
+
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let number = || value.trim().parse::<u64>().map_err(|e| {
+                    format!("Invalid value '{}' of '{}' in '{}': {}", value, key, path_config, e)
+                });
+                match key.trim() {
+                    "connect_timeout" => policy.connect_timeout = Duration::from_secs(number()?),
+                    "timeout" => policy.timeout = Duration::from_secs(number()?),
+                    "retries" => policy.retries = number()? as u32,
+                    "backoff" => policy.backoff = Duration::from_millis(number()?),
+                    "max_backoff" => policy.max_backoff = Duration::from_millis(number()?),
+                    _default => (),
+                }
+            }
+        }
+        Ok(policy)
+    }
+
+    /// How long to wait before attempt `attempt` (1 for the first retry), `None` if no further
+    /// attempt is made.
+    ///
+    /// Without `retry_after` the wait grows exponentially and a random part of its second half
+    /// is dropped, so panels failing at the same time don't retry at the same time. `random`
+    /// picks that part.
+    pub(crate) fn delay(&self, attempt: u32, retry_after: Option<Duration>, random: u64) -> Option<Duration> {
+        if attempt > self.retries {
+            return None;
+        }
+
+        if let Some(retry_after) = retry_after {
+            return Some(retry_after).filter(|retry_after| *retry_after <= self.max_backoff);
+        }
+
+        let backoff = self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_backoff);
+        let half = backoff / 2;
+        let jitter = Duration::from_millis(random % (half.as_millis() as u64 + 1));
+        Some(half + jitter)
+    }
+}
+
+/// The policy of `data/http.conf`, or the default one without that file.
+pub(crate) fn policy() -> &'static HttpPolicy {
+    POLICY.get_or_init(|| match HttpPolicy::new("data/http.conf") {
+        Ok(policy) => policy,
+        Err(e) => {
+            info!("Using the default HTTP policy: {}", e);
+            HttpPolicy::default()
+        }
+    })
+}
+
+/// The client all asynchronous requests are made with, so connections are reused.
+pub(crate) fn client() -> &'static Client {
+    CLIENT.get_or_init(|| {
+        let policy = policy();
+        Client::builder()
+            .connect_timeout(policy.connect_timeout)
+            .timeout(policy.timeout)
+            .build()
+            .unwrap_or_else(|e| {
+                warn!("Failed to create HTTP client, using one without timeouts: {}", describe(&e));
+                Client::new()
+            })
+    })
+}
+
+/// The client blocking requests are made with, e.g. those to the WebDAV server.
+pub(crate) fn blocking_client() -> &'static reqwest::blocking::Client {
+    BLOCKING_CLIENT.get_or_init(|| {
+        blocking_client_builder()
+            .build()
+            .unwrap_or_else(|e| {
+                warn!("Failed to create HTTP client, using one without timeouts: {}", describe(&e));
+                reqwest::blocking::Client::new()
+            })
+    })
+}
+
+/// A builder for blocking clients with the timeouts of the policy, for servers needing their
+/// own TLS settings.
+pub(crate) fn blocking_client_builder() -> reqwest::blocking::ClientBuilder {
+    let policy = policy();
+    reqwest::blocking::Client::builder()
+        .connect_timeout(policy.connect_timeout)
+        .timeout(policy.timeout)
+}
+
+/// Sends a request, retrying it after transient failures. `what` names the request in errors,
+/// e.g. "Failed to download 'https://…'".
+///
+/// Only GET, HEAD and PROPFIND requests are retried, others may have reached the server. Responses with
+/// other error statuses are returned as they are, the caller decides about them.
+pub(crate) async fn send(request: RequestBuilder, what: &str) -> Result<Response, String> {
+    let built = request.try_clone().and_then(|request| request.build().ok());
+    let provider = provider(built.as_ref().map(|request| request.url().clone()));
+    // requests with a streamed body can't be sent again
+    let repeatable = built.is_some_and(|request| is_repeatable(request.method()));
+
+    let mut attempt: u32 = 0;
+    loop {
+        attempt += 1;
+        let started = Instant::now();
+        let retry = if repeatable { request.try_clone() } else { None };
+        let result = match retry {
+            Some(retry) => retry.send().await,
+            None => {
+                let result = request.send().await;
+                record_call(&provider, started, result.as_ref().ok().map(|response| response.status()));
+                return result.map_err(|e| format!("{}: {}", what, describe(&e)));
+            },
+        };
+        record_call(&provider, started, result.as_ref().ok().map(|response| response.status()));
+
+        let outcome = result.as_ref().map(|response| (response.status(), response.headers()));
+        match retry_delay(attempt, outcome) {
+            Some(delay) => {
+                warn!("{}: {}, trying again in {} ms", what, describe_outcome(outcome), delay.as_millis());
+                tokio::time::sleep(delay).await;
+            },
+            None => return result.map_err(|e| format!("{}: {}", what, describe(&e))),
+        }
+    }
+}
+
+/// Like [send], for blocking requests.
+pub(crate) fn send_blocking(request: reqwest::blocking::RequestBuilder, what: &str) -> Result<reqwest::blocking::Response, String> {
+    let built = request.try_clone().and_then(|request| request.build().ok());
+    let provider = provider(built.as_ref().map(|request| request.url().clone()));
+    let repeatable = built.is_some_and(|request| is_repeatable(request.method()));
+
+    let mut attempt: u32 = 0;
+    loop {
+        attempt += 1;
+        let started = Instant::now();
+        let retry = if repeatable { request.try_clone() } else { None };
+        let result = match retry {
+            Some(retry) => retry.send(),
+            None => {
+                let result = request.send();
+                record_call(&provider, started, result.as_ref().ok().map(|response| response.status()));
+                return result.map_err(|e| format!("{}: {}", what, describe(&e)));
+            },
+        };
+        record_call(&provider, started, result.as_ref().ok().map(|response| response.status()));
+
+        let outcome = result.as_ref().map(|response| (response.status(), response.headers()));
+        match retry_delay(attempt, outcome) {
+            Some(delay) => {
+                warn!("{}: {}, trying again in {} ms", what, describe_outcome(outcome), delay.as_millis());
+                std::thread::sleep(delay);
+            },
+            None => return result.map_err(|e| format!("{}: {}", what, describe(&e))),
+        }
+    }
+}
+
+/// An error with all of its causes, e.g. "error sending request: connection refused".
+pub(crate) fn describe(error: &dyn Error) -> String {
+    let mut description = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let cause_description = cause.to_string();
+        // some errors repeat their cause in their own message
+        if !description.contains(&cause_description) {
+            description.push_str(&format!(": {}", cause_description));
+        }
+        source = cause.source();
+    }
+    description
+}
+
+// the host a request goes to, the provider in metrics
+fn provider(url: Option<reqwest::Url>) -> String {
+    url.and_then(|url| url.host_str().map(str::to_string)).unwrap_or_else(|| "unknown".to_string())
+}
+
+// Counts a request to a provider and its duration. Failures without a response and error
+// statuses count as errors, but not 401: the WebDAV connection answers it with credentials.
+fn record_call(provider: &str, started: Instant, status: Option<StatusCode>) {
+    let status_label = status.map_or("error".to_string(), |status| status.as_u16().to_string());
+    metrics::count(metrics::UPSTREAM_REQUESTS, &[("provider", provider), ("status", &status_label)]);
+    metrics::observe(metrics::UPSTREAM_REQUEST_DURATION, &[("provider", provider)], started.elapsed());
+
+    if status.is_none_or(|status| status.as_u16() >= 400 && status != StatusCode::UNAUTHORIZED) {
+        metrics::count(metrics::UPSTREAM_ERRORS, &[("provider", provider)]);
+    }
+}
+
+fn is_transient(status: StatusCode) -> bool {
+    matches!(status,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS | StatusCode::INTERNAL_SERVER_ERROR |
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT)
+}
+
+fn is_transient_error(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect()
+}
+
+/// Whether a request of that method may be sent again after a failure. A PUT or POST may have
+/// reached the server although its response didn't arrive, e.g. a conditional PUT or a token
+/// refresh, so they are sent once.
+fn is_repeatable(method: &Method) -> bool {
+    *method == Method::GET || *method == Method::HEAD || method.as_str() == "PROPFIND"
+}
+
+// The status and headers of a response, or why there is none.
+type Outcome<'a> = Result<(StatusCode, &'a HeaderMap), &'a reqwest::Error>;
+
+// The wait before the next attempt of a request, None if its outcome is final
+fn retry_delay(attempt: u32, outcome: Outcome) -> Option<Duration> {
+    let retry_after = match outcome {
+        Ok((status, _)) if !is_transient(status) => return None,
+        Ok((status, headers)) => retry_after(status, headers),
+        Err(e) if !is_transient_error(e) => return None,
+        Err(_) => None,
+    };
+    policy().delay(attempt, retry_after, random())
+}
+
+// Retry-After of 429 and 503 responses, either in seconds or as a date
+fn retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    parse_retry_after(value, Utc::now())
+}
+
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?.with_timezone(&Utc);
+    Some((date - now).to_std().unwrap_or(Duration::ZERO))
+}
+
+fn describe_outcome(outcome: Outcome) -> String {
+    match outcome {
+        Ok((status, _)) => status.to_string(),
+        Err(e) => describe(e),
+    }
+}
+
+// std seeds every RandomState randomly, which is random enough for jitter
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use reqwest::Method;
+
+    use crate::http_client::{HttpPolicy, is_repeatable, parse_retry_after};
+
+    #[test]
+    fn policy_config() {
+        let policy = HttpPolicy::new("data/test/http_test.conf").unwrap();
+
+        assert_eq!(policy, HttpPolicy {
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(20),
+            retries: 2,
+            backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_millis(1000),
+        });
+        assert!(HttpPolicy::new("data/test/http_missing.conf").is_err());
+    }
+
+    #[test]
+    fn backoff_with_jitter() {
+        let policy = HttpPolicy { retries: 4, backoff: Duration::from_millis(200), max_backoff: Duration::from_millis(1000), ..HttpPolicy::default() };
+
+        assert_eq!(policy.delay(1, None, 0), Some(Duration::from_millis(100)));
+        assert_eq!(policy.delay(1, None, 100), Some(Duration::from_millis(200)));
+        assert_eq!(policy.delay(2, None, 0), Some(Duration::from_millis(200)));
+        // capped at max_backoff
+        assert_eq!(policy.delay(4, None, 500), Some(Duration::from_millis(1000)));
+        assert_eq!(policy.delay(5, None, 0), None);
+
+        for random in 0..1000 {
+            let delay = policy.delay(3, None, random).unwrap();
+            assert!(delay >= Duration::from_millis(400) && delay <= Duration::from_millis(800));
+        }
+
+        assert_eq!(policy.delay(1, Some(Duration::from_millis(700)), 0), Some(Duration::from_millis(700)));
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(120)), 0), None);
+    }
+
+    #[test]
+    fn repeatable_methods() {
+        assert!(is_repeatable(&Method::GET));
+        assert!(is_repeatable(&Method::from_bytes(b"PROPFIND").unwrap()));
+        assert!(!is_repeatable(&Method::PUT));
+        assert!(!is_repeatable(&Method::POST));
+    }
+
+    #[test]
+    fn retry_after_parsing() {
+        let now = Utc.with_ymd_and_hms(2023, 10, 12, 12, 0, 0).unwrap();
+
+        assert_eq!(parse_retry_after("120", now), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Thu, 12 Oct 2023 12:01:30 GMT", now), Some(Duration::from_secs(90)));
+        assert_eq!(parse_retry_after("Thu, 12 Oct 2023 11:00:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
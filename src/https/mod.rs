@@ -0,0 +1,314 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, SystemTime};
+
+use axum_server::tls_rustls::RustlsConfig;
+use log::{info, warn};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::x509::{X509, X509NameBuilder};
+use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName};
+
+// self-signed certificates are made anew after that many days
+const SELF_SIGNED_DAYS: u32 = 3650;
+
+/// How the panel is served, read from `data/server.conf`.
+///
+/// - `listen=<address>:<port>`, `127.0.0.1:3000` if missing
+/// - `tls_certificate=<path>` and `tls_key=<path>` of PEM files serve HTTPS, e.g. of Let's Encrypt
+/// - `self_signed=true` serves HTTPS with a certificate made on the first start if no files are set
+/// - `assets=<path>` of a directory whose templates, styles and scripts replace the built-in ones,
+///   `~/.InfoPanel/assets` if missing
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    pub tls_certificate: Option<String>,
+    pub tls_key: Option<String>,
+    pub self_signed: bool,
+    pub assets: Option<String>,
+}
+
+/// PEM files of the certificate, with its chain, and the key HTTPS is served with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TlsFiles {
+    pub certificate: String,
+    pub key: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig { listen: SocketAddr::from(([127, 0, 0, 1], 3000)), tls_certificate: None, tls_key: None, self_signed: false, assets: None }
+    }
+}
+
+impl ServerConfig {
+    pub(crate) fn new(path_config: &str) -> Result<Self, String> {
+        let file = File::open(path_config).map_err(|e| {
+            format!("Failed to open '{}': {}", path_config, e)
+        })?;
+
+        let mut config = ServerConfig::default();
+        let reader = BufReader::new(file);
+        for line_result in reader.lines() {
+            let line = line_result.map_err(|e| {
+                format!("Error reading '{}': {}", path_config, e)
+            })?;
+
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim();
+                match key.trim() {
+                    "listen" => config.listen = value.parse().map_err(|e| {
+                        format!("Invalid address '{}' in '{}': {}", value, path_config, e)
+                    })?,
+                    "tls_certificate" if !value.is_empty() => config.tls_certificate = Some(value.to_string()),
+                    "tls_key" if !value.is_empty() => config.tls_key = Some(value.to_string()),
+                    "self_signed" => config.self_signed = value == "true",
+                    "assets" if !value.is_empty() => config.assets = Some(value.to_string()),
+                    _default => (),
+                }
+            }
+        }
+
+        if config.tls_certificate.is_some() != config.tls_key.is_some() {
+            return Err(format!("Expected both 'tls_certificate' and 'tls_key' in '{}'", path_config));
+        }
+        Ok(config)
+    }
+
+    /// The files to serve HTTPS with, a self-signed certificate in `directory` made if needed, or
+    /// None for plain HTTP.
+    pub(crate) fn tls_files(&self, directory: impl FnOnce() -> Result<String, String>) -> Result<Option<TlsFiles>, String> {
+        if let (Some(certificate), Some(key)) = (&self.tls_certificate, &self.tls_key) {
+            return Ok(Some(TlsFiles { certificate: certificate.clone(), key: key.clone() }));
+        }
+        if !self.self_signed {
+            return Ok(None);
+        }
+
+        let directory = directory()?;
+        let files = TlsFiles { certificate: format!("{}/certificate.pem", directory), key: format!("{}/key.pem", directory) };
+        if !is_valid_certificate(&files.certificate) || fs::metadata(&files.key).is_err() {
+            generate_self_signed(&files, &self.names())?;
+        }
+        Ok(Some(files))
+    }
+
+    /// Address the panel is reached by on this machine, e.g. `https://127.0.0.1:8443`.
+    pub(crate) fn local_url(&self) -> String {
+        let scheme = if self.tls_certificate.is_some() || self.self_signed { "https" } else { "http" };
+        let ip = match self.listen.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ip => ip,
+        };
+        format!("{}://{}", scheme, SocketAddr::new(ip, self.listen.port()))
+    }
+
+    // names clients may reach the panel by
+    fn names(&self) -> Vec<SubjectName> {
+        let mut names = vec![
+            SubjectName::Dns("localhost".to_string()),
+            SubjectName::Ip(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            SubjectName::Ip(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        ];
+        if let Some(hostname) = hostname() {
+            names.push(SubjectName::Dns(format!("{}.local", hostname)));
+            names.insert(0, SubjectName::Dns(hostname));
+        }
+        if !self.listen.ip().is_unspecified() && !self.listen.ip().is_loopback() {
+            names.push(SubjectName::Ip(self.listen.ip()));
+        }
+        names
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SubjectName {
+    Dns(String),
+    Ip(IpAddr),
+}
+
+/// Reloads the certificate whenever one of its files changes, e.g. after a renewal.
+pub(crate) fn watch(config: RustlsConfig, files: TlsFiles, interval: Duration) {
+    tokio::spawn(async move {
+        let mut last_modified = modified(&files);
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let now_modified = modified(&files);
+            if now_modified == last_modified {
+                continue;
+            }
+            match config.reload_from_pem_file(&files.certificate, &files.key).await {
+                Ok(()) => {
+                    info!("Reloaded the certificate '{}'", files.certificate);
+                    last_modified = now_modified;
+                },
+                // a renewal may have written only one of the files yet, tried again next time
+                Err(e) => warn!("Couldn't reload the certificate '{}': {}", files.certificate, e),
+            }
+        }
+    });
+}
+
+/// The SHA-256 fingerprint of a PEM certificate, as pinned by clients.
+pub(crate) fn fingerprint(path_certificate: &str) -> Result<String, String> {
+    let certificate = read_certificate(path_certificate)?;
+    let digest = certificate.digest(MessageDigest::sha256())
+        .map_err(|e| format!("Failed to hash certificate '{}': {}", path_certificate, e))?;
+
+    Ok(digest.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(":"))
+}
+
+fn modified(files: &TlsFiles) -> (Option<SystemTime>, Option<SystemTime>) {
+    let modified = |path: &str| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    (modified(&files.certificate), modified(&files.key))
+}
+
+fn read_certificate(path_certificate: &str) -> Result<X509, String> {
+    let pem = fs::read(path_certificate).map_err(|e| format!("Failed to read certificate '{}': {}", path_certificate, e))?;
+    X509::from_pem(&pem).map_err(|e| format!("Invalid certificate '{}': {}", path_certificate, e))
+}
+
+// whether the certificate exists and hasn't expired
+fn is_valid_certificate(path_certificate: &str) -> bool {
+    let now = match Asn1Time::days_from_now(0) {
+        Ok(now) => now,
+        Err(_) => return false,
+    };
+    read_certificate(path_certificate).is_ok_and(|certificate| certificate.not_after() > now)
+}
+
+fn hostname() -> Option<String> {
+    fs::read_to_string("/proc/sys/kernel/hostname").ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty() && hostname != "localhost")
+}
+
+fn generate_self_signed(files: &TlsFiles, names: &[SubjectName]) -> Result<(), String> {
+    let error = |e: openssl::error::ErrorStack| format!("Failed to make a self-signed certificate: {}", e);
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).map_err(error)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group).map_err(error)?).map_err(error)?;
+
+    let common_name = match names.first() {
+        Some(SubjectName::Dns(name)) => name.clone(),
+        _default => "localhost".to_string(),
+    };
+    let mut subject = X509NameBuilder::new().map_err(error)?;
+    subject.append_entry_by_text("CN", &common_name).map_err(error)?;
+    subject.append_entry_by_text("O", "InfoPanel").map_err(error)?;
+    let subject = subject.build();
+
+    let mut serial = BigNum::new().map_err(error)?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false).map_err(error)?;
+
+    let mut builder = X509::builder().map_err(error)?;
+    builder.set_version(2).map_err(error)?;
+    let serial = serial.to_asn1_integer().map_err(error)?;
+    let not_before = Asn1Time::days_from_now(0).map_err(error)?;
+    let not_after = Asn1Time::days_from_now(SELF_SIGNED_DAYS).map_err(error)?;
+    builder.set_serial_number(&serial).map_err(error)?;
+    builder.set_subject_name(&subject).map_err(error)?;
+    builder.set_issuer_name(&subject).map_err(error)?;
+    builder.set_pubkey(&key).map_err(error)?;
+    builder.set_not_before(&not_before).map_err(error)?;
+    builder.set_not_after(&not_after).map_err(error)?;
+
+    let mut alternative_names = SubjectAlternativeName::new();
+    for name in names {
+        match name {
+            SubjectName::Dns(name) => alternative_names.dns(name),
+            SubjectName::Ip(ip) => alternative_names.ip(&ip.to_string()),
+        };
+    }
+    let alternative_names = alternative_names.build(&builder.x509v3_context(None, None)).map_err(error)?;
+    builder.append_extension(alternative_names).map_err(error)?;
+    builder.append_extension(BasicConstraints::new().critical().build().map_err(error)?).map_err(error)?;
+    builder.append_extension(KeyUsage::new().critical().digital_signature().build().map_err(error)?).map_err(error)?;
+    builder.append_extension(ExtendedKeyUsage::new().server_auth().build().map_err(error)?).map_err(error)?;
+    builder.sign(&key, MessageDigest::sha256()).map_err(error)?;
+    let certificate = builder.build();
+
+    write_private(&files.key, &key.private_key_to_pem_pkcs8().map_err(error)?)?;
+    fs::write(&files.certificate, certificate.to_pem().map_err(error)?)
+        .map_err(|e| format!("Failed to write certificate '{}': {}", files.certificate, e))?;
+
+    info!("Made a self-signed certificate for {}, '{}'", common_name, files.certificate);
+    Ok(())
+}
+
+// writes a file only its owner may read
+fn write_private(path: &str, content: &[u8]) -> Result<(), String> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path).map_err(|e| format!("Failed to write '{}': {}", path, e))?;
+    file.write_all(content).map_err(|e| format!("Failed to write '{}': {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::SocketAddr;
+
+    use openssl::pkey::PKey;
+
+    use crate::https::{fingerprint, read_certificate, ServerConfig};
+
+    #[test]
+    fn new() {
+        let config = ServerConfig::new("data/test/server_test.conf").unwrap();
+
+        assert_eq!(config.listen, "0.0.0.0:8443".parse::<SocketAddr>().unwrap());
+        assert_eq!(config.tls_certificate, None);
+        assert_eq!(config.assets, Some("/srv/panel/assets".to_string()));
+        assert!(config.self_signed);
+        assert_eq!(config.local_url(), "https://127.0.0.1:8443");
+        assert_eq!(ServerConfig::default().local_url(), "http://127.0.0.1:3000");
+
+        assert!(ServerConfig::new("data/test/missing.conf").is_err());
+    }
+
+    #[test]
+    fn self_signed() {
+        let directory = std::env::temp_dir().join(format!("info_panel_tls_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let directory = directory.display().to_string();
+
+        let config = ServerConfig { self_signed: true, ..ServerConfig::default() };
+        let files = config.tls_files(|| Ok(directory.clone())).unwrap().unwrap();
+
+        let certificate = read_certificate(&files.certificate).unwrap();
+        let key = PKey::private_key_from_pem(&fs::read(&files.key).unwrap()).unwrap();
+        assert!(certificate.verify(&key).unwrap());
+        let names = certificate.subject_alt_names().unwrap();
+        assert!(names.iter().any(|name| name.dnsname() == Some("localhost")));
+
+        // kept on the next start
+        let before = fingerprint(&files.certificate).unwrap();
+        config.tls_files(|| Ok(directory.clone())).unwrap();
+        assert_eq!(fingerprint(&files.certificate).unwrap(), before);
+        assert_eq!(before.len(), 95);
+
+        fs::remove_dir_all(&directory).unwrap();
+
+        // plain HTTP without any TLS setting
+        assert_eq!(ServerConfig::default().tls_files(|| Err("unused".to_string())), Ok(None));
+    }
+}
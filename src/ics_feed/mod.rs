@@ -0,0 +1,215 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use reqwest::StatusCode;
+use reqwest::header::{ETAG, HeaderName, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+
+use crate::filesystem::FileSystemHandler;
+use crate::health;
+use crate::http_client;
+use crate::metrics;
+use crate::offline_cache::{file_name, OfflineCache};
+use crate::webdav::calendar::Calendar;
+use crate::webdav::calendar::vtimezone::read_display_zone;
+
+/// A read-only iCalendar file published under a URL, e.g. school holidays or waste collection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IcsFeed {
+    pub name: String,
+    pub url: String,
+}
+
+// Validators of the last download, sent along so unchanged feeds aren't downloaded again.
+#[derive(Debug, Default, PartialEq)]
+struct Validators {
+    e_tag: String,
+    last_modified: String,
+}
+
+impl IcsFeed {
+    pub(crate) fn new(name: &str, url: &str) -> Self {
+        IcsFeed { name: name.to_string(), url: normalise_url(url) }
+    }
+
+    /// Downloads the feed and parses every component of it.
+    ///
+    /// The last download is kept in `~/.InfoPanel/feeds` and used if the server reports the feed
+    /// as unchanged or can't be reached.
+    pub(crate) async fn read_calendar(&self, filesystem_handler: &FileSystemHandler) -> Result<Calendar, String> {
+        let directory = filesystem_handler.create_directory("feeds")?;
+        let path_ics = format!("{}/{}.ics", directory, file_name(&self.name));
+        let path_validators = format!("{}/{}.validators", directory, file_name(&self.name));
+
+        let cached = fs::read_to_string(&path_ics).ok();
+        let validators = match cached {
+            Some(_) => read_validators(&path_validators),
+            None => Validators::default(),
+        };
+
+        let cache = OfflineCache::shared();
+        let cache_key = format!("feed_{}", self.name);
+
+        let downloaded = self.download(&validators).await;
+        health::record(&cache_key, &downloaded);
+        if let Ok(download) = &downloaded {
+            // the server answered that the kept copy is current
+            metrics::count_cache_lookup("feed", download.is_none());
+        }
+        if let Some(cache) = cache {
+            match downloaded {
+                Ok(_) => cache.mark_online(&cache_key),
+                Err(_) => cache.mark_offline(&cache_key),
+            }
+        }
+
+        let ics = match downloaded {
+            Ok(Some((ics, validators))) => {
+                if let Err(e) = fs::write(&path_ics, &ics).and_then(|_| write_validators(&path_validators, &validators)) {
+                    warn!("Couldn't keep feed '{}' in '{}': {}", self.name, directory, e);
+                }
+                ics
+            },
+            Ok(None) => {
+                info!("Feed '{}' is unchanged", self.name);
+                cached.ok_or_else(|| format!("Feed '{}' is unchanged, but no copy of it is kept", self.name))?
+            },
+            Err(e) => match cached {
+                Some(ics) => {
+                    warn!("{}, using the last download", e);
+                    let downloaded_at = fs::metadata(&path_ics).and_then(|metadata| metadata.modified()).ok();
+                    health::record_cached(&cache_key, downloaded_at.map(DateTime::<Utc>::from));
+                    ics
+                },
+                None => return Err(e),
+            },
+        };
+
+        Ok(Calendar::new(self.name.clone(), &[ics]))
+    }
+
+    // `None` if the feed didn't change since it was downloaded with `validators`
+    async fn download(&self, validators: &Validators) -> Result<Option<(String, Validators)>, String> {
+        let mut request = http_client::client().get(&self.url);
+        if !validators.e_tag.is_empty() {
+            request = request.header(IF_NONE_MATCH, &validators.e_tag);
+        }
+        if !validators.last_modified.is_empty() {
+            request = request.header(IF_MODIFIED_SINCE, &validators.last_modified);
+        }
+
+        let response = http_client::send(request, &format!("Failed to download feed '{}'", self.name)).await?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(format!("Failed to download feed '{}': {}", self.name, response.status()));
+        }
+
+        let header = |name: HeaderName| response.headers().get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let validators = Validators { e_tag: header(ETAG), last_modified: header(LAST_MODIFIED) };
+
+        let ics = response.text().await
+            .map_err(|e| format!("Failed to read feed '{}': {}", self.name, http_client::describe(&e)))?;
+        Ok(Some((ics, validators)))
+    }
+}
+
+/// Reads the feeds of a config, one `feed=<name>,<url>` line each.
+pub(crate) fn read_feeds(path_config: &str) -> Result<Vec<IcsFeed>, String> {
+    let file = File::open(path_config).map_err(|e| {
+        format!("Failed to open '{}': {}", path_config, e)
+    })?;
+
+    let mut feeds: Vec<IcsFeed> = Vec::new();
+    let reader = BufReader::new(file);
+    for line_result in reader.lines() {
+        let line = line_result.map_err(|e| {
+            format!("Error reading '{}': {}", path_config, e)
+        })?;
+
+        // URLs may contain '=' themselves
+        if let Some(("feed", value)) = line.split_once('=') {
+            match value.split_once(',') {
+                Some((name, url)) if !name.trim().is_empty() && !url.trim().is_empty() => {
+                    feeds.push(IcsFeed::new(name.trim(), url.trim()))
+                },
+                _ => return Err(format!("Expected 'feed=<name>,<url>' in '{}', got '{}'", path_config, line)),
+            }
+        }
+    }
+    Ok(feeds)
+}
+
+/// Reads every feed of a config. Feeds that can't be read are logged and skipped.
+pub(crate) async fn read_calendars(path_config: &str, filesystem_handler: &FileSystemHandler) -> Result<Vec<Calendar>, String> {
+    let display_zone = read_display_zone(path_config);
+
+    let mut calendars: Vec<Calendar> = Vec::new();
+    for feed in read_feeds(path_config)? {
+        match feed.read_calendar(filesystem_handler).await {
+            Ok(mut calendar) => {
+                calendar.display_zone = display_zone;
+                calendars.push(calendar);
+            },
+            Err(e) => warn!("Skipping feed '{}': {}", feed.name, e),
+        }
+    }
+    Ok(calendars)
+}
+
+// webcal:// is https:// for calendar applications
+fn normalise_url(url: &str) -> String {
+    match url.get(..9) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("webcal://") => format!("https://{}", &url[9..]),
+        _ => url.to_string(),
+    }
+}
+
+fn read_validators(path: &str) -> Validators {
+    let mut validators = Validators::default();
+
+    if let Ok(file) = File::open(path) {
+        let reader = BufReader::new(file);
+        for line in reader.lines().map_while(Result::ok) {
+            match line.split_once('=') {
+                Some(("e_tag", value)) => validators.e_tag = value.to_string(),
+                Some(("last_modified", value)) => validators.last_modified = value.to_string(),
+                _default => (),
+            }
+        }
+    }
+    validators
+}
+
+fn write_validators(path: &str, validators: &Validators) -> std::io::Result<()> {
+    fs::write(path, format!("e_tag={}\nlast_modified={}\n", validators.e_tag, validators.last_modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ics_feed::{IcsFeed, read_feeds};
+    use crate::offline_cache::file_name;
+
+    #[test]
+    fn feed_config() {
+        let feeds = read_feeds("data/test/feeds_test.conf").unwrap();
+
+        assert_eq!(feeds, vec![
+            IcsFeed { name: "Müll".to_string(), url: "https://example.org/waste.ics?street=12&year=2023".to_string() },
+            IcsFeed { name: "Ferien".to_string(), url: "https://example.org/holidays.ics".to_string() },
+        ]);
+        assert_eq!(file_name(&feeds[0].name), "Müll");
+        assert_ne!(file_name("Müll Bio"), file_name("Müll/Bio"));
+        assert!(file_name("Müll/Bio").starts_with("Müll_Bio_"));
+        assert!(!file_name("../Müll").contains('/'));
+
+        assert!(read_feeds("data/test/feeds_missing.conf").is_err());
+    }
+}
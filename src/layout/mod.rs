@@ -0,0 +1,256 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use chrono::NaiveDateTime;
+
+use crate::agenda::AgendaDay;
+use crate::locale::Locale;
+use crate::openweather_api::weather_entry::WeatherEntry;
+use crate::webdav::calendar::Calendar;
+use crate::webdav::contacts::Anniversary;
+
+pub(crate) mod widgets;
+
+/// Something shown on the panel, placed in the grid of the layout.
+pub(crate) trait Widget: Send + Sync {
+    /// Name of the widget in `data/layout.conf`, also its CSS class.
+    fn kind(&self) -> &'static str;
+
+    /// Data the widget shows, only data some widget shows is read.
+    fn sources(&self) -> &'static [Source];
+
+    /// The content of the widget.
+    fn render(&self, data: &PanelData) -> String;
+}
+
+/// Data read for the widgets of the panel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Weather,
+    Agenda,
+    Tasks,
+    Birthdays,
+}
+
+/// Everything the widgets show, taken from the last data of the sources for every rendering of
+/// the panel. Data no widget shows or that couldn't be read is missing.
+pub struct PanelData {
+    /// Now in the display zone.
+    pub now: NaiveDateTime,
+    pub locale: Locale,
+    pub weather: Option<WeatherData>,
+    pub agenda: Vec<AgendaDay>,
+    pub tasks: Option<Calendar>,
+    pub anniversaries: Vec<Anniversary>,
+    /// Directory below `icons/` the weather icons are taken from.
+    pub icon_set: String,
+}
+
+#[derive(Clone)]
+pub struct WeatherData {
+    pub current: WeatherEntry,
+    /// 3-hourly forecast, starting with the current period.
+    pub forecast: Vec<WeatherEntry>,
+}
+
+/// Where a widget is in the grid, counted from 1 like CSS grid lines.
+pub struct Placement {
+    /// Id of the widget's element, its kind with a number if it is used more than once.
+    pub id: String,
+    pub column: u32,
+    pub row: u32,
+    pub width: u32,
+    pub height: u32,
+    pub widget: Box<dyn Widget>,
+}
+
+/// The grid of the panel and the widgets placed in it, read from `data/layout.conf`:
+///
+/// - `grid=<columns>x<rows>`
+/// - `widget=<kind>,<column>,<row>,<width>,<height>[,<parameter>]`, one per widget
+///
+/// Kinds are listed in [widgets::new].
+pub struct Layout {
+    pub columns: u32,
+    pub rows: u32,
+    pub placements: Vec<Placement>,
+}
+
+impl Default for Layout {
+    /// Weather and forecast above the todos and birthdays, the agenda beside them.
+    fn default() -> Self {
+        let mut layout = Layout { columns: 2, rows: 4, placements: vec![] };
+        let defaults = [
+            ("weather_now", 1, 1, 1, 1),
+            ("forecast", 1, 2, 1, 1),
+            ("agenda", 2, 1, 1, 4),
+            ("todos", 1, 3, 1, 1),
+            ("birthdays", 1, 4, 1, 1),
+        ];
+        for (kind, column, row, width, height) in defaults {
+            if let Ok(widget) = widgets::new(kind, "") {
+                layout.place(widget, column, row, width, height);
+            }
+        }
+        layout
+    }
+}
+
+impl Layout {
+    pub(crate) fn new(path_config: &str) -> Result<Self, String> {
+        let file = File::open(path_config).map_err(|e| {
+            format!("Failed to open '{}': {}", path_config, e)
+        })?;
+
+        let mut layout = Layout { columns: 1, rows: 1, placements: vec![] };
+        let mut widget_lines = vec![];
+        let reader = BufReader::new(file);
+        for line_result in reader.lines() {
+            let line = line_result.map_err(|e| {
+                format!("Error reading '{}': {}", path_config, e)
+            })?;
+
+            if line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some(("grid", value)) => {
+                    let (columns, rows) = value.trim().split_once('x')
+                        .and_then(|(columns, rows)| columns.trim().parse::<u32>().ok().zip(rows.trim().parse::<u32>().ok()))
+                        .filter(|(columns, rows)| *columns > 0 && *rows > 0)
+                        .ok_or_else(|| format!("Expected 'grid=<columns>x<rows>' in '{}', got '{}'", path_config, line))?;
+                    layout.columns = columns;
+                    layout.rows = rows;
+                },
+                Some(("widget", value)) => widget_lines.push(value.to_string()),
+                _default => (),
+            }
+        }
+
+        // the grid may follow the widgets
+        for value in widget_lines {
+            let invalid = |reason: &str| format!("Invalid widget '{}' in '{}': {}", value, path_config, reason);
+
+            let parts: Vec<&str> = value.splitn(6, ',').collect();
+            if parts.len() < 5 {
+                return Err(invalid("expected <kind>,<column>,<row>,<width>,<height>[,<parameter>]"));
+            }
+            let numbers: Vec<u32> = parts[1..5].iter()
+                .map(|number| number.trim().parse::<u32>())
+                .collect::<Result<_, _>>()
+                .map_err(|e| invalid(&e.to_string()))?;
+            let (column, row, width, height) = (numbers[0], numbers[1], numbers[2], numbers[3]);
+
+            if column == 0 || row == 0 || width == 0 || height == 0 {
+                return Err(invalid("positions count from 1 and sizes from 1"));
+            }
+            // huge positions must not wrap around into the grid
+            let last_column = column.checked_add(width - 1);
+            let last_row = row.checked_add(height - 1);
+            if last_column.is_none_or(|last| last > layout.columns) || last_row.is_none_or(|last| last > layout.rows) {
+                return Err(invalid(&format!("outside of the {}x{} grid", layout.columns, layout.rows)));
+            }
+
+            let widget = widgets::new(parts[0].trim(), parts.get(5).map_or("", |parameter| parameter.trim()))
+                .map_err(|e| invalid(&e))?;
+            layout.place(widget, column, row, width, height);
+        }
+        Ok(layout)
+    }
+
+    fn place(&mut self, widget: Box<dyn Widget>, column: u32, row: u32, width: u32, height: u32) {
+        let same_kind = self.placements.iter().filter(|placement| placement.widget.kind() == widget.kind()).count();
+        let id = match same_kind {
+            0 => widget.kind().to_string(),
+            _default => format!("{}{}", widget.kind(), same_kind + 1),
+        };
+        self.placements.push(Placement { id, column, row, width, height, widget });
+    }
+
+    /// Whether a widget shows data of `source`.
+    pub(crate) fn needs(&self, source: Source) -> bool {
+        self.placements.iter().any(|placement| placement.widget.sources().contains(&source))
+    }
+
+    /// Ids of the widgets' elements.
+    pub(crate) fn section_ids(&self) -> Vec<String> {
+        self.placements.iter().map(|placement| placement.id.clone()).collect()
+    }
+
+    /// The grid with every widget in its place.
+    pub(crate) fn render(&self, data: &PanelData) -> String {
+        let mut html = format!("<div id=\"panel\" style=\"grid-template-columns: repeat({}, 1fr); grid-template-rows: repeat({}, 1fr)\">\n",
+                               self.columns, self.rows);
+        for placement in &self.placements {
+            html.push_str(&format!("<div id=\"{}\" class=\"widget {}\" style=\"grid-column: {} / span {}; grid-row: {} / span {}\">\n{}</div>\n",
+                                   placement.id,
+                                   placement.widget.kind(),
+                                   placement.column,
+                                   placement.width,
+                                   placement.row,
+                                   placement.height,
+                                   placement.widget.render(data)));
+        }
+        html.push_str("</div>");
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::layout::{Layout, PanelData, Source};
+    use crate::locale::Locale;
+
+    #[test]
+    fn new() {
+        let layout = Layout::new("data/test/layout_test.conf").unwrap();
+
+        assert_eq!((layout.columns, layout.rows), (3, 4));
+        assert_eq!(layout.section_ids(), vec!["clock", "weather_now", "agenda", "text", "text2", "waste"]);
+        let agenda = &layout.placements[2];
+        assert_eq!((agenda.column, agenda.row, agenda.width, agenda.height), (2, 1, 2, 3));
+
+        assert!(layout.needs(Source::Weather));
+        assert!(layout.needs(Source::Agenda));
+        assert!(!layout.needs(Source::Tasks));
+        assert!(!layout.needs(Source::Birthdays));
+
+        assert!(Layout::new("data/test/missing.conf").is_err());
+    }
+
+    #[test]
+    fn grid() {
+        let layout = Layout::new("data/test/layout_test.conf").unwrap();
+        let data = PanelData {
+            now: NaiveDate::from_ymd_opt(2023, 10, 12).unwrap().and_hms_opt(14, 5, 0).unwrap(),
+            locale: Locale::German,
+            weather: None,
+            agenda: vec![],
+            tasks: None,
+            anniversaries: vec![],
+            icon_set: "colour".to_string(),
+        };
+
+        let html = layout.render(&data);
+        assert!(html.starts_with("<div id=\"panel\" style=\"grid-template-columns: repeat(3, 1fr); grid-template-rows: repeat(4, 1fr)\">\n"));
+        assert!(html.contains("<div id=\"clock\" class=\"widget clock\" style=\"grid-column: 1 / span 1; grid-row: 1 / span 1\">\n"));
+        assert!(html.contains("<div id=\"text2\" class=\"widget text\" style=\"grid-column: 3 / span 1; grid-row: 4 / span 1\">\n<p>Müll raus, bitte!</p></div>"));
+    }
+
+    #[test]
+    fn default_layout() {
+        let layout = Layout::default();
+
+        assert_eq!(layout.section_ids(), vec!["weather_now", "forecast", "agenda", "todos", "birthdays"]);
+        assert!(layout.needs(Source::Tasks));
+    }
+
+    #[test]
+    fn widget_outside_grid() {
+        let error = Layout::new("data/test/layout_overflow_test.conf").err().unwrap();
+
+        assert!(error.contains("outside of the 3x4 grid"), "{}", error);
+    }
+}
@@ -0,0 +1,328 @@
+use chrono::NaiveDate;
+
+use crate::layout::{PanelData, Source, Widget};
+use crate::openweather_api::parsing::utc_to_local_date_time;
+use crate::openweather_api::weather_entry::WeatherEntry;
+use crate::website;
+use crate::website::escape_html;
+
+// 3-hourly periods the forecast shows by default
+const FORECAST_PERIODS: usize = 3;
+
+/// The widget of a kind of the layout config. Parameters are optional unless noted.
+///
+/// - `weather_now`: current weather, sunrise and sunset
+/// - `forecast`: the coming 3-hourly periods, as many as the parameter says
+/// - `agenda`: today's date and the coming days of the agenda
+/// - `todos`: open todos of the task calendar
+/// - `birthdays`: birthdays and anniversaries of the coming week
+/// - `waste`: next collection of every bin named in the parameter, e.g. `Restmüll;Papier`, found
+///   in the agenda
+/// - `clock`: day of the week, date and time
+/// - `text`: the parameter as text
+pub(crate) fn new(kind: &str, parameter: &str) -> Result<Box<dyn Widget>, String> {
+    match kind {
+        "weather_now" => Ok(Box::new(WeatherNow)),
+        "forecast" => {
+            let periods = match parameter {
+                "" => FORECAST_PERIODS,
+                periods => periods.parse().map_err(|e| format!("Invalid number of periods '{}': {}", periods, e))?,
+            };
+            Ok(Box::new(Forecast { periods }))
+        },
+        "agenda" => Ok(Box::new(Agenda)),
+        "todos" => Ok(Box::new(Todos)),
+        "birthdays" => Ok(Box::new(Birthdays)),
+        "waste" => {
+            let bins: Vec<String> = parameter.split(';')
+                .map(|bin| bin.trim().to_string())
+                .filter(|bin| !bin.is_empty())
+                .collect();
+            if bins.is_empty() {
+                return Err("Expected the bins, e.g. 'Restmüll;Papier'".to_string());
+            }
+            Ok(Box::new(Waste { bins }))
+        },
+        "clock" => Ok(Box::new(Clock)),
+        "text" => Ok(Box::new(Text { text: parameter.to_string() })),
+        _default => Err(format!("Unknown widget '{}'", kind)),
+    }
+}
+
+struct WeatherNow;
+
+struct Forecast {
+    periods: usize,
+}
+
+struct Agenda;
+
+struct Todos;
+
+struct Birthdays;
+
+struct Waste {
+    bins: Vec<String>,
+}
+
+struct Clock;
+
+struct Text {
+    text: String,
+}
+
+impl Widget for WeatherNow {
+    fn kind(&self) -> &'static str {
+        "weather_now"
+    }
+
+    fn sources(&self) -> &'static [Source] {
+        &[Source::Weather]
+    }
+
+    fn render(&self, data: &PanelData) -> String {
+        let weather = match &data.weather {
+            Some(weather) => weather,
+            None => return "<table></table>\n".to_string(),
+        };
+        let current = &weather.current;
+        let time = |time| utc_to_local_date_time(time).time().to_string();
+
+        format!("<table>\n\
+                 <tr><td rowspan=\"2\">{}</td><td rowspan=\"2\">{}</td><td rowspan=\"2\">{}</td><td>{}</td></tr>\n\
+                 <tr><td>{}</td></tr>\n\
+                 <tr><td colspan=\"4\">{} {} {} {}</td></tr>\n\
+                 </table>\n",
+                icon(current, &data.icon_set),
+                current.main.temp,
+                escape_html(&current.weather.description),
+                time(current.sys.sunrise),
+                time(current.sys.sunset),
+                current.main.feels_like,
+                current.main.humidity,
+                current.main.temp_max,
+                current.precipitation_probability)
+    }
+}
+
+impl Widget for Forecast {
+    fn kind(&self) -> &'static str {
+        "forecast"
+    }
+
+    fn sources(&self) -> &'static [Source] {
+        &[Source::Weather]
+    }
+
+    fn render(&self, data: &PanelData) -> String {
+        // the first period is the current one
+        let periods: Vec<&WeatherEntry> = match &data.weather {
+            Some(weather) => weather.forecast.iter().skip(1).take(self.periods).collect(),
+            None => vec![],
+        };
+        let row = |cell: &dyn Fn(&WeatherEntry) -> String| -> String {
+            periods.iter().map(|period| format!("<td>{}</td>", cell(period))).collect()
+        };
+
+        format!("<table>\n<tr>{}</tr>\n<tr>{}</tr>\n<tr>{}</tr>\n<tr>{}</tr>\n</table>\n",
+                row(&|period| icon(period, &data.icon_set)),
+                row(&|period| utc_to_local_date_time(period.time_of_forecast).time().to_string()),
+                row(&|period| period.main.temp.to_string()),
+                row(&|period| period.precipitation_probability.to_string()))
+    }
+}
+
+impl Widget for Agenda {
+    fn kind(&self) -> &'static str {
+        "agenda"
+    }
+
+    fn sources(&self) -> &'static [Source] {
+        &[Source::Agenda]
+    }
+
+    fn render(&self, data: &PanelData) -> String {
+        // today is taken from the agenda, so both agree on the display zone
+        let today = data.agenda.first().map_or(data.now.date(), |day| day.date);
+        let (day_of_week, date) = website::agenda::format_today(today, data.locale);
+
+        format!("<table>\n<tr><td class=\"dayOfWeek\" colspan=\"2\">{}</td></tr>\n<tr><td colspan=\"2\">{}</td></tr>\n{}</table>\n",
+                day_of_week,
+                date,
+                website::agenda::render_agenda(&data.agenda, data.locale))
+    }
+}
+
+impl Widget for Todos {
+    fn kind(&self) -> &'static str {
+        "todos"
+    }
+
+    fn sources(&self) -> &'static [Source] {
+        &[Source::Tasks]
+    }
+
+    fn render(&self, data: &PanelData) -> String {
+        let rows = data.tasks.as_ref().map(|tasks| website::tasks::render_tasks(tasks, data.locale)).unwrap_or_default();
+
+        format!("<table>\n<tr><td colspan=\"2\">{}</td><td><a class=\"addEntry\" href=\"/add\">+</a></td></tr>\n{}</table>\n",
+                data.locale.text("Aufgaben"), rows)
+    }
+}
+
+impl Widget for Birthdays {
+    fn kind(&self) -> &'static str {
+        "birthdays"
+    }
+
+    fn sources(&self) -> &'static [Source] {
+        &[Source::Birthdays]
+    }
+
+    fn render(&self, data: &PanelData) -> String {
+        format!("<table>\n<tr><td colspan=\"2\">{}</td></tr>\n{}</table>\n",
+                data.locale.text("Geburtstage"),
+                website::birthdays::render_birthdays(&data.anniversaries, data.now.date(), data.locale))
+    }
+}
+
+impl Widget for Waste {
+    fn kind(&self) -> &'static str {
+        "waste"
+    }
+
+    fn sources(&self) -> &'static [Source] {
+        &[Source::Agenda]
+    }
+
+    fn render(&self, data: &PanelData) -> String {
+        let today = data.now.date();
+        let mut rows = String::new();
+        for bin in &self.bins {
+            let collection = next_collection(data, bin);
+            let class = if collection.is_some_and(|date| (date - today).num_days() <= 1) { "wasteBin soon" } else { "wasteBin" };
+            let date = match collection {
+                Some(date) => data.locale.relative_day(date, today),
+                None => "-".to_string(),
+            };
+
+            rows.push_str(&format!("<tr class=\"{}\"><td class=\"wasteName\">{}</td><td class=\"wasteDate\">{}</td></tr>\n",
+                                   class, escape_html(bin), date));
+        }
+        format!("<table>\n{}</table>\n", rows)
+    }
+}
+
+impl Widget for Clock {
+    fn kind(&self) -> &'static str {
+        "clock"
+    }
+
+    fn sources(&self) -> &'static [Source] {
+        &[]
+    }
+
+    fn render(&self, data: &PanelData) -> String {
+        let (day_of_week, date) = website::agenda::format_today(data.now.date(), data.locale);
+
+        format!("<div class=\"clockTime\">{}</div>\n<div class=\"clockDate\">{}, {}</div>\n",
+                data.now.format("%H:%M"), day_of_week, date)
+    }
+}
+
+impl Widget for Text {
+    fn kind(&self) -> &'static str {
+        "text"
+    }
+
+    fn sources(&self) -> &'static [Source] {
+        &[]
+    }
+
+    fn render(&self, _data: &PanelData) -> String {
+        format!("<p>{}</p>", escape_html(&self.text))
+    }
+}
+
+// the weather icon of the set the theme shows
+fn icon(entry: &WeatherEntry, icon_set: &str) -> String {
+    format!("<img src=\"/icons/{}/{}.svg\" width=\"45\" height=\"45\">", escape_html(icon_set), escape_html(&entry.weather.icon))
+}
+
+// the first day of the agenda with an entry naming the bin
+fn next_collection(data: &PanelData, bin: &str) -> Option<NaiveDate> {
+    let bin = bin.to_lowercase();
+    data.agenda.iter()
+        .find(|day| day.entries.iter().any(|entry| entry.summary.to_lowercase().contains(&bin)))
+        .map(|day| day.date)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::agenda::{AgendaDay, AgendaEntry, CalendarStyle};
+    use crate::layout::PanelData;
+    use crate::layout::widgets::new;
+    use crate::locale::Locale;
+
+    fn data(agenda: Vec<AgendaDay>) -> PanelData {
+        PanelData {
+            now: NaiveDate::from_ymd_opt(2023, 10, 12).unwrap().and_hms_opt(7, 5, 0).unwrap(),
+            locale: Locale::German,
+            weather: None,
+            agenda,
+            tasks: None,
+            anniversaries: vec![],
+            icon_set: "colour".to_string(),
+        }
+    }
+
+    fn day(day: u32, summaries: &[&str]) -> AgendaDay {
+        let date = NaiveDate::from_ymd_opt(2023, 10, day).unwrap();
+        let entries = summaries.iter()
+            .map(|summary| AgendaEntry {
+                summary: summary.to_string(),
+                location: String::new(),
+                start: date.and_hms_opt(0, 0, 0).unwrap(),
+                end: date.and_hms_opt(23, 59, 59).unwrap(),
+                is_all_day: true,
+                time: "ganztägig".to_string(),
+                day_of_span: 1,
+                span_days: 1,
+                style: CalendarStyle::new("Müll", "", 0),
+            })
+            .collect();
+        AgendaDay { date, entries }
+    }
+
+    #[test]
+    fn waste() {
+        let widget = new("waste", "Restmüll; Papier;Gelber Sack").unwrap();
+        let agenda = vec![day(12, &[]), day(13, &["Abfuhr Restmüll"]), day(16, &["Zahnarzt", "Papier"])];
+
+        assert_eq!(widget.render(&data(agenda)),
+                   "<table>\n\
+                    <tr class=\"wasteBin soon\"><td class=\"wasteName\">Restmüll</td><td class=\"wasteDate\">morgen</td></tr>\n\
+                    <tr class=\"wasteBin\"><td class=\"wasteName\">Papier</td><td class=\"wasteDate\">16.10.</td></tr>\n\
+                    <tr class=\"wasteBin\"><td class=\"wasteName\">Gelber Sack</td><td class=\"wasteDate\">-</td></tr>\n\
+                    </table>\n");
+
+        assert!(new("waste", "").is_err());
+    }
+
+    #[test]
+    fn clock_and_text() {
+        assert_eq!(new("clock", "").unwrap().render(&data(vec![])),
+                   "<div class=\"clockTime\">07:05</div>\n<div class=\"clockDate\">Donnerstag, 12.10.2023</div>\n");
+        assert_eq!(new("text", "Gute <Reise>").unwrap().render(&data(vec![])), "<p>Gute &lt;Reise&gt;</p>");
+    }
+
+    #[test]
+    fn without_data() {
+        assert_eq!(new("weather_now", "").unwrap().render(&data(vec![])), "<table></table>\n");
+        assert_eq!(new("forecast", "").unwrap().render(&data(vec![])), "<table>\n<tr></tr>\n<tr></tr>\n<tr></tr>\n<tr></tr>\n</table>\n");
+        assert!(new("forecast", "drei").is_err());
+        assert!(new("radar", "").is_err());
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::broadcast;
+
+// updates a slow client may fall behind before it is told to reload
+const CHANNEL_CAPACITY: usize = 16;
+
+/// The new HTML of a changed section of a profile's panel, the whole element including its tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionUpdate {
+    /// Name of the profile, empty for the default panel.
+    pub profile: String,
+    pub section: String,
+    pub html: String,
+}
+
+/// Notices which sections of the rendered panels changed and passes them on to the connected
+/// clients.
+#[derive(Debug)]
+pub struct LiveUpdates {
+    sender: broadcast::Sender<SectionUpdate>,
+    // hash of every section as last rendered, by profile and section
+    hashes: Mutex<HashMap<(String, String), u64>>,
+    // connected clients by profile
+    clients: Mutex<HashMap<String, usize>>,
+}
+
+/// Updates of a profile's panel for one client, counted as connected until dropped.
+pub struct Subscription {
+    pub profile: String,
+    pub receiver: broadcast::Receiver<SectionUpdate>,
+    live: Arc<LiveUpdates>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Ok(mut clients) = self.live.clients.lock() {
+            if let Some(count) = clients.get_mut(&self.profile) {
+                *count -= 1;
+                if *count == 0 {
+                    clients.remove(&self.profile);
+                }
+            }
+        }
+    }
+}
+
+impl LiveUpdates {
+    pub(crate) fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        LiveUpdates { sender, hashes: Mutex::new(HashMap::new()), clients: Mutex::new(HashMap::new()) }
+    }
+
+    pub(crate) fn subscribe(self: &Arc<Self>, profile: &str) -> Subscription {
+        if let Ok(mut clients) = self.clients.lock() {
+            *clients.entry(profile.to_string()).or_default() += 1;
+        }
+        Subscription { profile: profile.to_string(), receiver: self.sender.subscribe(), live: self.clone() }
+    }
+
+    /// Profiles with connected clients.
+    pub(crate) fn profiles_with_clients(&self) -> Vec<String> {
+        match self.clients.lock() {
+            Ok(clients) => clients.keys().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Compares the sections of a profile's rendered panel, the elements with the given ids,
+    /// with the last one and sends those that changed. Returns the number of changed sections.
+    pub(crate) fn publish(&self, profile: &str, html: &str, sections: &[String]) -> usize {
+        let mut hashes = match self.hashes.lock() {
+            Ok(hashes) => hashes,
+            Err(_) => return 0,
+        };
+
+        let mut changed = 0;
+        for section in sections {
+            let element = match element_by_id(html, section) {
+                Some(element) => element,
+                None => continue,
+            };
+
+            let mut hasher = DefaultHasher::new();
+            element.hash(&mut hasher);
+            let hash = hasher.finish();
+
+            // the first rendering is what the clients loaded
+            if hashes.insert((profile.to_string(), section.clone()), hash).is_some_and(|previous| previous != hash) {
+                changed += 1;
+                // no client connected isn't an error
+                let _ = self.sender.send(SectionUpdate { profile: profile.to_string(), section: section.clone(), html: element.to_string() });
+            }
+        }
+        changed
+    }
+}
+
+/// The element with the given id, from its start tag to the matching end tag.
+pub(crate) fn element_by_id<'a>(html: &'a str, id: &str) -> Option<&'a str> {
+    let attribute = html.find(&format!("id=\"{}\"", id))?;
+    let start = html[..attribute].rfind('<')?;
+    let tag: String = html[start + 1..].chars()
+        .take_while(|character| character.is_ascii_alphanumeric())
+        .collect();
+
+    let (open, close) = (format!("<{}", tag), format!("</{}>", tag));
+    let mut depth = 0;
+    let mut position = start;
+    loop {
+        let next_open = html[position + 1..].find(&open).map(|found| position + 1 + found);
+        let next_close = html[position + 1..].find(&close).map(|found| position + 1 + found)?;
+
+        match next_open {
+            // a nested element of the same kind
+            Some(next_open) if next_open < next_close => {
+                if is_tag_start(html, next_open + open.len()) {
+                    depth += 1;
+                }
+                position = next_open;
+            },
+            _default if depth == 0 => return Some(&html[start..next_close + close.len()]),
+            _default => {
+                depth -= 1;
+                position = next_close;
+            },
+        }
+    }
+}
+
+// whether `<table` at this position isn't the start of e.g. `<tablefoo`
+fn is_tag_start(html: &str, after_name: usize) -> bool {
+    html[after_name..].chars().next().is_some_and(|character| character.is_whitespace() || character == '>' || character == '/')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::live::{element_by_id, LiveUpdates};
+
+    const PANEL: &str = "<body><div id=\"offlineNotice\"></div><table id=\"outmostTable\"><tr><td>\
+        <table id=\"weatherTable\"><tr><td>12</td></tr></table></td><td>\
+        <table id=\"taskTable\"><tr><td>Milch</td></tr></table></td></tr></table></body>";
+
+    #[test]
+    fn elements() {
+        assert_eq!(element_by_id(PANEL, "weatherTable"), Some("<table id=\"weatherTable\"><tr><td>12</td></tr></table>"));
+        assert_eq!(element_by_id(PANEL, "offlineNotice"), Some("<div id=\"offlineNotice\"></div>"));
+        // nested tables belong to the outer one
+        assert!(element_by_id(PANEL, "outmostTable").unwrap().ends_with("Milch</td></tr></table></td></tr></table>"));
+        assert_eq!(element_by_id(PANEL, "birthdayTable"), None);
+    }
+
+    #[test]
+    fn changed_sections() {
+        let live = Arc::new(LiveUpdates::new());
+        let sections = ["offlineNotice".to_string(), "weatherTable".to_string(), "taskTable".to_string()];
+        let mut subscription = live.subscribe("kitchen");
+        assert_eq!(live.profiles_with_clients(), vec!["kitchen"]);
+
+        assert_eq!(live.publish("kitchen", PANEL, &sections), 0);
+        assert_eq!(live.publish("kitchen", PANEL, &sections), 0);
+        // profiles are compared on their own
+        assert_eq!(live.publish("", &PANEL.replace("Milch", "Brot"), &sections), 0);
+
+        assert_eq!(live.publish("kitchen", &PANEL.replace("Milch", "Brot"), &sections), 1);
+        let update = subscription.receiver.try_recv().unwrap();
+        assert_eq!((update.profile.as_str(), update.section.as_str()), ("kitchen", "taskTable"));
+        assert_eq!(update.html, "<table id=\"taskTable\"><tr><td>Brot</td></tr></table>");
+        assert!(subscription.receiver.try_recv().is_err());
+
+        drop(subscription);
+        assert!(live.profiles_with_clients().is_empty());
+    }
+}
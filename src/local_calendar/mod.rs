@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+
+use crate::health;
+use crate::webdav::calendar::Calendar;
+use crate::webdav::calendar::vtimezone::read_display_zone;
+
+/// A calendar read from a local .ics file or a directory of them, e.g. a vdirsyncer storage.
+pub struct LocalCalendar {
+    pub name: String,
+    pub path: PathBuf,
+    files: HashMap<PathBuf, ParsedFile>,
+}
+
+// a file is parsed again once its modification time or size changes
+struct ParsedFile {
+    modified: SystemTime,
+    length: u64,
+    calendar: Calendar,
+}
+
+impl LocalCalendar {
+    pub(crate) fn new(name: &str, path: &str) -> Self {
+        LocalCalendar { name: name.to_string(), path: expand_home(path), files: HashMap::new() }
+    }
+
+    /// Parses new and modified files and forgets removed ones. Returns the number of files
+    /// parsed.
+    pub(crate) fn refresh(&mut self) -> Result<usize, String> {
+        let paths = ics_files(&self.path)?;
+        self.files.retain(|path, _| paths.contains(path));
+
+        let mut parsed: usize = 0;
+        for path in paths {
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    warn!("Skipping '{}': {}", path.display(), e);
+                    continue;
+                }
+            };
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            let length = metadata.len();
+
+            if self.files.get(&path).is_some_and(|file| file.modified == modified && file.length == length) {
+                continue;
+            }
+
+            match fs::read_to_string(&path) {
+                Ok(ics) => {
+                    debug!("Parsing '{}'", path.display());
+                    let calendar = Calendar::new(self.name.clone(), &[ics]);
+                    self.files.insert(path, ParsedFile { modified, length, calendar });
+                    parsed += 1;
+                },
+                Err(e) => warn!("Skipping '{}': {}", path.display(), e),
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Every event, todo and timezone of the files read so far.
+    pub(crate) fn calendar(&self) -> Calendar {
+        let mut calendar = Calendar::new(self.name.clone(), &[]);
+
+        // sorted, so the order doesn't change between refreshes
+        let mut paths: Vec<&PathBuf> = self.files.keys().collect();
+        paths.sort();
+        for path in paths {
+            calendar.merge(&self.files[path].calendar);
+        }
+        calendar
+    }
+}
+
+/// Reads the local calendars of a config, one `calendar=<name>,<path>` line each.
+pub(crate) fn read_local_calendars(path_config: &str) -> Result<Vec<LocalCalendar>, String> {
+    let file = File::open(path_config).map_err(|e| {
+        format!("Failed to open '{}': {}", path_config, e)
+    })?;
+
+    let mut calendars: Vec<LocalCalendar> = Vec::new();
+    let reader = BufReader::new(file);
+    for line_result in reader.lines() {
+        let line = line_result.map_err(|e| {
+            format!("Error reading '{}': {}", path_config, e)
+        })?;
+
+        if let Some(("calendar", value)) = line.split_once('=') {
+            match value.split_once(',') {
+                Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+                    calendars.push(LocalCalendar::new(name.trim(), path.trim()))
+                },
+                _ => return Err(format!("Expected 'calendar=<name>,<path>' in '{}', got '{}'", path_config, line)),
+            }
+        }
+    }
+    Ok(calendars)
+}
+
+/// Reads the local calendars of a config and keeps them up to date in the background.
+pub(crate) fn watch(path_config: &str, interval: Duration) -> Result<Arc<Mutex<Vec<LocalCalendar>>>, String> {
+    let mut calendars = read_local_calendars(path_config)?;
+    for calendar in calendars.iter_mut() {
+        if let Err(e) = refresh(calendar, interval) {
+            warn!("Couldn't read local calendar '{}': {}", calendar.name, e);
+        }
+    }
+
+    let calendars = Arc::new(Mutex::new(calendars));
+    let watched = Arc::clone(&calendars);
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            // reading the files blocks
+            let watched = Arc::clone(&watched);
+            let refreshed = tokio::task::spawn_blocking(move || {
+                let mut calendars = match watched.lock() {
+                    Ok(calendars) => calendars,
+                    Err(_) => return false,
+                };
+                for calendar in calendars.iter_mut() {
+                    match refresh(calendar, interval) {
+                        Ok(0) => (),
+                        Ok(parsed) => info!("Read {} changed file(s) of local calendar '{}'", parsed, calendar.name),
+                        Err(e) => warn!("Couldn't read local calendar '{}': {}", calendar.name, e),
+                    }
+                }
+                true
+            }).await;
+
+            if !matches!(refreshed, Ok(true)) {
+                return;
+            }
+        }
+    });
+    Ok(calendars)
+}
+
+// refreshes a calendar and records it for the status page
+fn refresh(calendar: &mut LocalCalendar, interval: Duration) -> Result<usize, String> {
+    let source = format!("local_{}", calendar.name);
+    let result = calendar.refresh();
+
+    health::record(&source, &result);
+    if let Ok(interval) = chrono::Duration::from_std(interval) {
+        health::schedule(&source, Utc::now() + interval);
+    }
+    result
+}
+
+/// The calendars of all local sources, in the display zone of the config.
+pub(crate) fn read_calendars(path_config: &str, sources: &Mutex<Vec<LocalCalendar>>) -> Vec<Calendar> {
+    let display_zone = read_display_zone(path_config);
+
+    match sources.lock() {
+        Ok(sources) => sources.iter()
+            .map(|source| {
+                let mut calendar = source.calendar();
+                calendar.display_zone = display_zone;
+                calendar
+            })
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+// A single file or every .ics file below a directory. Subdirectories that can't be read are
+// skipped, linked ones aren't followed so links can't form a loop.
+fn ics_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    let entries = fs::read_dir(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    for entry in entries.map_while(Result::ok) {
+        let entry_path = entry.path();
+        let is_directory = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+
+        if is_directory {
+            match ics_files(&entry_path) {
+                Ok(directory_files) => files.extend(directory_files),
+                Err(e) => warn!("Skipping directory: {}", e),
+            }
+        } else if entry_path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ics")) {
+            files.push(entry_path);
+        }
+    }
+    Ok(files)
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(relative), Some(home)) => home.join(relative),
+        _ => PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::local_calendar::LocalCalendar;
+
+    #[test]
+    fn directory_refresh() {
+        let directory = std::env::temp_dir().join(format!("info_panel_local_calendar_{}", std::process::id()));
+        fs::create_dir_all(directory.join("family")).unwrap();
+        fs::copy("data/test/vevent.ics", directory.join("family/event.ics")).unwrap();
+        fs::copy("data/test/vtodo.ics", directory.join("todo.ics")).unwrap();
+        fs::write(directory.join("notes.txt"), "no calendar").unwrap();
+
+        let mut calendar = LocalCalendar::new("local", directory.to_str().unwrap());
+        assert_eq!(calendar.refresh().unwrap(), 2);
+        assert_eq!(calendar.refresh().unwrap(), 0);
+        assert_eq!(calendar.calendar().events.len(), 1);
+        assert_eq!(calendar.calendar().todos.len(), 1);
+
+        // changed files are parsed again, removed ones are forgotten
+        fs::copy("data/test/vtodo_tree.ics", directory.join("todo.ics")).unwrap();
+        fs::remove_file(directory.join("family/event.ics")).unwrap();
+        assert_eq!(calendar.refresh().unwrap(), 1);
+        assert_eq!(calendar.calendar().events.len(), 0);
+        assert_eq!(calendar.calendar().todos.len(), 7);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn linked_directories() {
+        let directory = std::env::temp_dir().join(format!("info_panel_local_calendar_links_{}", std::process::id()));
+        fs::create_dir_all(directory.join("family")).unwrap();
+        fs::copy("data/test/vevent.ics", directory.join("family/event.ics")).unwrap();
+        // a link back up would recurse forever if it was followed
+        std::os::unix::fs::symlink(&directory, directory.join("family/loop")).unwrap();
+
+        let mut calendar = LocalCalendar::new("local", directory.to_str().unwrap());
+        assert_eq!(calendar.refresh().unwrap(), 1);
+        assert_eq!(calendar.calendar().events.len(), 1);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use chrono::{NaiveDate, NaiveDateTime, Weekday};
+
+/// Language of the texts and dates of the panel, German unless a profile asks for another.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Locale {
+    #[default]
+    German,
+    English,
+}
+
+impl Locale {
+    /// The locale of a language tag, e.g. `de`, `de_DE` or `en-GB`.
+    pub(crate) fn new(tag: &str) -> Result<Self, String> {
+        let language = tag.trim().split(['_', '-']).next().unwrap_or_default().to_lowercase();
+
+        match language.as_str() {
+            "de" => Ok(Locale::German),
+            "en" => Ok(Locale::English),
+            _default => Err(format!("Unsupported locale '{}', expected 'de' or 'en'", tag)),
+        }
+    }
+
+    /// The language as used by the `lang` attribute of HTML.
+    pub(crate) fn tag(self) -> &'static str {
+        match self {
+            Locale::German => "de",
+            Locale::English => "en",
+        }
+    }
+
+    /// A text of the panel, given in German.
+    pub(crate) fn text(self, german: &'static str) -> &'static str {
+        if self == Locale::German {
+            return german;
+        }
+
+        match german {
+            "Aufgaben" => "Tasks",
+            "Geburtstage" => "Birthdays",
+            "Keine Termine" => "No events",
+            "Keine Geburtstage" => "No birthdays",
+            "Jahrestag: " => "Anniversary: ",
+            "heute" => "today",
+            "morgen" => "tomorrow",
+            "ab" => "from",
+            "bis" => "until",
+            _default => german,
+        }
+    }
+
+    pub(crate) fn weekday(self, weekday: Weekday) -> &'static str {
+        match (self, weekday) {
+            (Locale::German, Weekday::Mon) => "Montag",
+            (Locale::German, Weekday::Tue) => "Dienstag",
+            (Locale::German, Weekday::Wed) => "Mittwoch",
+            (Locale::German, Weekday::Thu) => "Donnerstag",
+            (Locale::German, Weekday::Fri) => "Freitag",
+            (Locale::German, Weekday::Sat) => "Samstag",
+            (Locale::German, Weekday::Sun) => "Sonntag",
+            (Locale::English, Weekday::Mon) => "Monday",
+            (Locale::English, Weekday::Tue) => "Tuesday",
+            (Locale::English, Weekday::Wed) => "Wednesday",
+            (Locale::English, Weekday::Thu) => "Thursday",
+            (Locale::English, Weekday::Fri) => "Friday",
+            (Locale::English, Weekday::Sat) => "Saturday",
+            (Locale::English, Weekday::Sun) => "Sunday",
+        }
+    }
+
+    /// e.g. "12.10.2023" or "12/10/2023"
+    pub(crate) fn date(self, date: NaiveDate) -> String {
+        match self {
+            Locale::German => date.format("%d.%m.%Y").to_string(),
+            Locale::English => date.format("%d/%m/%Y").to_string(),
+        }
+    }
+
+    /// e.g. "12.10." or "12/10"
+    pub(crate) fn day_and_month(self, date: NaiveDate) -> String {
+        match self {
+            Locale::German => date.format("%d.%m.").to_string(),
+            Locale::English => date.format("%d/%m").to_string(),
+        }
+    }
+
+    /// e.g. "12.10. 14:30" or "12/10 14:30"
+    pub(crate) fn day_and_time(self, date_time: NaiveDateTime) -> String {
+        format!("{} {}", self.day_and_month(date_time.date()), date_time.format("%H:%M"))
+    }
+
+    /// "heute", "morgen" or the day and month of a date.
+    pub(crate) fn relative_day(self, date: NaiveDate, today: NaiveDate) -> String {
+        match (date - today).num_days() {
+            0 => self.text("heute").to_string(),
+            1 => self.text("morgen").to_string(),
+            _default => self.day_and_month(date),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, Weekday};
+
+    use crate::locale::Locale;
+
+    #[test]
+    fn new() {
+        assert_eq!(Locale::new("de_DE"), Ok(Locale::German));
+        assert_eq!(Locale::new("en-GB"), Ok(Locale::English));
+        assert_eq!(Locale::new(" EN "), Ok(Locale::English));
+        assert!(Locale::new("fr").is_err());
+    }
+
+    #[test]
+    fn texts_and_dates() {
+        let date = NaiveDate::from_ymd_opt(2023, 10, 12).unwrap();
+
+        assert_eq!(Locale::German.text("Keine Termine"), "Keine Termine");
+        assert_eq!(Locale::English.text("Keine Termine"), "No events");
+        assert_eq!(Locale::English.weekday(Weekday::Thu), "Thursday");
+        assert_eq!(Locale::German.date(date), "12.10.2023");
+        assert_eq!(Locale::English.day_and_month(date), "12/10");
+        assert_eq!(Locale::English.relative_day(date.succ_opt().unwrap(), date), "tomorrow");
+        assert_eq!(Locale::German.relative_day(date + chrono::Duration::days(3), date), "15.10.");
+    }
+}
@@ -1,24 +1,107 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::fs;
 use std::fs::File;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use axum::handler::HandlerWithoutStateExt;
-use axum::response::{Html, IntoResponse};
+use axum::extract::{ConnectInfo, MatchedPath, Path, Query, State};
+use axum::Form;
+use axum::http::{header, HeaderMap, Request, StatusCode, Uri};
+use axum::middleware;
+use axum::middleware::Next;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::Router;
-use axum::routing::get;
+use axum::routing::{get, post};
+use axum_server::tls_rustls::RustlsConfig;
 use chrono::Utc;
+use futures_util::Stream;
 use icalendar::Component;
 use log::*;
 use simplelog::*;
+use tokio::sync::broadcast::error::RecvError;
 
+use crate::access::{AccessConfig, Decision};
+use crate::agenda::{AgendaConfig, AgendaDay};
 use crate::filesystem::FileSystemHandler;
+use crate::live::LiveUpdates;
+use crate::https::ServerConfig;
+use crate::layout::{Layout, PanelData, Source, WeatherData};
+use crate::profile::{Format, Profile, PROFILE_DIRECTORY};
+use crate::local_calendar::LocalCalendar;
+use crate::offline_cache::OfflineCache;
 use crate::openweather_api::OpenWeatherClient;
-use crate::openweather_api::parsing::{parse_json_current, parse_json_forecast, utc_to_local_date_time};
+use crate::quota::{Budget, QuotaTracker};
+use crate::secrets::RedactingLogger;
+use crate::webdav::calendar::Calendar;
+use crate::webdav::contacts::vcard::VCard;
+use crate::openweather_api::parsing::{parse_json_current, parse_json_forecast};
 
 mod webdav;
+mod access;
+mod assets;
 mod openweather_api;
+mod ics_feed;
+mod live;
+mod local_calendar;
+mod metrics;
+mod agenda;
+mod health;
+mod http_client;
+mod https;
+mod layout;
+mod locale;
+mod offline_cache;
+mod profile;
+mod quota;
+mod secrets;
+mod website;
 pub mod filesystem;
 
+// how often local calendars are checked for changed files
+const LOCAL_CALENDAR_INTERVAL: Duration = Duration::from_secs(60);
+
+// how often the weather, CalDAV calendars, feeds, tasks and contacts are read
+const SOURCE_INTERVAL: Duration = Duration::from_secs(300);
+
+// how often the panel is rendered to find changes for live clients
+const LIVE_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+// how often the certificate files are checked for a renewal
+const CERTIFICATE_INTERVAL: Duration = Duration::from_secs(300);
+
+// configs holding passwords or API keys
+const CONFIGS_WITH_SECRETS: [&str; 3] = ["data/webdav.conf", "data/openweathermap_prod.conf", "data/access.conf"];
+
+// a weather refresh requests the current weather and the forecast
+const WEATHER_CALLS_PER_REFRESH: u32 = 2;
+
+#[derive(Clone)]
+struct AppState {
+    access: Arc<AccessConfig>,
+    live: Arc<LiveUpdates>,
+    local_calendars: Arc<Mutex<Vec<LocalCalendar>>>,
+    sources: Arc<Mutex<SourceData>>,
+    weather_quota: Arc<Mutex<QuotaTracker>>,
+    // where the panel is reached on this machine, assets of screenshots are loaded from there
+    local_url: String,
+}
+
+// The last data of the remote sources, read in the background so panels are rendered without
+// waiting for servers. All profiles are rendered from it.
+#[derive(Default)]
+struct SourceData {
+    weather: Option<WeatherData>,
+    // CalDAV calendars of the agenda config and of all profiles, by name
+    calendars: HashMap<String, Calendar>,
+    feeds: Vec<Calendar>,
+    tasks: Option<Calendar>,
+    contacts: Vec<VCard>,
+}
+
 #[tokio::main]
 async fn main() {
     let filesystem_handler = match FileSystemHandler::new() {
@@ -29,8 +112,8 @@ async fn main() {
         }
     };
 
-    // create logger
-    match CombinedLogger::init(
+    // create logger, secrets are redacted from every message
+    let logger = CombinedLogger::new(
         vec![
             TermLogger::new(
                 LevelFilter::Info,
@@ -43,145 +126,844 @@ async fn main() {
                 File::create(format!("{}/log_{}.log", &filesystem_handler.home_directory_software, Utc::now().format("%Y-%m-%d").to_string())).unwrap(),
             ),
         ]
-    ) {
-        Ok(_) => (),
+    );
+    match log::set_boxed_logger(Box::new(RedactingLogger::new(logger))) {
+        Ok(_) => log::set_max_level(LevelFilter::Info),
         Err(_) => {
             eprintln!("Failed to create Logger");
             return
         }
     }
 
+    secrets::warn_if_world_readable(&CONFIGS_WITH_SECRETS);
+    // secrets are resolved once at startup, a `command:` isn't run again for every request
+    if let Err(e) = webdav::resolve_secrets("webdav.conf") {
+        info!("Secrets of the WebDAV config not read: {}", e);
+    }
+    if let Err(e) = OpenWeatherClient::new("data/openweathermap_prod.conf") {
+        info!("Secrets of the OpenWeather config not read: {}", e);
+    }
+    health::start();
+
+    // the last results of all sources, shown while they can't be reached
+    if let Err(e) = OfflineCache::open() {
+        warn!("No offline cache: {}", e);
+    }
+
     // Route all requests on "/" endpoint to anonymous handler.
     //
     // A handler is an async function which returns something that implements
     // `axum::response::IntoResponse`.
 
+    let local_calendars = match local_calendar::watch("data/local_calendars.conf", LOCAL_CALENDAR_INTERVAL) {
+        Ok(local_calendars) => local_calendars,
+        Err(e) => {
+            info!("No local calendars: {}", e);
+            Arc::new(Mutex::new(vec![]))
+        }
+    };
+    let weather_budget = match Budget::new("data/openweathermap_prod.conf", WEATHER_CALLS_PER_REFRESH) {
+        Ok(budget) => budget,
+        Err(e) => {
+            info!("No OpenWeather budget: {}", e);
+            Budget::unlimited(WEATHER_CALLS_PER_REFRESH)
+        }
+    };
+    let quota_directory = filesystem_handler.create_directory("quota").ok();
+    let weather_quota = Arc::new(Mutex::new(QuotaTracker::new("openweather", weather_budget, quota_directory.as_deref())));
+
+    // only a missing config means no access control, a broken one must not open the panel
+    let access = if fs::metadata("data/access.conf").is_err() {
+        info!("No access control: 'data/access.conf' doesn't exist");
+        AccessConfig::default()
+    } else {
+        match AccessConfig::new("data/access.conf") {
+            Ok(access) => access,
+            Err(e) => {
+                error!("Invalid access config: {}", e);
+                return;
+            }
+        }
+    };
+
+    let server_config = if fs::metadata("data/server.conf").is_err() {
+        info!("Default server settings: 'data/server.conf' doesn't exist");
+        ServerConfig::default()
+    } else {
+        match ServerConfig::new("data/server.conf") {
+            Ok(server_config) => server_config,
+            Err(e) => {
+                error!("Invalid server config: {}", e);
+                return;
+            }
+        }
+    };
+
+    // assets of the directory replace the built-in ones
+    let assets_directory = server_config.assets.clone()
+        .unwrap_or_else(|| format!("{}/assets", filesystem_handler.home_directory_software));
+    if fs::metadata(&assets_directory).is_ok() {
+        info!("Assets of '{}' take precedence over the built-in ones", assets_directory);
+    }
+    assets::set_override_directory(Some(assets_directory));
+
+    let state = AppState {
+        access: Arc::new(access),
+        live: Arc::new(LiveUpdates::new()),
+        local_calendars,
+        sources: Arc::new(Mutex::new(SourceData::default())),
+        weather_quota,
+        local_url: server_config.local_url(),
+    };
+    tokio::spawn(refresh_sources(state.clone(), SOURCE_INTERVAL));
+    tokio::spawn(refresh_live_sections(state.clone(), LIVE_UPDATE_INTERVAL));
+
     // A closure or a function can be used as handler.
     let app = Router::new()
-        .nest("/weather_icons", axum_static::static_router(&format!("{}/weather_icons", &filesystem_handler.home_directory_software)))
-        .nest("/styles", axum_static::static_router("data/styles"))
-        .route("/", get(handler));
+        .route("/", get(handler))
+        .route("/p/:profile", get(profile_handler))
+        .route("/todos/complete", post(complete_todo))
+        .route("/add", get(add_form).post(add_entry_form))
+        .route("/api/v1/entries", post(add_entry_api))
+        .route("/api/v1/agenda", get(agenda_api))
+        .route("/status", get(status))
+        .route("/api/v1/status", get(status_api))
+        .route("/health", get(liveness))
+        .route("/metrics", get(metrics_endpoint))
+        .route("/events", get(live_events))
+        .route_layer(middleware::from_fn(track_requests))
+        .with_state(state.clone())
+        .route("/styles/*path", get(asset))
+        .route("/icons/*path", get(asset))
+        .route("/scripts/*path", get(asset))
+        .layer(middleware::from_fn_with_state(state, access_control));
+
+    let tls_files = match server_config.tls_files(|| filesystem_handler.create_directory("tls")) {
+        Ok(tls_files) => tls_files,
+        Err(e) => {
+            // never fall back to plain HTTP when HTTPS is asked for
+            error!("Failed to set up HTTPS: {}", e);
+            return;
+        }
+    };
 
     // Address that server will bind to.
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
+    let addr = server_config.listen;
+    let make_service = app.into_make_service_with_connect_info::<SocketAddr>();
 
-    // Use `hyper::server::Server` which is re-exported through `axum::Server` to serve the app.
-    info!("Starting service...");
-    axum::Server::bind(&addr)
-        // Hyper server takes a make service.
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    match tls_files {
+        Some(tls_files) => {
+            let tls_config = match RustlsConfig::from_pem_file(&tls_files.certificate, &tls_files.key).await {
+                Ok(tls_config) => tls_config,
+                Err(e) => {
+                    error!("Failed to read the certificate '{}': {}", tls_files.certificate, e);
+                    return;
+                }
+            };
+            match https::fingerprint(&tls_files.certificate) {
+                Ok(fingerprint) => info!("Certificate fingerprint (SHA-256): {}", fingerprint),
+                Err(e) => warn!("{}", e),
+            }
+            https::watch(tls_config.clone(), tls_files, CERTIFICATE_INTERVAL);
+
+            info!("Starting service on https://{}...", addr);
+            axum_server::bind_rustls(addr, tls_config)
+                .serve(make_service)
+                .await
+                .unwrap();
+        },
+        None => {
+            // Use `hyper::server::Server` which is re-exported through `axum::Server` to serve the app.
+            info!("Starting service on http://{}...", addr);
+            axum::Server::bind(&addr)
+                // Hyper server takes a make service.
+                .serve(make_service)
+                .await
+                .unwrap();
+        },
+    }
 }
 
 
-async fn handler() -> Html<String> {
-    let path = "src/website/index.html";
-    let html_file = fs::read_to_string(path);
+// Lets requests through that the access config allows. A token sent in the query is kept in a
+// cookie, so links and forms of the panel work without it.
+async fn access_control<B>(State(state): State<AppState>, ConnectInfo(client): ConnectInfo<SocketAddr>, request: Request<B>, next: Next<B>) -> Response {
+    let permission = access::permission_of(request.method(), request.uri().path());
+    let credentials = access::Credentials::from_request(request.headers(), request.uri().query());
 
-    return match html_file {
-        Ok(html_content) => {
-            return match set_weather_data(&html_content).await {
-                Ok(modified_html) => Html(modified_html),
-                Err(e) => {
-                    error!("Error setting weather data: {}", e);
-                    Html(html_content)
+    match state.access.decide(client.ip(), permission, &credentials) {
+        Decision::Allow(device) => {
+            let query_token = access::query_token(request.uri().query()).filter(|_| device.is_some());
+
+            let mut response = next.run(request).await;
+            if let Some(token) = query_token {
+                let cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age=31536000", access::TOKEN_COOKIE, token);
+                if let Ok(cookie) = header::HeaderValue::from_str(&cookie) {
+                    response.headers_mut().append(header::SET_COOKIE, cookie);
                 }
             }
+            response
+        },
+        Decision::Forbidden => {
+            warn!("Refused {} {} of {}", request.method(), request.uri().path(), client.ip());
+            (StatusCode::FORBIDDEN, "Forbidden").into_response()
+        },
+        Decision::Unauthorized(basic) => {
+            info!("Unauthorized {} {} of {}", request.method(), request.uri().path(), client.ip());
+            if basic {
+                (StatusCode::UNAUTHORIZED, [(header::WWW_AUTHENTICATE, "Basic realm=\"InfoPanel\"")], "Unauthorized").into_response()
+            } else {
+                (StatusCode::UNAUTHORIZED, "Unauthorized").into_response()
+            }
+        },
+    }
+}
+
+// Counts the requests of every route and how long they took.
+async fn track_requests<B>(request: Request<B>, next: Next<B>) -> Response {
+    let route = request.extensions().get::<MatchedPath>()
+        .map_or_else(|| request.uri().path().to_string(), |path| path.as_str().to_string());
+    let method = request.method().to_string();
+    let started = std::time::Instant::now();
+
+    let response = next.run(request).await;
+
+    let status = response.status().as_u16().to_string();
+    metrics::count(metrics::HTTP_REQUESTS, &[("route", &route), ("method", &method), ("status", &status)]);
+    metrics::observe(metrics::HTTP_REQUEST_DURATION, &[("route", &route)], started.elapsed());
+    response
+}
 
+async fn handler(State(state): State<AppState>) -> Html<String> {
+    match render_panel(&state, &default_profile()) {
+        Ok((html_content, sections)) => {
+            // clients connected for live updates get what changed since
+            state.live.publish("", &html_content, &sections);
+            Html(html_content)
+        },
+        Err(e) => {
+            error!("{}", e);
+            Html("Error reading HTML file. Check the log file.".to_string())
+        }
+    }
+}
+
+// `/p/<name>` in the format of the profile, `/p/<name>.html` and `/p/<name>.png` in that format
+async fn profile_handler(State(state): State<AppState>, Path(profile): Path<String>) -> Response {
+    let (name, format) = match (profile.strip_suffix(".png"), profile.strip_suffix(".html")) {
+        (Some(name), _) => (name, Some(Format::Png)),
+        (_, Some(name)) => (name, Some(Format::Html)),
+        _default => (profile.as_str(), None),
+    };
+
+    let profile = match Profile::read(PROFILE_DIRECTORY, name) {
+        Ok(profile) => profile,
+        Err(e) => {
+            warn!("Unknown profile: {}", e);
+            return (StatusCode::NOT_FOUND, "Unknown profile").into_response();
+        }
+    };
+
+    let (html_content, sections) = match render_panel(&state, &profile) {
+        Ok(panel) => panel,
+        Err(e) => {
+            error!("{}", e);
+            return Html("Error reading HTML file. Check the log file.".to_string()).into_response();
+        }
+    };
 
+    match format.unwrap_or(profile.format) {
+        Format::Html => {
+            state.live.publish(&profile.name, &html_content, &sections);
+            Html(html_content).into_response()
         },
+        Format::Png => {
+            // the renderer is a blocking process
+            let local_url = state.local_url.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let directory = FileSystemHandler::new()?.create_directory("render")?;
+                profile.screenshot(&html_content, &local_url, &directory)
+            }).await.map_err(|e| format!("Rendering PNG failed: {}", e));
+
+            match result {
+                Ok(Ok(png)) => ([(header::CONTENT_TYPE, "image/png")], png).into_response(),
+                Ok(Err(e)) | Err(e) => {
+                    error!("Couldn't render PNG: {}", e);
+                    (StatusCode::INTERNAL_SERVER_ERROR, "Couldn't render PNG. Check the log file.").into_response()
+                }
+            }
+        },
+    }
+}
+
+// Styles and scripts, built in or of the assets directory
+async fn asset(uri: Uri) -> Response {
+    let path = uri.path().trim_start_matches('/');
+
+    match assets::read(path) {
+        Ok(content) => ([(header::CONTENT_TYPE, assets::content_type(path))], content).into_response(),
+        Err(e) => {
+            debug!("{}", e);
+            (StatusCode::NOT_FOUND, "Not found").into_response()
+        }
+    }
+}
+
+// The panel of a profile and the ids of its sections that are updated live.
+fn render_panel(state: &AppState, profile: &Profile) -> Result<(String, Vec<String>), String> {
+    let html_content = assets::read_to_string("templates/index.html")?;
+
+    let layout = read_layout(profile);
+    let mut data = read_panel_data(state, profile, &layout);
+
+    let eink = match AgendaConfig::new("data/agenda.conf") {
+        Ok(config) => config.eink,
+        Err(e) => {
+            error!("Error reading agenda config: {}", e);
+            false
+        }
+    };
+
+    // sunrise and sunset of the current weather switch to the night theme
+    let sun = data.weather.as_ref().map(|weather| (weather.current.sys.sunrise, weather.current.sys.sunset));
+    let theme = match profile.theme_at(Utc::now().naive_utc(), sun) {
+        "" if eink => "eink",
+        "" => "light",
+        theme => theme,
+    };
+    data.icon_set = profile.icon_set(theme).to_string();
+    let mut classes = vec![format!("theme-{}", theme)];
+    if eink {
+        classes.push("eink".to_string());
+    }
+
+    let html_content = html_content
+        .replace("#lang", profile.locale.tag())
+        .replace("#body_class", &classes.join(" "))
+        .replace("#width", &profile.width.to_string())
+        .replace("#height", &profile.height.to_string())
+        .replace("#offline", &render_offline_notice())
+        .replace("#panel", &layout.render(&data));
+
+    let mut sections = vec!["theme".to_string(), "offlineNotice".to_string()];
+    sections.extend(layout.section_ids());
+    Ok((html_content, sections))
+}
+
+// birthdays and anniversaries of the coming week
+const BIRTHDAY_DAYS: i64 = 7;
+
+// The panel at `/`, set up by the profile `default` if there is one
+fn default_profile() -> Profile {
+    if fs::metadata(format!("{}/default.conf", PROFILE_DIRECTORY)).is_err() {
+        return Profile::default();
+    }
+
+    match Profile::read(PROFILE_DIRECTORY, "default") {
+        Ok(profile) => profile,
+        Err(e) => {
+            warn!("Using the built-in default profile: {}", e);
+            Profile::default()
+        }
+    }
+}
+
+fn read_layout(profile: &Profile) -> Layout {
+    match Layout::new(&profile.layout) {
+        Ok(layout) => layout,
+        Err(e) => {
+            warn!("Using the default layout: {}", e);
+            Layout::default()
+        }
+    }
+}
+
+// Takes what the widgets of the layout show from the last data of the sources. Data that
+// couldn't be read is left out.
+fn read_panel_data(state: &AppState, profile: &Profile, layout: &Layout) -> PanelData {
+    let display_zone = webdav::calendar::vtimezone::read_display_zone("data/webdav.conf");
+    let now = webdav::calendar::vtimezone::now_in(display_zone);
+
+    let sources = match state.sources.lock() {
+        Ok(sources) => sources,
         Err(_) => {
-            error!("Error reading HTML file. Is the path '{}' correct?", path);
-            Html(format!("Error reading HTML file. Check the log file."))
+            error!("Data of the sources unavailable");
+            return PanelData { now, locale: profile.locale, weather: None, agenda: vec![], tasks: None, anniversaries: vec![], icon_set: profile.icon_set(&profile.theme).to_string() };
+        }
+    };
+
+    // the night theme needs sunrise and sunset
+    let weather = if layout.needs(Source::Weather) || !profile.night_theme.is_empty() {
+        sources.weather.clone()
+    } else {
+        None
+    };
+
+    let agenda = if layout.needs(Source::Agenda) {
+        match build_agenda(state, &sources, profile) {
+            Ok(agenda) => agenda,
+            Err(e) => {
+                error!("Error reading agenda data: {}", e);
+                vec![]
+            }
         }
+    } else {
+        vec![]
+    };
+
+    let tasks = if layout.needs(Source::Tasks) {
+        sources.tasks.clone()
+    } else {
+        None
+    };
+
+    let anniversaries = if layout.needs(Source::Birthdays) {
+        webdav::contacts::upcoming(&sources.contacts, now.date(), BIRTHDAY_DAYS)
+    } else {
+        vec![]
+    };
+
+    let icon_set = profile.icon_set(&profile.theme).to_string();
+    PanelData { now, locale: profile.locale, weather, agenda, tasks, anniversaries, icon_set }
+}
+
+// Reads the sources the panels of all profiles show, one after another, and keeps their data for
+// rendering. Sources that can't be read fall back to their offline cache.
+async fn refresh_sources(state: AppState, interval: Duration) {
+    loop {
+        let mut profiles = Profile::read_all(PROFILE_DIRECTORY);
+        profiles.push(default_profile());
+        let layouts: Vec<Layout> = profiles.iter().map(read_layout).collect();
+        let needs = |source: Source| layouts.iter().any(|layout| layout.needs(source));
+        // cache keys of the sources read below, for the status page
+        let mut scheduled: Vec<String> = Vec::new();
+
+        let weather = if needs(Source::Weather) || profiles.iter().any(|profile| !profile.night_theme.is_empty()) {
+            match read_weather_data(&state).await {
+                Ok(weather) => Some(weather),
+                Err(e) => {
+                    error!("Error reading weather data: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let (calendars, feeds) = if needs(Source::Agenda) {
+            let mut names = match AgendaConfig::new("data/agenda.conf") {
+                Ok(config) => config.calendars,
+                Err(e) => {
+                    error!("Error reading agenda config: {}", e);
+                    vec![]
+                }
+            };
+            for profile in &profiles {
+                names.extend(profile.calendars.iter().flatten().cloned());
+            }
+            names.sort();
+            names.dedup();
+            scheduled.extend(names.iter().map(|name| format!("calendar_{}", name)));
+            if let Ok(feeds) = ics_feed::read_feeds("data/feeds.conf") {
+                scheduled.extend(feeds.iter().map(|feed| format!("feed_{}", feed.name)));
+            }
+            (agenda::read_caldav_calendars(&names).await, agenda::read_feeds().await)
+        } else {
+            (HashMap::new(), vec![])
+        };
+
+        let tasks = if needs(Source::Tasks) {
+            if let Some(name) = webdav::read_task_calendar_name("data/webdav.conf") {
+                scheduled.push(format!("calendar_{}", name));
+            }
+            // the WebDAV client is blocking
+            match tokio::task::spawn_blocking(|| webdav::read_tasks("webdav.conf")).await {
+                Ok(Ok(calendar)) => Some(calendar),
+                Ok(Err(e)) => {
+                    error!("Error reading task data: {}", e);
+                    None
+                },
+                Err(e) => {
+                    error!("Reading tasks failed: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let contacts = if needs(Source::Birthdays) {
+            let address_books = webdav::contacts::read_address_book_names("data/webdav.conf");
+            scheduled.extend(address_books.iter().map(|name| format!("address_book_{}", name)));
+            // the WebDAV client is blocking
+            match tokio::task::spawn_blocking(|| webdav::contacts::read_contacts("webdav.conf")).await {
+                Ok(contacts) => contacts,
+                Err(e) => {
+                    error!("Reading contacts failed: {}", e);
+                    vec![]
+                }
+            }
+        } else {
+            vec![]
+        };
+
+        match state.sources.lock() {
+            Ok(mut sources) => *sources = SourceData { weather, calendars, feeds, tasks, contacts },
+            Err(_) => return,
+        }
+        if let Ok(interval) = chrono::Duration::from_std(interval) {
+            for source in &scheduled {
+                health::schedule(source, Utc::now() + interval);
+            }
+        }
+        tokio::time::sleep(interval).await;
     }
 }
 
-async fn set_weather_data(html_content: &String) -> Result<String, String> {
+// Renders the panel now and then while clients wait for live updates, so they learn about
+// changed weather or calendars without reloading.
+async fn refresh_live_sections(state: AppState, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        for name in state.live.profiles_with_clients() {
+            let profile = match name.as_str() {
+                "" => default_profile(),
+                name => match Profile::read(PROFILE_DIRECTORY, name) {
+                    Ok(profile) => profile,
+                    Err(e) => {
+                        warn!("Couldn't read the profile for live updates: {}", e);
+                        continue;
+                    }
+                },
+            };
+
+            match render_panel(&state, &profile) {
+                Ok((html_content, sections)) => {
+                    let changed = state.live.publish(&name, &html_content, &sections);
+                    if changed > 0 {
+                        debug!("Sent {} changed section(s) to live clients", changed);
+                    }
+                },
+                Err(e) => warn!("Couldn't render the panel for live updates: {}", e),
+            }
+        }
+    }
+}
+
+// Server-sent events with every changed section of the panel of a profile (`?profile=<name>`,
+// the default panel without), as `section` events holding its id and HTML.
+async fn live_events(State(state): State<AppState>, Query(query): Query<HashMap<String, String>>) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response> {
+    // empty is the default panel, other profiles need a config
+    let profile = query.get("profile").map_or("", String::as_str);
+    if !profile.is_empty() {
+        if let Err(e) = Profile::read(PROFILE_DIRECTORY, profile) {
+            warn!("Unknown profile: {}", e);
+            return Err((StatusCode::NOT_FOUND, "Unknown profile").into_response());
+        }
+    }
+    let subscription = state.live.subscribe(profile);
+
+    let events = futures_util::stream::unfold(subscription, |mut subscription| async move {
+        let event = loop {
+            match subscription.receiver.recv().await {
+                Ok(update) if update.profile != subscription.profile => continue,
+                Ok(update) => break Event::default()
+                    .event("section")
+                    .data(json::object!{ section: update.section, html: update.html }.dump()),
+                // the client missed updates and has to load the whole panel
+                Err(RecvError::Lagged(_)) => break Event::default().event("reload").data(""),
+                Err(RecvError::Closed) => return None,
+            }
+        };
+        Some((Ok(event), subscription))
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+async fn read_weather_data(state: &AppState) -> Result<WeatherData, String> {
     let client = match OpenWeatherClient::new("data/openweathermap_prod.conf") {
         Ok(client) => client,
         Err(e) => return Err(format!("Couldn't create OpenWeatherClient: {}", e)),
     };
 
-    let json_current = match client.make_request_current().await {
+    let (json_current, json_forecast) = read_weather(&client, &state.weather_quota).await?;
+
+    let current = match parse_json_current(&json_current) {
+        Some(data) => data,
+        None => return Err(format!("Couldn't parse json (current) into weather entry: {}", json_current)),
+    };
+
+    let forecast = match parse_json_forecast(&json_forecast) {
+        Some(data) => data,
+        None => return Err(format!("Couldn't parse json (forecast) into weather entries: {}", json_forecast)),
+    };
+
+    let location = current.city.as_str();
+    metrics::set_gauge(metrics::WEATHER_TEMPERATURE, &[("location", location), ("units", &client.units)], current.main.temp as f64);
+    metrics::set_gauge(metrics::WEATHER_FEELS_LIKE, &[("location", location), ("units", &client.units)], current.main.feels_like as f64);
+    metrics::set_gauge(metrics::WEATHER_HUMIDITY, &[("location", location)], current.main.humidity as f64);
+
+    Ok(WeatherData { current, forecast })
+}
+
+// The current weather and the forecast as JSON. OpenWeather is only asked once the refresh
+// interval of its budget passed, in between the last responses are used.
+async fn read_weather(client: &OpenWeatherClient, quota: &Mutex<QuotaTracker>) -> Result<(String, String), String> {
+    let cache = OfflineCache::shared();
+    let cached_weather = cache
+        .and_then(|cache| cache.load("weather_current").zip(cache.load("weather_forecast")));
+
+    let now = Utc::now();
+    let reserved = quota.lock()
+        .map(|mut quota| {
+            let reserved = quota.try_reserve(now, cached_weather.is_none());
+            schedule_weather(&quota, now);
+            reserved
+        })
+        .map_err(|e| format!("Quota of OpenWeather unavailable: {}", e))?;
+
+    if !reserved {
+        return match cached_weather {
+            Some(weather) => {
+                metrics::count_cache_lookup("weather", true);
+                Ok(weather)
+            },
+            None => Err("Budget of OpenWeather used up and no weather kept".to_string()),
+        };
+    }
+    metrics::count_cache_lookup("weather", false);
+
+    // the last response is used while OpenWeather can't be reached
+    let keep_or_load = |key: &str, result: Result<String, String>| match cache {
+        Some(cache) => cache.keep_or_load(key, result),
+        None => result,
+    };
+
+    let result_current = client.make_request_current().await;
+    let json_current = match keep_or_load("weather_current", result_current) {
         Ok(json) => json,
         Err(e) => return Err(format!("Error making forecast request: {}", e)),
     };
 
-    let json_forecast = match client.make_request_forecast_3h_5d().await {
+    let result_forecast = client.make_request_forecast_3h_5d().await;
+    let json_forecast = match keep_or_load("weather_forecast", result_forecast) {
         Ok(json) => json,
         Err(e) => return Err(format!("Error making forecast request: {}", e)),
     };
 
-    let weather_entry_current = match parse_json_current(&json_current) {
-        Some(data) => data,
-        None => return Err(format!("Couldn't parse json (current) into weather entry: {}", json_forecast)),
-    };
+    Ok((json_current, json_forecast))
+}
 
-    let weather_entries_forecast = match parse_json_forecast(&json_forecast) {
-        Some(data) => data,
-        None => return Err(format!("Couldn't parse json (forecast) into weather entries: {}", json_forecast)),
-    };
+// both weather requests are made on the schedule of the budget
+fn schedule_weather(quota: &QuotaTracker, now: chrono::DateTime<Utc>) {
+    let interval = chrono::Duration::from_std(quota.refresh_interval(now)).unwrap_or_default();
+    let next_refresh = quota.last_refresh().unwrap_or(now) + interval;
 
-    let mut modified_html_content = html_content.clone();
+    health::schedule("weather_current", next_refresh);
+    health::schedule("weather_forecast", next_refresh);
+}
+
+fn render_offline_notice() -> String {
+    let offline_since = OfflineCache::shared().and_then(|cache| cache.offline_since());
+    let display_zone = webdav::calendar::vtimezone::read_display_zone("data/webdav.conf");
+
+    website::offline::render_offline(offline_since, display_zone)
+}
 
-    let weather_entry_0 = weather_entries_forecast.get(0).unwrap();
-    let weather_entry_3 = weather_entries_forecast.get(1).unwrap();
-    let weather_entry_6 = weather_entries_forecast.get(2).unwrap();
-    let weather_entry_9 = weather_entries_forecast.get(3).unwrap();
+async fn complete_todo(State(state): State<AppState>, headers: HeaderMap, Form(form): Form<HashMap<String, String>>) -> Response {
+    let field = |key: &str| form.get(key).cloned().unwrap_or_default();
+    let (uid, path, e_tag) = (field("uid"), field("path"), field("e_tag"));
+
+    let result = tokio::task::spawn_blocking(move || {
+        webdav::complete_todo("webdav.conf", &uid, &path, &e_tag)
+    }).await;
+
+    // back to the panel of the profile the todo was completed on
+    let panel = headers.get(header::REFERER)
+        .and_then(|referer| referer.to_str().ok())
+        .and_then(|referer| reqwest::Url::parse(referer).ok())
+        .map(|referer| referer.path().to_string())
+        .filter(|path| path.starts_with("/p/"))
+        .unwrap_or_else(|| "/".to_string());
+
+    let completion = match result {
+        Ok(Ok(completion)) => completion,
+        Ok(Err(e)) => {
+            error!("Couldn't complete todo: {}", e);
+            return Redirect::to(&panel).into_response();
+        },
+        Err(e) => {
+            error!("Completing todo failed: {}", e);
+            return Redirect::to(&panel).into_response();
+        },
+    };
 
-    modified_html_content = modified_html_content.replace("#temp_current", weather_entry_current.main.temp.to_string().as_str());
-    modified_html_content = modified_html_content.replace("#desc", weather_entry_current.weather.description.to_string().as_str());
-    modified_html_content = modified_html_content.replace("#feel_like_current", weather_entry_current.main.feels_like.to_string().as_str());
-    modified_html_content = modified_html_content.replace("#humidity_current", weather_entry_current.main.humidity.to_string().as_str());
-    modified_html_content = modified_html_content.replace("#max_temp_current", weather_entry_current.main.temp_max.to_string().as_str());
-    modified_html_content = modified_html_content.replace("#rain_current", weather_entry_current.precipitation_probability.to_string().as_str());
-    modified_html_content = modified_html_content.replace("#sunrise", utc_to_local_date_time(weather_entry_current.sys.sunrise).time().to_string().as_str());
-    modified_html_content = modified_html_content.replace("#sunset", utc_to_local_date_time(weather_entry_current.sys.sunset).time().to_string().as_str());
+    // the panel shows the todos as they are on the server now, with their current ETags
+    refresh_tasks(&state).await;
 
-    modified_html_content = modified_html_content.replace("#time_+3", utc_to_local_date_time(weather_entry_3.time_of_forecast).time().to_string().as_str());
-    modified_html_content = modified_html_content.replace("#temp_+3", weather_entry_3.main.temp.to_string().as_str());
-    modified_html_content = modified_html_content.replace("#rain_+3", weather_entry_3.precipitation_probability.to_string().as_str());
+    match completion {
+        webdav::Completion::Completed => Redirect::to(&panel).into_response(),
+        webdav::Completion::Conflict => (StatusCode::CONFLICT, Html(format!(
+            "<p>Die Aufgabe wurde inzwischen auf dem Server geändert und ist nicht abgehakt.</p>\
+            <p><a href=\"{}\">Zurück zur Übersicht</a></p>", website::escape_html(&panel)))).into_response(),
+    }
+}
 
-    modified_html_content = modified_html_content.replace("#time_+6", utc_to_local_date_time(weather_entry_6.time_of_forecast).time().to_string().as_str());
-    modified_html_content = modified_html_content.replace("#temp_+6", weather_entry_6.main.temp.to_string().as_str());
-    modified_html_content = modified_html_content.replace("#rain_+6", weather_entry_6.precipitation_probability.to_string().as_str());
+// reads the task calendar again without waiting for the next refresh of the sources
+async fn refresh_tasks(state: &AppState) {
+    match tokio::task::spawn_blocking(|| webdav::read_tasks("webdav.conf")).await {
+        Ok(Ok(calendar)) => if let Ok(mut sources) = state.sources.lock() {
+            sources.tasks = Some(calendar);
+        },
+        Ok(Err(e)) => error!("Error reading task data: {}", e),
+        Err(e) => error!("Reading tasks failed: {}", e),
+    }
+}
 
-    modified_html_content = modified_html_content.replace("#time_+9", utc_to_local_date_time(weather_entry_9.time_of_forecast).time().to_string().as_str());
-    modified_html_content = modified_html_content.replace("#temp_+9", weather_entry_9.main.temp.to_string().as_str());
-    modified_html_content = modified_html_content.replace("#rain_+9", weather_entry_9.precipitation_probability.to_string().as_str());
+async fn add_form() -> Html<String> {
+    render_add_form("").await
+}
 
+async fn render_add_form(message: &str) -> Html<String> {
+    let calendars = webdav::read_writable_calendars("webdav.conf");
 
-    match client.download_icon(&weather_entry_0.weather.icon).await  {
-        Ok(_) => debug!("Success reading icon current weather."),
-        Err(msg) => error!("{}",msg)
+    match website::add::render_form(&calendars, message) {
+        Ok(html) => Html(html),
+        Err(e) => {
+            error!("{}", e);
+            Html("Error reading HTML file. Check the log file.".to_string())
+        }
+    }
+}
 
+async fn add_entry_form(Form(form): Form<HashMap<String, String>>) -> Response {
+    match add_entry(form).await {
+        Ok(_) => Redirect::to("/").into_response(),
+        Err(e) => {
+            warn!("Couldn't add entry: {}", e);
+            render_add_form(&e).await.into_response()
+        }
     }
+}
+
+// Expects a JSON object with the fields of the quick-add form, e.g.
+// {"kind": "todo", "calendar": "family", "summary": "buy milk", "date": "2023-10-13"}
+async fn add_entry_api(body: String) -> Response {
+    let json_value = match json::parse(&body) {
+        Ok(json_value) if json_value.is_object() => json_value,
+        _ => return json_response(StatusCode::BAD_REQUEST, json::object!{ error: "Expected a JSON object" }),
+    };
 
-    match client.download_icon(&weather_entry_3.weather.icon).await  {
-        Ok(_) => debug!("Success reading icon weather+3."),
-        Err(msg) => error!("{}",msg)
+    // null is a missing field, anything else but text is rejected
+    let mut fields: HashMap<String, String> = HashMap::new();
+    for (key, value) in json_value.entries().filter(|(_, value)| !value.is_null()) {
+        match value.as_str() {
+            Some(text) => fields.insert(key.to_string(), text.to_string()),
+            None => return json_response(StatusCode::BAD_REQUEST, json::object!{ error: format!("'{}' must be a string", key) }),
+        };
+    }
 
+    match add_entry(fields).await {
+        Ok(uid) => json_response(StatusCode::CREATED, json::object!{ uid: uid }),
+        Err(e) => {
+            warn!("Couldn't add entry: {}", e);
+            json_response(StatusCode::BAD_REQUEST, json::object!{ error: e })
+        }
     }
+}
 
-    match client.download_icon(&weather_entry_6.weather.icon).await  {
-        Ok(_) => debug!("Success reading icon weather+6."),
-        Err(msg) => error!("{}",msg)
+async fn add_entry(fields: HashMap<String, String>) -> Result<String, String> {
+    let display_zone = webdav::calendar::vtimezone::read_display_zone("data/webdav.conf");
+    let (calendar, entry) = website::add::parse_entry(&fields, display_zone)?;
+
+    tokio::task::spawn_blocking(move || webdav::add_entry("webdav.conf", &calendar, &entry))
+        .await
+        .map_err(|e| format!("Adding entry failed: {}", e))?
+}
 
+fn json_response(status: StatusCode, json_value: json::JsonValue) -> Response {
+    (status, [(header::CONTENT_TYPE, "application/json")], json_value.dump()).into_response()
+}
+
+// The agenda of a profile from the last data of the sources.
+fn build_agenda(state: &AppState, sources: &SourceData, profile: &Profile) -> Result<Vec<AgendaDay>, String> {
+    let mut config = AgendaConfig::new("data/agenda.conf")?;
+    if let Some(calendars) = &profile.calendars {
+        config.calendars = calendars.clone();
     }
+    let calendars = agenda::collect_calendars(&config, &sources.calendars, &sources.feeds, &state.local_calendars);
+
+    let display_zone = webdav::calendar::vtimezone::read_display_zone("data/webdav.conf");
+    let today = webdav::calendar::vtimezone::now_in(display_zone).date();
 
-    match client.download_icon(&weather_entry_9.weather.icon).await  {
-        Ok(_) => debug!("Success reading icon weather+9."),
-        Err(msg) => error!("{}",msg)
+    Ok(agenda::build_agenda(&calendars, today, config.days, profile.locale))
+}
+
+async fn status(State(state): State<AppState>) -> Html<String> {
+    let now = Utc::now();
+    let display_zone = webdav::calendar::vtimezone::read_display_zone("data/webdav.conf");
+
+    let summary = website::status::render_summary(health::started(), now, display_zone);
+    let sources: String = health::sources().iter()
+        .map(|(name, source)| website::status::render_source(name, source, now, display_zone))
+        .collect();
+    let quota = match state.weather_quota.lock() {
+        Ok(quota) => website::status::render_quota(&quota, now),
+        Err(_) => String::new(),
+    };
 
+    match website::status::render_status(&summary, &sources, &quota) {
+        Ok(html) => Html(html),
+        Err(e) => {
+            error!("{}", e);
+            Html("Error reading HTML file. Check the log file.".to_string())
+        }
     }
+}
 
-    modified_html_content = modified_html_content.replace("#icon_current", &format!("/weather_icons/{}.png", &weather_entry_0.weather.icon)).to_string();
-    modified_html_content = modified_html_content.replace("#icon_+3", &format!("/weather_icons/{}.png", &weather_entry_3.weather.icon)).to_string();
-    modified_html_content = modified_html_content.replace("#icon_+6", &format!("/weather_icons/{}.png", &weather_entry_6.weather.icon)).to_string();
-    modified_html_content = modified_html_content.replace("#icon_+9", &format!("/weather_icons/{}.png", &weather_entry_9.weather.icon)).to_string();
+async fn status_api(State(state): State<AppState>) -> Response {
+    let now = Utc::now();
+    let quotas = match state.weather_quota.lock() {
+        Ok(quota) => json::array![json::object!{
+            provider: quota.provider.as_str(),
+            calls_today: quota.calls_today(now),
+            calls_per_day: quota.budget.calls_per_day,
+            calls_this_minute: quota.calls_this_minute(now),
+            calls_per_minute: quota.budget.calls_per_minute,
+            refresh_interval_seconds: quota.refresh_interval(now).as_secs(),
+        }],
+        Err(_) => json::array![],
+    };
 
+    json_response(StatusCode::OK, health::to_json(&health::sources(), quotas, now))
+}
 
-    Ok(modified_html_content)
-}
\ No newline at end of file
+async fn metrics_endpoint() -> Response {
+    let text = metrics::render(&health::sources(), health::started());
+    (StatusCode::OK, [(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response()
+}
+
+// Liveness check for systemd or container health checks, fails once shared state is broken by
+// a panic.
+async fn liveness(State(state): State<AppState>) -> Response {
+    if state.local_calendars.is_poisoned() || state.sources.is_poisoned() || state.weather_quota.is_poisoned() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "unhealthy").into_response();
+    }
+    (StatusCode::OK, "ok").into_response()
+}
+
+async fn agenda_api(State(state): State<AppState>) -> Response {
+    let agenda = match state.sources.lock() {
+        Ok(sources) => build_agenda(&state, &sources, &default_profile()),
+        Err(_) => Err("Data of the sources unavailable".to_string()),
+    };
+    match agenda {
+        Ok(agenda) => json_response(StatusCode::OK, agenda::to_json(&agenda)),
+        Err(e) => {
+            error!("Couldn't read agenda: {}", e);
+            json_response(StatusCode::INTERNAL_SERVER_ERROR, json::object!{ error: e })
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use crate::health::SourceHealth;
+
+// every value recorded since the start, by metric and labels
+static METRICS: OnceLock<Mutex<Registry>> = OnceLock::new();
+
+// upper bounds of the duration histograms, in seconds
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+pub(crate) const HTTP_REQUESTS: &str = "infopanel_http_requests_total";
+pub(crate) const HTTP_REQUEST_DURATION: &str = "infopanel_http_request_duration_seconds";
+pub(crate) const UPSTREAM_REQUESTS: &str = "infopanel_upstream_requests_total";
+pub(crate) const UPSTREAM_ERRORS: &str = "infopanel_upstream_errors_total";
+pub(crate) const UPSTREAM_REQUEST_DURATION: &str = "infopanel_upstream_request_duration_seconds";
+pub(crate) const CACHE_LOOKUPS: &str = "infopanel_cache_lookups_total";
+pub(crate) const WEATHER_TEMPERATURE: &str = "infopanel_weather_temperature";
+pub(crate) const WEATHER_FEELS_LIKE: &str = "infopanel_weather_feels_like";
+pub(crate) const WEATHER_HUMIDITY: &str = "infopanel_weather_humidity_percent";
+const LAST_SUCCESS: &str = "infopanel_source_last_success_timestamp_seconds";
+const LAST_ERROR: &str = "infopanel_source_last_error_timestamp_seconds";
+const SOURCE_UP: &str = "infopanel_source_up";
+const START_TIME: &str = "infopanel_start_time_seconds";
+
+// type and description of every metric
+const HELP: [(&str, &str, &str); 13] = [
+    (HTTP_REQUESTS, "counter", "Requests of the panel by route, method and status."),
+    (HTTP_REQUEST_DURATION, "histogram", "Time taken to answer requests of the panel by route."),
+    (UPSTREAM_REQUESTS, "counter", "Requests to data sources by provider and status, every retry counts."),
+    (UPSTREAM_ERRORS, "counter", "Requests to data sources that failed or were answered with an error, by provider."),
+    (UPSTREAM_REQUEST_DURATION, "histogram", "Time taken by requests to data sources by provider."),
+    (CACHE_LOOKUPS, "counter", "Lookups of kept results by cache, a hit saved or replaced a request."),
+    (WEATHER_TEMPERATURE, "gauge", "Current temperature in the units of the OpenWeather config."),
+    (WEATHER_FEELS_LIKE, "gauge", "Current felt temperature in the units of the OpenWeather config."),
+    (WEATHER_HUMIDITY, "gauge", "Current relative humidity."),
+    (LAST_SUCCESS, "gauge", "Time a data source was last read."),
+    (LAST_ERROR, "gauge", "Time a data source last failed."),
+    (SOURCE_UP, "gauge", "Whether a data source was read the last time it was tried."),
+    (START_TIME, "gauge", "Start time of the panel."),
+];
+
+#[derive(Debug, Default)]
+struct Registry {
+    counters: BTreeMap<(&'static str, String), u64>,
+    gauges: BTreeMap<(&'static str, String), f64>,
+    histograms: BTreeMap<(&'static str, String), Histogram>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Histogram {
+    // counts per bucket of `BUCKETS`, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// Adds one to a counter.
+pub(crate) fn count(name: &'static str, labels: &[(&str, &str)]) {
+    update(|registry| *registry.counters.entry((name, format_labels(labels))).or_default() += 1);
+}
+
+pub(crate) fn set_gauge(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    update(|registry| {
+        registry.gauges.insert((name, format_labels(labels)), value);
+    });
+}
+
+/// Records a duration in a histogram.
+pub(crate) fn observe(name: &'static str, labels: &[(&str, &str)], duration: Duration) {
+    let seconds = duration.as_secs_f64();
+    update(|registry| {
+        let histogram = registry.histograms.entry((name, format_labels(labels))).or_default();
+        if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    });
+}
+
+/// Records a cache lookup, `hit` if the kept result was used.
+pub(crate) fn count_cache_lookup(cache: &str, hit: bool) {
+    count(CACHE_LOOKUPS, &[("cache", cache), ("result", if hit { "hit" } else { "miss" })]);
+}
+
+/// All metrics in the text format of Prometheus, with the state of the sources and the start
+/// time of the panel.
+pub(crate) fn render(sources: &[(String, SourceHealth)], started: chrono::DateTime<chrono::Utc>) -> String {
+    let mut gauges: BTreeMap<(&'static str, String), f64> = match METRICS.get().map(|metrics| metrics.lock()) {
+        Some(Ok(registry)) => registry.gauges.clone(),
+        _default => BTreeMap::new(),
+    };
+    for (name, health) in sources {
+        let labels = format_labels(&[("source", name)]);
+        if let Some(last_success) = health.last_success {
+            gauges.insert((LAST_SUCCESS, labels.clone()), last_success.timestamp() as f64);
+        }
+        if let Some((last_error, _)) = &health.last_error {
+            gauges.insert((LAST_ERROR, labels.clone()), last_error.timestamp() as f64);
+        }
+        gauges.insert((SOURCE_UP, labels), if crate::health::is_failing(health) { 0.0 } else { 1.0 });
+    }
+    gauges.insert((START_TIME, String::new()), started.timestamp() as f64);
+
+    let mut text = String::new();
+    let registry = match METRICS.get().map(|metrics| metrics.lock()) {
+        Some(Ok(registry)) => Some(registry),
+        _default => None,
+    };
+
+    for (name, kind, help) in HELP {
+        let mut samples = String::new();
+        match kind {
+            "counter" => if let Some(registry) = &registry {
+                for ((_, labels), value) in registry.counters.range((name, String::new())..).take_while(|((metric, _), _)| *metric == name) {
+                    let _ = writeln!(samples, "{}{} {}", name, labels, value);
+                }
+            },
+            "histogram" => if let Some(registry) = &registry {
+                for ((_, labels), histogram) in registry.histograms.range((name, String::new())..).take_while(|((metric, _), _)| *metric == name) {
+                    write_histogram(&mut samples, name, labels, histogram);
+                }
+            },
+            _default => for ((_, labels), value) in gauges.range((name, String::new())..).take_while(|((metric, _), _)| *metric == name) {
+                let _ = writeln!(samples, "{}{} {}", name, labels, value);
+            },
+        }
+
+        if !samples.is_empty() {
+            let _ = writeln!(text, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+            text.push_str(&samples);
+        }
+    }
+    text
+}
+
+fn write_histogram(text: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    // the bucket label joins the others
+    let with_bound = |bound: &str| match labels.strip_suffix('}') {
+        Some(labels) => format!("{},le=\"{}\"}}", labels, bound),
+        None => format!("{{le=\"{}\"}}", bound),
+    };
+
+    let mut cumulative = 0;
+    for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+        cumulative += count;
+        let _ = writeln!(text, "{}_bucket{} {}", name, with_bound(&bound.to_string()), cumulative);
+    }
+    let _ = writeln!(text, "{}_bucket{} {}", name, with_bound("+Inf"), histogram.count);
+    let _ = writeln!(text, "{}_sum{} {}", name, labels, histogram.sum);
+    let _ = writeln!(text, "{}_count{} {}", name, labels, histogram.count);
+}
+
+// e.g. `{route="/",status="200"}`, empty without labels
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    let labels: Vec<String> = labels.iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+fn update(change: impl FnOnce(&mut Registry)) {
+    if let Ok(mut registry) = METRICS.get_or_init(|| Mutex::new(Registry::default())).lock() {
+        change(&mut registry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::health::SourceHealth;
+    use crate::metrics::{count, format_labels, HTTP_REQUEST_DURATION, HTTP_REQUESTS, observe, render, set_gauge, WEATHER_HUMIDITY};
+
+    #[test]
+    fn labels() {
+        assert_eq!(format_labels(&[]), "");
+        assert_eq!(format_labels(&[("route", "/"), ("status", "200")]), "{route=\"/\",status=\"200\"}");
+        assert_eq!(format_labels(&[("source", "feed_\"A\"\\B")]), "{source=\"feed_\\\"A\\\"\\\\B\"}");
+    }
+
+    #[test]
+    fn text_format() {
+        count(HTTP_REQUESTS, &[("route", "/metrics_test"), ("method", "GET"), ("status", "200")]);
+        count(HTTP_REQUESTS, &[("route", "/metrics_test"), ("method", "GET"), ("status", "200")]);
+        observe(HTTP_REQUEST_DURATION, &[("route", "/metrics_test")], Duration::from_millis(30));
+        observe(HTTP_REQUEST_DURATION, &[("route", "/metrics_test")], Duration::from_secs(20));
+        set_gauge(WEATHER_HUMIDITY, &[("location", "Nastätten")], 81.0);
+
+        let started = Utc.with_ymd_and_hms(2023, 10, 12, 12, 0, 0).unwrap();
+        let source = SourceHealth { last_success: Some(started), ..SourceHealth::default() };
+        let text = render(&[("weather_current".to_string(), source)], started);
+
+        assert!(text.contains("# TYPE infopanel_http_requests_total counter\n"));
+        assert!(text.contains("infopanel_http_requests_total{route=\"/metrics_test\",method=\"GET\",status=\"200\"} 2\n"));
+        assert!(text.contains("infopanel_http_request_duration_seconds_bucket{route=\"/metrics_test\",le=\"0.025\"} 0\n"));
+        assert!(text.contains("infopanel_http_request_duration_seconds_bucket{route=\"/metrics_test\",le=\"0.05\"} 1\n"));
+        assert!(text.contains("infopanel_http_request_duration_seconds_bucket{route=\"/metrics_test\",le=\"+Inf\"} 2\n"));
+        assert!(text.contains("infopanel_http_request_duration_seconds_count{route=\"/metrics_test\"} 2\n"));
+        assert!(text.contains("infopanel_weather_humidity_percent{location=\"Nastätten\"} 81\n"));
+        assert!(text.contains("infopanel_source_last_success_timestamp_seconds{source=\"weather_current\"} 1697112000\n"));
+        assert!(text.contains("infopanel_source_up{source=\"weather_current\"} 1\n"));
+        assert!(text.contains("infopanel_start_time_seconds 1697112000\n"));
+    }
+}
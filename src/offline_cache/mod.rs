@@ -0,0 +1,178 @@
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use openssl::sha::sha256;
+
+use crate::filesystem::FileSystemHandler;
+use crate::health;
+use crate::metrics;
+
+/// The last successful result of every data source, kept in `~/.InfoPanel/cache` so the panel
+/// keeps showing something when the network or a server is down.
+///
+/// A source whose last request failed is marked as offline with the time of its first failure,
+/// which survives restarts.
+#[derive(Debug, Clone)]
+pub struct OfflineCache {
+    directory: String,
+}
+
+// the cache in the home directory, opened once at the start
+static SHARED: OnceLock<OfflineCache> = OnceLock::new();
+
+impl OfflineCache {
+    pub(crate) fn new(directory: &str) -> Result<Self, String> {
+        fs::create_dir_all(directory).map_err(|e| format!("Failed to create '{}': {}", directory, e))?;
+        Ok(OfflineCache { directory: directory.to_string() })
+    }
+
+    /// Opens the cache in the home directory of the software, once at the start.
+    pub(crate) fn open() -> Result<(), String> {
+        let filesystem_handler = FileSystemHandler::new()?;
+        let directory = filesystem_handler.create_directory("cache")?;
+        let _ = SHARED.set(OfflineCache::new(&directory)?);
+        Ok(())
+    }
+
+    /// The cache opened at the start, `None` without one.
+    pub(crate) fn shared() -> Option<&'static Self> {
+        SHARED.get()
+    }
+
+    /// Keeps `result` if the source could be read, otherwise falls back to the last result
+    /// kept for `key`.
+    pub(crate) fn keep_or_load(&self, key: &str, result: Result<String, String>) -> Result<String, String> {
+        health::record(key, &result);
+        match result {
+            Ok(content) => {
+                if let Err(e) = fs::write(self.path(key, "cache"), &content) {
+                    warn!("Couldn't keep '{}' in '{}': {}", key, self.directory, e);
+                }
+                self.mark_online(key);
+                Ok(content)
+            },
+            Err(e) => {
+                self.mark_offline(key);
+                let loaded = self.load(key);
+                metrics::count_cache_lookup("offline", loaded.is_some());
+                match loaded {
+                    Some(content) => {
+                        warn!("{}, using the last result of '{}'", e, key);
+                        health::record_cached(key, self.updated(key));
+                        Ok(content)
+                    },
+                    None => Err(e),
+                }
+            },
+        }
+    }
+
+    /// The last result kept for `key`, without touching the state of the source.
+    pub(crate) fn load(&self, key: &str) -> Option<String> {
+        fs::read_to_string(self.path(key, "cache")).ok()
+    }
+
+    /// When the last result of `key` was kept.
+    pub(crate) fn updated(&self, key: &str) -> Option<DateTime<Utc>> {
+        let modified = fs::metadata(self.path(key, "cache")).and_then(|metadata| metadata.modified()).ok()?;
+        Some(DateTime::<Utc>::from(modified))
+    }
+
+    /// Marks a source as unreachable, unless it already is.
+    pub(crate) fn mark_offline(&self, key: &str) {
+        let path = self.path(key, "offline");
+        if Path::new(&path).exists() {
+            return;
+        }
+
+        info!("'{}' is offline", key);
+        if let Err(e) = fs::write(&path, Utc::now().to_rfc3339()) {
+            warn!("Couldn't mark '{}' as offline: {}", key, e);
+        }
+    }
+
+    pub(crate) fn mark_online(&self, key: &str) {
+        let path = self.path(key, "offline");
+        if fs::remove_file(&path).is_ok() {
+            info!("'{}' is online again", key);
+        }
+    }
+
+    /// The first failure of the sources that are offline, `None` if all are online.
+    pub(crate) fn offline_since(&self) -> Option<DateTime<Utc>> {
+        fs::read_dir(&self.directory).ok()?
+            .map_while(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == "offline"))
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|since| DateTime::parse_from_rfc3339(since.trim()).ok())
+            .map(|since| since.with_timezone(&Utc))
+            .min()
+    }
+
+    fn path(&self, key: &str, extension: &str) -> String {
+        format!("{}/{}.{}", self.directory, file_name(key), extension)
+    }
+}
+
+/// A file name for a key or name. Names with characters replaced get a hash of the name,
+/// "Müll/Bio" and "Müll Bio" must not share files.
+pub(crate) fn file_name(name: &str) -> String {
+    let is_kept = |character: char| character.is_alphanumeric() || character == '-' || character == '_';
+    let replaced: String = name.chars()
+        .map(|character| if is_kept(character) { character } else { '_' })
+        .collect();
+    if name.chars().all(is_kept) && !name.is_empty() {
+        return replaced;
+    }
+
+    let hash: String = sha256(name.as_bytes())[..4].iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}_{}", replaced, hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::offline_cache::{file_name, OfflineCache};
+
+    #[test]
+    fn fallback_to_last_result() {
+        let directory = std::env::temp_dir().join(format!("info_panel_offline_cache_{}", std::process::id()));
+        let cache = OfflineCache::new(directory.to_str().unwrap()).unwrap();
+
+        // nothing to fall back to yet
+        assert_eq!(cache.keep_or_load("weather", Err("unreachable".to_string())), Err("unreachable".to_string()));
+        let since = cache.offline_since().unwrap();
+
+        assert_eq!(cache.keep_or_load("weather", Ok("{\"temp\": 12}".to_string())), Ok("{\"temp\": 12}".to_string()));
+        assert_eq!(cache.offline_since(), None);
+
+        assert_eq!(cache.keep_or_load("weather", Err("unreachable".to_string())), Ok("{\"temp\": 12}".to_string()));
+        assert_eq!(cache.keep_or_load("calendar/family", Err("unreachable".to_string())), Err("unreachable".to_string()));
+        // the first failure counts
+        assert!(cache.offline_since().unwrap() >= since);
+        let first = cache.offline_since().unwrap();
+        cache.mark_offline("weather");
+        assert_eq!(cache.offline_since(), Some(first));
+
+        cache.mark_online("weather");
+        cache.mark_online("calendar/family");
+        assert_eq!(cache.offline_since(), None);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(file_name("weather_current"), "weather_current");
+        assert_eq!(file_name("calendar_Geburtstage-Familie"), "calendar_Geburtstage-Familie");
+        assert_eq!(file_name("address_book_Büro"), "address_book_Büro");
+        assert_ne!(file_name("calendar_Müll Bio"), file_name("calendar_Müll/Bio"));
+        assert!(file_name("calendar_Müll/Bio").starts_with("calendar_Müll_Bio_"));
+        assert!(!file_name("feed_../secret").contains('/'));
+        assert_eq!(file_name("").len(), 9);
+    }
+}
@@ -1,6 +1,6 @@
 use chrono::NaiveDateTime;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WeatherEntry {
     pub city: String,
     pub timezone: i64,
@@ -46,7 +46,7 @@ impl PartialEq for WeatherEntry {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Main {
     pub temp: f32,
     pub feels_like: f32,
@@ -86,7 +86,7 @@ impl PartialEq for Main {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Weather {
     pub main: String,
     pub description: String,
@@ -111,7 +111,7 @@ impl PartialEq for Weather {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Rain {
     pub hour_1: f32,
     pub hour_3: f32
@@ -132,7 +132,7 @@ impl PartialEq for Rain {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Clouds {
     pub cloudiness: u8
 }
@@ -149,7 +149,7 @@ impl PartialEq for Clouds {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Wind {
     pub speed: f32,
     pub direction_deg: i16,
@@ -174,7 +174,7 @@ impl PartialEq for Wind {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Sys {
     pub part_of_day: char,
     pub country: String,
@@ -0,0 +1,306 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::NaiveDateTime;
+use log::{debug, warn};
+
+use crate::locale::Locale;
+
+/// Directory of the profile configs, one `<name>.conf` per profile.
+pub(crate) const PROFILE_DIRECTORY: &str = "data/profiles";
+
+// numbers the files of screenshots
+static SCREENSHOTS: AtomicU64 = AtomicU64::new(0);
+
+/// A panel served at `/p/<name>`, read from `data/profiles/<name>.conf`. The panel at `/` is
+/// the profile `default`. All profiles share the same data sources and caches.
+///
+/// - `resolution=<width>x<height>` of the display, 800x480 if missing
+/// - `layout=<path>` of the layout config, `data/layout.conf` if missing
+/// - `theme=<name>` of the look: `light`, `dark`, `dimmed`, `eink` or one of styles.css
+/// - `night_theme=<name>` shown between sunset and sunrise
+/// - `icons=<set>` of the weather icons below `icons/`, `colour` or `mono`, empty follows the theme
+/// - `calendars=<calendar>,<calendar>` of the CalDAV server, replacing those of the agenda config
+/// - `locale=de` or `locale=en`
+/// - `format=html` or `format=png`, what `/p/<name>` serves
+/// - `renderer=<command>` taking a screenshot for PNG, with `{input}`, `{output}`, `{width}` and
+///   `{height}` replaced by the HTML file, the PNG file and the resolution
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub layout: String,
+    /// Empty for the default look.
+    pub theme: String,
+    /// Empty to keep the theme at night.
+    pub night_theme: String,
+    /// Empty for the icons of the theme.
+    pub icons: String,
+    /// None shows the calendars of the agenda config.
+    pub calendars: Option<Vec<String>>,
+    pub locale: Locale,
+    pub format: Format,
+    /// The command and its arguments, empty if the profile can't be served as PNG.
+    pub renderer: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Html,
+    Png,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            name: String::new(),
+            width: 800,
+            height: 480,
+            layout: "data/layout.conf".to_string(),
+            theme: String::new(),
+            night_theme: String::new(),
+            icons: String::new(),
+            calendars: None,
+            locale: Locale::default(),
+            format: Format::Html,
+            renderer: vec![],
+        }
+    }
+}
+
+impl Profile {
+    pub(crate) fn new(name: &str, path_config: &str) -> Result<Self, String> {
+        let file = File::open(path_config).map_err(|e| {
+            format!("Failed to open '{}': {}", path_config, e)
+        })?;
+
+        let mut profile = Profile { name: name.to_string(), ..Profile::default() };
+        let reader = BufReader::new(file);
+        for line_result in reader.lines() {
+            let line = line_result.map_err(|e| {
+                format!("Error reading '{}': {}", path_config, e)
+            })?;
+
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim();
+                let invalid = |expected: &str| format!("Expected '{}' in '{}', got '{}'", expected, path_config, line);
+
+                match key.trim() {
+                    "resolution" => {
+                        let (width, height) = value.split_once('x')
+                            .and_then(|(width, height)| width.trim().parse::<u32>().ok().zip(height.trim().parse::<u32>().ok()))
+                            .filter(|(width, height)| *width > 0 && *height > 0)
+                            .ok_or_else(|| invalid("resolution=<width>x<height>"))?;
+                        profile.width = width;
+                        profile.height = height;
+                    },
+                    "layout" if !value.is_empty() => profile.layout = value.to_string(),
+                    "theme" => profile.theme = value.to_string(),
+                    "night_theme" => profile.night_theme = value.to_string(),
+                    "icons" if value.chars().all(|character| character.is_ascii_alphanumeric() || character == '-' || character == '_') => {
+                        profile.icons = value.to_string()
+                    },
+                    "icons" => return Err(invalid("icons=<set> of letters, digits, '-' and '_'")),
+                    "calendars" => profile.calendars = Some(value.split(',')
+                        .map(|calendar| calendar.trim().to_string())
+                        .filter(|calendar| !calendar.is_empty())
+                        .collect()),
+                    "locale" => profile.locale = Locale::new(value).map_err(|e| format!("{} in '{}'", e, path_config))?,
+                    "format" => profile.format = match value {
+                        "html" => Format::Html,
+                        "png" => Format::Png,
+                        _default => return Err(invalid("format=html or format=png")),
+                    },
+                    "renderer" => profile.renderer = value.split_whitespace().map(str::to_string).collect(),
+                    _default => (),
+                }
+            }
+        }
+
+        if profile.format == Format::Png && profile.renderer.is_empty() {
+            return Err(format!("Expected a 'renderer' for PNG in '{}'", path_config));
+        }
+        Ok(profile)
+    }
+
+    /// The profile of that name in `directory`. Names are letters, digits, `-` and `_`.
+    pub(crate) fn read(directory: &str, name: &str) -> Result<Self, String> {
+        if name.is_empty() || !name.chars().all(|character| character.is_ascii_alphanumeric() || character == '-' || character == '_') {
+            return Err(format!("Invalid profile name '{}'", name));
+        }
+        Profile::new(name, &format!("{}/{}.conf", directory, name))
+    }
+
+    /// Every profile of `directory`, by name. Profiles that can't be read are left out.
+    pub(crate) fn read_all(directory: &str) -> Vec<Self> {
+        let entries = match fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("No profiles in '{}': {}", directory, e);
+                return vec![];
+            }
+        };
+
+        let mut names: Vec<String> = entries.map_while(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "conf"))
+            .filter_map(|path| path.file_stem().and_then(|name| name.to_str()).map(str::to_string))
+            .collect();
+        names.sort();
+
+        names.iter()
+            .filter_map(|name| match Profile::read(directory, name) {
+                Ok(profile) => Some(profile),
+                Err(e) => {
+                    warn!("Skipping profile '{}': {}", name, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The theme shown at `now`, the night theme between `sunset` and `sunrise` of the day.
+    /// Times are UTC, without them the day theme is shown.
+    pub(crate) fn theme_at(&self, now: NaiveDateTime, sun: Option<(NaiveDateTime, NaiveDateTime)>) -> &str {
+        match sun {
+            // unknown times of the weather are zero
+            Some((sunrise, sunset)) if !self.night_theme.is_empty() && sunrise < sunset && (now < sunrise || now >= sunset) => &self.night_theme,
+            _default => &self.theme,
+        }
+    }
+
+    /// The set of weather icons shown with `theme`: the one of the profile, monochrome icons for
+    /// e-ink or coloured ones.
+    pub(crate) fn icon_set(&self, theme: &str) -> &str {
+        match (self.icons.as_str(), theme) {
+            ("", "eink") => "mono",
+            ("", _) => "colour",
+            (icons, _) => icons,
+        }
+    }
+
+    /// The profile's PNG of a rendered panel, taken by its renderer. The files of every
+    /// screenshot are written to `directory` and removed afterwards, assets of the panel are
+    /// loaded from `base_url`.
+    pub(crate) fn screenshot(&self, html: &str, base_url: &str, directory: &str) -> Result<Vec<u8>, String> {
+        let (program, arguments) = self.renderer.split_first()
+            .ok_or_else(|| format!("No renderer for PNG in profile '{}'", self.name))?;
+
+        // requests of the same profile at the same time mustn't share files
+        let file = format!("{}/{}_{}_{}", directory, self.name, std::process::id(), SCREENSHOTS.fetch_add(1, Ordering::Relaxed));
+        let input = format!("{}.html", file);
+        let output = format!("{}.png", file);
+
+        let result = self.run_renderer(program, arguments, html, base_url, &input, &output);
+        let _ = fs::remove_file(&input);
+        let _ = fs::remove_file(&output);
+        result
+    }
+
+    fn run_renderer(&self, program: &str, arguments: &[String], html: &str, base_url: &str, input: &str, output: &str) -> Result<Vec<u8>, String> {
+        // links of the panel are relative to the server
+        let html = html.replacen("<head>", &format!("<head>\n        <base href=\"{}/\">", base_url), 1);
+        fs::write(input, html).map_err(|e| format!("Failed to write '{}': {}", input, e))?;
+
+        let replace = |argument: &String| argument
+            .replace("{input}", input)
+            .replace("{output}", output)
+            .replace("{width}", &self.width.to_string())
+            .replace("{height}", &self.height.to_string());
+        let result = Command::new(program)
+            .args(arguments.iter().map(replace))
+            .output()
+            .map_err(|e| format!("Failed to run renderer '{}': {}", program, e))?;
+
+        if !result.status.success() {
+            return Err(format!("Renderer '{}' failed with {}: {}", program, result.status, String::from_utf8_lossy(&result.stderr).trim()));
+        }
+        fs::read(output).map_err(|e| format!("Renderer '{}' didn't write '{}': {}", program, output, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    use crate::locale::Locale;
+    use crate::profile::{Format, Profile};
+
+    #[test]
+    fn new() {
+        let profile = Profile::read("data/test/profiles", "kitchen").unwrap();
+
+        assert_eq!((profile.width, profile.height), (1280, 800));
+        assert_eq!(profile.layout, "data/test/layout_test.conf");
+        assert_eq!(profile.theme, "light");
+        assert_eq!(profile.night_theme, "dimmed");
+        assert_eq!(profile.calendars, Some(vec!["family".to_string(), "school".to_string()]));
+        assert_eq!(profile.locale, Locale::English);
+        assert_eq!(profile.format, Format::Html);
+
+        let hallway = Profile::read("data/test/profiles", "hallway").unwrap();
+        assert_eq!(hallway.layout, "data/layout.conf");
+        assert_eq!(hallway.format, Format::Png);
+        assert_eq!(hallway.renderer, vec!["cp", "{input}", "{output}"]);
+
+        assert!(Profile::read("data/test/profiles", "../server_test").is_err());
+        assert!(Profile::read("data/test/profiles", "missing").is_err());
+
+        let names: Vec<String> = Profile::read_all("data/test/profiles").into_iter().map(|profile| profile.name).collect();
+        assert_eq!(names, vec!["hallway", "kitchen"]);
+        assert!(Profile::read_all("data/test/missing").is_empty());
+    }
+
+    #[test]
+    fn night_theme() {
+        let profile = Profile::read("data/test/profiles", "kitchen").unwrap();
+        let at = |hour| NaiveDate::from_ymd_opt(2023, 10, 12).unwrap().and_hms_opt(hour, 0, 0).unwrap();
+        let sun = Some((at(5), at(16)));
+
+        assert_eq!(profile.theme_at(at(12), sun), "light");
+        assert_eq!(profile.theme_at(at(16), sun), "dimmed");
+        assert_eq!(profile.theme_at(at(2), sun), "dimmed");
+        assert_eq!(profile.theme_at(at(2), None), "light");
+        assert_eq!(profile.theme_at(at(2), Some((NaiveDateTime::default(), NaiveDateTime::default()))), "light");
+        assert_eq!(Profile::default().theme_at(at(2), sun), "");
+    }
+
+    #[test]
+    fn icon_set() {
+        let profile = Profile::read("data/test/profiles", "kitchen").unwrap();
+        assert_eq!(profile.icon_set("light"), "colour");
+        assert_eq!(profile.icon_set("eink"), "mono");
+
+        let hallway = Profile::read("data/test/profiles", "hallway").unwrap();
+        assert_eq!(hallway.icon_set("light"), "mono");
+    }
+
+    #[test]
+    fn screenshot() {
+        let directory = std::env::temp_dir().join(format!("info_panel_render_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let directory = directory.display().to_string();
+
+        // copying the HTML stands in for a browser
+        let hallway = Profile::read("data/test/profiles", "hallway").unwrap();
+        let png = hallway.screenshot("<html><head></head></html>", "http://127.0.0.1:3000", &directory).unwrap();
+        assert_eq!(String::from_utf8(png).unwrap(), "<html><head>\n        <base href=\"http://127.0.0.1:3000/\"></head></html>");
+
+        let broken = Profile { renderer: vec!["false".to_string()], ..hallway };
+        assert!(broken.screenshot("<html></html>", "http://127.0.0.1:3000", &directory).is_err());
+        assert!(Profile::default().screenshot("<html></html>", "http://127.0.0.1:3000", &directory).is_err());
+        // nothing is left behind
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
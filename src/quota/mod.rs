@@ -0,0 +1,306 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use log::{info, warn};
+
+// share of the daily budget after which refreshes are spread over the rest of the day
+const STRETCH_THRESHOLD_PERCENT: u32 = 80;
+
+/// Call limits of an API provider and how often its data is refreshed, read from the
+/// provider's config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Budget {
+    pub calls_per_minute: Option<u32>,
+    pub calls_per_day: Option<u32>,
+    /// Interval between refreshes while the budget lasts.
+    pub refresh: Duration,
+    /// Calls made by one refresh.
+    pub calls_per_refresh: u32,
+}
+
+/// Counts the calls made to a provider and decides when its data may be refreshed. The counts
+/// are kept in `~/.InfoPanel/quota` so a restart doesn't reset them.
+#[derive(Debug)]
+pub struct QuotaTracker {
+    pub provider: String,
+    pub budget: Budget,
+    path: Option<String>,
+    usage: Usage,
+}
+
+// provider's days are UTC days
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Usage {
+    day: Option<NaiveDate>,
+    day_calls: u32,
+    minute: i64,
+    minute_calls: u32,
+    last_refresh: Option<DateTime<Utc>>,
+}
+
+impl Budget {
+    /// No limits, refreshing every ten minutes.
+    pub(crate) fn unlimited(calls_per_refresh: u32) -> Self {
+        Budget { calls_per_minute: None, calls_per_day: None, refresh: Duration::from_secs(600), calls_per_refresh }
+    }
+
+    /// Reads `calls_per_minute`, `calls_per_day` and `refresh` (in seconds) of a config. Missing
+    /// limits mean no limit.
+    pub(crate) fn new(path_config: &str, calls_per_refresh: u32) -> Result<Self, String> {
+        let file = File::open(path_config).map_err(|e| {
+            format!("Failed to open '{}': {}", path_config, e)
+        })?;
+
+        let mut budget = Budget::unlimited(calls_per_refresh);
+        let reader = BufReader::new(file);
+        for line_result in reader.lines() {
+            let line = line_result.map_err(|e| {
+                format!("Error reading '{}': {}", path_config, e)
+            })?;
+
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once('=') {
+                let number = || value.trim().parse::<u32>().map_err(|e| {
+                    format!("Invalid value '{}' of '{}' in '{}': {}", value, key, path_config, e)
+                });
+                match key.trim() {
+                    "calls_per_minute" => budget.calls_per_minute = Some(number()?),
+                    "calls_per_day" => budget.calls_per_day = Some(number()?),
+                    "refresh" => budget.refresh = Duration::from_secs(number()? as u64),
+                    _default => (),
+                }
+            }
+        }
+        Ok(budget)
+    }
+}
+
+impl QuotaTracker {
+    /// A tracker continuing the counts kept in `directory`, if any.
+    pub(crate) fn new(provider: &str, budget: Budget, directory: Option<&str>) -> Self {
+        let path = directory.map(|directory| format!("{}/{}.usage", directory, provider));
+        let usage = path.as_deref().map(read_usage).unwrap_or_default();
+
+        QuotaTracker { provider: provider.to_string(), budget, path, usage }
+    }
+
+    /// Whether the data is old enough to be refreshed and the budget allows a refresh.
+    pub(crate) fn is_due(&self, now: DateTime<Utc>) -> bool {
+        let old_enough = match self.usage.last_refresh {
+            Some(last_refresh) => (now - last_refresh).to_std().unwrap_or(Duration::ZERO) >= self.refresh_interval(now),
+            None => true,
+        };
+        old_enough && self.may_refresh(now)
+    }
+
+    /// Whether the budget allows a refresh, no matter how old the data is.
+    pub(crate) fn may_refresh(&self, now: DateTime<Utc>) -> bool {
+        self.remaining_minute(now).is_none_or(|remaining| remaining >= self.budget.calls_per_refresh)
+            && self.remaining_day(now).is_none_or(|remaining| remaining >= self.budget.calls_per_refresh)
+    }
+
+    /// The refresh interval of the budget, stretched once most of the daily budget is used so the
+    /// rest lasts until the end of the day.
+    pub(crate) fn refresh_interval(&self, now: DateTime<Utc>) -> Duration {
+        let (calls_per_day, remaining) = match (self.budget.calls_per_day, self.remaining_day(now)) {
+            (Some(calls_per_day), Some(remaining)) => (calls_per_day, remaining),
+            _default => return self.budget.refresh,
+        };
+        if self.calls_today(now) * 100 < calls_per_day * STRETCH_THRESHOLD_PERCENT {
+            return self.budget.refresh;
+        }
+
+        let end_of_day = (now.date_naive() + chrono::Duration::days(1)).and_hms_opt(0, 0, 0).unwrap().and_utc();
+        let rest_of_day = (end_of_day - now).to_std().unwrap_or(Duration::ZERO);
+
+        let refreshes_left = remaining / self.budget.calls_per_refresh.max(1);
+        if refreshes_left == 0 {
+            return rest_of_day.max(self.budget.refresh);
+        }
+        (rest_of_day / refreshes_left).max(self.budget.refresh)
+    }
+
+    /// Reserves the calls of a refresh if one is due, or if `needs_data` and the budget allows it.
+    /// The calls and the refresh are counted before the requests are made, so refreshes started
+    /// at the same time can't both use the budget.
+    pub(crate) fn try_reserve(&mut self, now: DateTime<Utc>, needs_data: bool) -> bool {
+        let allowed = if needs_data { self.may_refresh(now) } else { self.is_due(now) };
+        if !allowed {
+            return false;
+        }
+
+        for _ in 0..self.budget.calls_per_refresh {
+            self.record_call(now);
+        }
+        self.record_refresh(now);
+        true
+    }
+
+    /// Counts a call and keeps the counts on disk.
+    pub(crate) fn record_call(&mut self, now: DateTime<Utc>) {
+        self.roll_over(now);
+        self.usage.day_calls += 1;
+        self.usage.minute_calls += 1;
+
+        if self.budget.calls_per_day.is_some_and(|calls_per_day| self.usage.day_calls * 100 == calls_per_day * STRETCH_THRESHOLD_PERCENT) {
+            info!("{}% of the daily budget of '{}' used, refreshing less often", STRETCH_THRESHOLD_PERCENT, self.provider);
+        }
+        self.write();
+    }
+
+    pub(crate) fn record_refresh(&mut self, now: DateTime<Utc>) {
+        self.usage.last_refresh = Some(now);
+        self.write();
+    }
+
+    pub(crate) fn calls_today(&self, now: DateTime<Utc>) -> u32 {
+        if self.usage.day == Some(now.date_naive()) { self.usage.day_calls } else { 0 }
+    }
+
+    pub(crate) fn calls_this_minute(&self, now: DateTime<Utc>) -> u32 {
+        if self.usage.minute == minute_of(now) { self.usage.minute_calls } else { 0 }
+    }
+
+    pub(crate) fn last_refresh(&self) -> Option<DateTime<Utc>> {
+        self.usage.last_refresh
+    }
+
+    fn remaining_day(&self, now: DateTime<Utc>) -> Option<u32> {
+        self.budget.calls_per_day.map(|calls_per_day| calls_per_day.saturating_sub(self.calls_today(now)))
+    }
+
+    fn remaining_minute(&self, now: DateTime<Utc>) -> Option<u32> {
+        self.budget.calls_per_minute.map(|calls_per_minute| calls_per_minute.saturating_sub(self.calls_this_minute(now)))
+    }
+
+    // starts new counts for a new day or minute
+    fn roll_over(&mut self, now: DateTime<Utc>) {
+        if self.usage.day != Some(now.date_naive()) {
+            self.usage.day = Some(now.date_naive());
+            self.usage.day_calls = 0;
+        }
+        if self.usage.minute != minute_of(now) {
+            self.usage.minute = minute_of(now);
+            self.usage.minute_calls = 0;
+        }
+    }
+
+    fn write(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let usage = format!("day={}\nday_calls={}\nminute={}\nminute_calls={}\nlast_refresh={}\n",
+                            self.usage.day.map(|day| day.to_string()).unwrap_or_default(),
+                            self.usage.day_calls,
+                            self.usage.minute,
+                            self.usage.minute_calls,
+                            self.usage.last_refresh.map(|last_refresh| last_refresh.to_rfc3339()).unwrap_or_default());
+        if let Err(e) = fs::write(path, usage) {
+            warn!("Couldn't keep the calls to '{}' in '{}': {}", self.provider, path, e);
+        }
+    }
+}
+
+fn minute_of(time: DateTime<Utc>) -> i64 {
+    time.timestamp() / 60
+}
+
+fn read_usage(path: &str) -> Usage {
+    let mut usage = Usage::default();
+
+    if let Ok(file) = File::open(path) {
+        let reader = BufReader::new(file);
+        for line in reader.lines().map_while(Result::ok) {
+            match line.split_once('=') {
+                Some(("day", value)) => usage.day = value.parse::<NaiveDate>().ok(),
+                Some(("day_calls", value)) => usage.day_calls = value.parse::<u32>().unwrap_or_default(),
+                Some(("minute", value)) => usage.minute = value.parse::<i64>().unwrap_or_default(),
+                Some(("minute_calls", value)) => usage.minute_calls = value.parse::<u32>().unwrap_or_default(),
+                Some(("last_refresh", value)) => usage.last_refresh = DateTime::parse_from_rfc3339(value).ok()
+                    .map(|last_refresh| last_refresh.with_timezone(&Utc)),
+                _default => (),
+            }
+        }
+    }
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::quota::{Budget, QuotaTracker};
+
+    #[test]
+    fn budget_config() {
+        let budget = Budget::new("data/test/openweathermap_test.conf", 2).unwrap();
+
+        assert_eq!(budget, Budget {
+            calls_per_minute: Some(4),
+            calls_per_day: Some(100),
+            refresh: Duration::from_secs(600),
+            calls_per_refresh: 2,
+        });
+    }
+
+    #[test]
+    fn stretched_refreshes() {
+        let directory = std::env::temp_dir().join(format!("info_panel_quota_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let budget = Budget { calls_per_minute: Some(4), calls_per_day: Some(100), refresh: Duration::from_secs(600), calls_per_refresh: 2 };
+
+        let mut tracker = QuotaTracker::new("openweather", budget.clone(), directory.to_str());
+        let now = Utc.with_ymd_and_hms(2023, 10, 12, 12, 0, 0).unwrap();
+        assert!(tracker.is_due(now));
+
+        for _ in 0..4 {
+            tracker.record_call(now);
+        }
+        tracker.record_refresh(now);
+        // not due yet and the minute's budget is used up
+        assert!(!tracker.is_due(now + chrono::Duration::seconds(30)));
+        assert!(tracker.is_due(now + chrono::Duration::minutes(10)));
+
+        // counts survive a restart
+        let mut tracker = QuotaTracker::new("openweather", budget.clone(), directory.to_str());
+        assert_eq!(tracker.calls_today(now), 4);
+        assert_eq!(tracker.calls_this_minute(now + chrono::Duration::minutes(1)), 0);
+
+        // 80 of 100 calls used, the remaining 10 refreshes are spread over the last 12 hours
+        for _ in 0..76 {
+            tracker.record_call(now);
+        }
+        assert_eq!(tracker.refresh_interval(now), Duration::from_secs(12 * 60 * 6));
+        // a new day starts with the full budget
+        assert_eq!(tracker.calls_today(now + chrono::Duration::days(1)), 0);
+        assert_eq!(tracker.refresh_interval(now + chrono::Duration::days(1)), Duration::from_secs(600));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn reserved_refreshes() {
+        let budget = Budget { calls_per_minute: Some(4), calls_per_day: Some(100), refresh: Duration::from_secs(600), calls_per_refresh: 2 };
+        let mut tracker = QuotaTracker::new("openweather", budget, None);
+        let now = Utc.with_ymd_and_hms(2023, 10, 12, 12, 0, 0).unwrap();
+
+        assert!(tracker.try_reserve(now, false));
+        assert_eq!(tracker.calls_this_minute(now), 2);
+        // a second refresh started at the same time isn't due anymore
+        assert!(!tracker.try_reserve(now, false));
+        // without data the rest of the minute's budget may be used, but no more
+        assert!(tracker.try_reserve(now, true));
+        assert!(!tracker.try_reserve(now, true));
+        assert_eq!(tracker.calls_this_minute(now), 4);
+    }
+}
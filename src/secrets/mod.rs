@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fs;
+use std::process::Command;
+use std::sync::{Mutex, OnceLock};
+
+use log::{Log, Metadata, Record, warn};
+
+// values of secrets read so far, removed from every log message
+static SECRETS: OnceLock<Mutex<Vec<String>>> = OnceLock::new();
+// values of the references resolved so far, e.g. of "command:pass show dav"
+static RESOLVED: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+
+// names of parameters whose values are secrets, e.g. in "?appid=…" or "password=…"
+const SECRET_PARAMETERS: [&str; 10] = [
+    "appid", "api_key", "apikey", "key", "token", "access_token", "refresh_token", "password", "client_secret", "secret",
+];
+
+// credentials of Authorization headers, e.g. "Bearer …"
+const AUTHORIZATION_SCHEMES: [&str; 2] = ["Bearer ", "Authorization: Basic "];
+
+// shorter secrets would redact ordinary words
+const MIN_SECRET_LENGTH: usize = 4;
+
+/// Shown instead of a secret.
+pub(crate) const REDACTED: &str = "***";
+
+/// Reads the value of a secret in a config.
+///
+/// - `env:<name>` reads an environment variable
+/// - `file:<path>` reads a file, which should only be readable by its owner
+/// - `credential:<name>` reads a file of systemd's `$CREDENTIALS_DIRECTORY`
+/// - `command:<command line>` runs a command with `sh -c` and reads its output, e.g. of `pass`
+///
+/// Anything else is the secret itself. Every secret read is redacted from the log. References
+/// are only resolved once, later configs reuse the value, so a command isn't run again.
+pub(crate) fn resolve(value: &str) -> Result<String, String> {
+    let value = value.trim();
+
+    let resolved = RESOLVED.get_or_init(|| Mutex::new(HashMap::new()));
+    if let Some(secret) = resolved.lock().ok().and_then(|resolved| resolved.get(value).cloned()) {
+        return Ok(secret);
+    }
+
+    let secret = if let Some(name) = value.strip_prefix("env:") {
+        std::env::var(name).map_err(|e| format!("Failed to read environment variable '{}': {}", name, e))?
+    } else if let Some(path) = value.strip_prefix("file:") {
+        read_secret_file(path)?
+    } else if let Some(name) = value.strip_prefix("credential:") {
+        let directory = std::env::var("CREDENTIALS_DIRECTORY")
+            .map_err(|_| format!("Credential '{}' requested, but no $CREDENTIALS_DIRECTORY is set", name))?;
+        read_secret_file(&format!("{}/{}", directory, name))?
+    } else if let Some(command_line) = value.strip_prefix("command:") {
+        run_secret_command(command_line)?
+    } else {
+        register(value);
+        return Ok(value.to_string());
+    };
+
+    register(&secret);
+    if let Ok(mut resolved) = resolved.lock() {
+        resolved.insert(value.to_string(), secret.clone());
+    }
+    Ok(secret)
+}
+
+/// Redacts a secret from every following log message.
+pub(crate) fn register(secret: &str) {
+    if secret.len() < MIN_SECRET_LENGTH {
+        return;
+    }
+
+    if let Ok(mut secrets) = SECRETS.get_or_init(|| Mutex::new(Vec::new())).lock() {
+        if !secrets.iter().any(|known| known == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+}
+
+/// Replaces the secrets read so far, values of secret parameters like `appid=` and credentials
+/// of Authorization headers with `***`.
+pub(crate) fn redact(text: &str) -> String {
+    let mut redacted = text.to_string();
+
+    if let Some(Ok(secrets)) = SECRETS.get().map(|secrets| secrets.lock()) {
+        for secret in secrets.iter() {
+            redacted = redacted.replace(secret.as_str(), REDACTED);
+        }
+    }
+
+    for parameter in SECRET_PARAMETERS {
+        redacted = redact_values(&redacted, &format!("{}=", parameter));
+    }
+    for scheme in AUTHORIZATION_SCHEMES {
+        redacted = redact_values(&redacted, scheme);
+    }
+    redacted
+}
+
+/// Warns about configs other users may read, they hold passwords and keys.
+pub(crate) fn warn_if_world_readable(paths: &[&str]) {
+    for path in paths {
+        if let Ok(metadata) = fs::metadata(path) {
+            if is_world_readable(&metadata) {
+                warn!("'{}' is readable by every user of this system, restrict it with 'chmod 600 {}'", path, path);
+            }
+        }
+    }
+}
+
+/// Passes log records on with [redact]ed messages.
+pub struct RedactingLogger {
+    inner: Box<dyn Log>,
+}
+
+impl RedactingLogger {
+    pub(crate) fn new(inner: Box<dyn Log>) -> Self {
+        RedactingLogger { inner }
+    }
+}
+
+impl Log for RedactingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = redact(&record.args().to_string());
+        self.inner.log(&Record::builder()
+            .args(format_args!("{}", message))
+            .metadata(record.metadata().clone())
+            .module_path(record.module_path())
+            .file(record.file())
+            .line(record.line())
+            .build());
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+// replaces what follows `marker` up to the next separator, e.g. the key of "appid=<key>&lang=de"
+fn redact_values(text: &str, marker: &str) -> String {
+    let lowercase = text.to_ascii_lowercase();
+    let marker_lowercase = marker.to_ascii_lowercase();
+
+    let mut redacted = String::with_capacity(text.len());
+    let mut position = 0;
+    while let Some(found) = lowercase[position..].find(&marker_lowercase) {
+        let start = position + found;
+        let value_start = start + marker.len();
+
+        // "monkey=" doesn't hold a key
+        let is_parameter_start = text[..start].chars().next_back()
+            .is_none_or(|previous| !previous.is_ascii_alphanumeric() && previous != '_');
+        let value_end = text[value_start..]
+            .find(|character: char| character == '&' || character == '"' || character == '\'' || character == ',' || character.is_whitespace())
+            .map_or(text.len(), |end| value_start + end);
+
+        redacted.push_str(&text[position..value_start]);
+        if is_parameter_start && value_end > value_start && &text[value_start..value_end] != REDACTED {
+            redacted.push_str(REDACTED);
+        } else {
+            redacted.push_str(&text[value_start..value_end]);
+        }
+        position = value_end;
+    }
+    redacted.push_str(&text[position..]);
+    redacted
+}
+
+fn read_secret_file(path: &str) -> Result<String, String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("Failed to read secret file '{}': {}", path, e))?;
+    if is_world_readable(&metadata) {
+        warn!("Secret file '{}' is readable by every user of this system, restrict it with 'chmod 600 {}'", path, path);
+    }
+
+    let secret = fs::read_to_string(path).map_err(|e| format!("Failed to read secret file '{}': {}", path, e))?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn run_secret_command(command_line: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command_line)
+        .output()
+        .map_err(|e| format!("Failed to run secret command '{}': {}", command_line, e))?;
+
+    if !output.status.success() {
+        return Err(format!("Secret command '{}' failed: {}", command_line, output.status));
+    }
+
+    let secret = String::from_utf8(output.stdout)
+        .map_err(|e| format!("Output of secret command '{}' isn't text: {}", command_line, e))?;
+    // pass and similar tools print the secret in the first line
+    Ok(secret.lines().next().unwrap_or_default().to_string())
+}
+
+#[cfg(unix)]
+fn is_world_readable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o004 != 0
+}
+
+#[cfg(not(unix))]
+fn is_world_readable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::secrets::{redact, resolve};
+
+    #[test]
+    fn secret_sources() {
+        std::env::set_var("INFO_PANEL_TEST_SECRET", "from-environment");
+        assert_eq!(resolve("env:INFO_PANEL_TEST_SECRET"), Ok("from-environment".to_string()));
+        assert!(resolve("env:INFO_PANEL_MISSING_SECRET").is_err());
+
+        let path = std::env::temp_dir().join(format!("info_panel_secret_{}", std::process::id()));
+        fs::write(&path, "from-file\n").unwrap();
+        assert_eq!(resolve(&format!("file:{}", path.display())), Ok("from-file".to_string()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(resolve("command:echo from-command; echo second line"), Ok("from-command".to_string()));
+        // commands run once, later configs reuse their output
+        let runs = std::env::temp_dir().join(format!("info_panel_secret_runs_{}", std::process::id()));
+        let command = format!("command:echo run >> {}; echo counted", runs.display());
+        assert_eq!(resolve(&command), Ok("counted".to_string()));
+        assert_eq!(resolve(&command), Ok("counted".to_string()));
+        assert_eq!(fs::read_to_string(&runs).unwrap(), "run\n");
+        fs::remove_file(&runs).unwrap();
+        assert!(resolve("command:exit 1").is_err());
+        assert_eq!(resolve(" literal-secret "), Ok("literal-secret".to_string()));
+
+        // secrets read are redacted wherever they show up
+        assert_eq!(redact("user:from-file@example.org"), "user:***@example.org");
+    }
+
+    #[test]
+    fn redaction() {
+        assert_eq!(redact("https://api.openweathermap.org/data/2.5/weather?lat=50.2&lon=7.8&units=metric&lang=de&appid=0123abcd"),
+                   "https://api.openweathermap.org/data/2.5/weather?lat=50.2&lon=7.8&units=metric&lang=de&appid=***");
+        assert_eq!(redact("refresh with access_token=abc&refresh_token=def failed"), "refresh with access_token=***&refresh_token=*** failed");
+        assert_eq!(redact("Authorization: Bearer eyJhbGciOi.x.y"), "Authorization: Bearer ***");
+        assert_eq!(redact("password=geheim, user=anna"), "password=***, user=anna");
+        // not a parameter of its own
+        assert_eq!(redact("monkey=banana"), "monkey=banana");
+        assert_eq!(redact("nothing to hide"), "nothing to hide");
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use openssl::hash::{hash, MessageDigest};
+use reqwest::{Method, StatusCode, Url};
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+
+use crate::filesystem::FileSystemHandler;
+use crate::http_client;
+use crate::secrets;
+use crate::webdav::tls::TlsOptions;
+
+// access tokens are refreshed this long before they expire
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+// last Digest challenge of every server with the number of requests made with it
+static DIGEST_CHALLENGES: OnceLock<Mutex<HashMap<String, (DigestChallenge, u32)>>> = OnceLock::new();
+// access tokens of every OAuth2 client with the time they expire
+static ACCESS_TOKENS: OnceLock<Mutex<HashMap<String, (String, Instant)>>> = OnceLock::new();
+
+/// How requests to the WebDAV server authenticate, `auth=` in the config.
+#[derive(Clone, PartialEq)]
+pub enum Auth {
+    Basic { user: String, password: String },
+    Digest { user: String, password: String },
+    /// A fixed token, e.g. an app password some providers hand out.
+    Bearer { token: String },
+    /// Access tokens fetched with a refresh token and refreshed before they expire.
+    OAuth2(OAuth2),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct OAuth2 {
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: String,
+    qop: Option<String>,
+}
+
+impl Auth {
+    /// Reads the authentication of a WebDAV config. Secrets are resolved with
+    /// [secrets::resolve].
+    pub(crate) fn new(values: &HashMap<String, String>, path_config: &str) -> Result<Self, String> {
+        let value = |key: &str| values.get(key).map(|value| value.trim()).filter(|value| !value.is_empty());
+        let required = |key: &str| value(key)
+            .map(|value| value.to_string())
+            .ok_or_else(|| format!("Error parsing content of '{}': '{}' is missing", path_config, key));
+        let secret = |key: &str| required(key).and_then(|value| secrets::resolve(&value));
+
+        match value("auth").unwrap_or("basic") {
+            "basic" => Ok(Auth::Basic { user: required("user")?, password: secret("password")? }),
+            "digest" => Ok(Auth::Digest { user: required("user")?, password: secret("password")? }),
+            "bearer" => Ok(Auth::Bearer { token: secret("token")? }),
+            "oauth2" => Ok(Auth::OAuth2(OAuth2 {
+                token_url: required("token_url")?,
+                client_id: required("client_id")?,
+                client_secret: value("client_secret").map(secrets::resolve).transpose()?.unwrap_or_default(),
+                refresh_token: secret("refresh_token")?,
+            })),
+            other => Err(format!("Unknown authentication '{}' in '{}', expected basic, digest, bearer or oauth2", other, path_config)),
+        }
+    }
+
+    /// Adds the credentials to a request. Digest credentials can only be added once the server
+    /// sent a challenge. Access tokens are fetched with the TLS settings of the connection.
+    pub(crate) fn authorize(&self, request: RequestBuilder, method: &Method, url: &str, tls: &TlsOptions) -> Result<RequestBuilder, String> {
+        match self {
+            Auth::Basic { user, password } => Ok(request.basic_auth(user, Some(password))),
+            Auth::Bearer { token } => Ok(request.bearer_auth(token)),
+            Auth::OAuth2(oauth2) => Ok(request.bearer_auth(oauth2.access_token(tls)?)),
+            Auth::Digest { user, password } => {
+                let origin = origin_of(url)?;
+                let mut challenges = lock(&DIGEST_CHALLENGES)?;
+                match challenges.get_mut(&origin) {
+                    Some((challenge, count)) => {
+                        *count += 1;
+                        let authorization = challenge.authorization(user, password, method.as_str(), &request_uri(url)?, *count, &cnonce())?;
+                        Ok(request.header(AUTHORIZATION, authorization))
+                    },
+                    None => Ok(request),
+                }
+            },
+        }
+    }
+
+    /// Takes note of a 401 response. Returns whether sending the request again may succeed,
+    /// i.e. a Digest challenge arrived or an access token was refreshed.
+    pub(crate) fn accept_unauthorized(&self, response: &Response) -> Result<bool, String> {
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(false);
+        }
+
+        match self {
+            Auth::Digest { .. } => {
+                let challenge = response.headers().get_all(WWW_AUTHENTICATE).iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .find_map(DigestChallenge::parse)
+                    .ok_or_else(|| format!("Server of '{}' didn't send a Digest challenge", response.url()))?;
+
+                let origin = origin_of(response.url().as_str())?;
+                lock(&DIGEST_CHALLENGES)?.insert(origin, (challenge, 0));
+                Ok(true)
+            },
+            Auth::OAuth2(oauth2) => {
+                // the token may have been revoked before it expired
+                lock(&ACCESS_TOKENS)?.remove(&oauth2.cache_key());
+                Ok(true)
+            },
+            _default => Ok(false),
+        }
+    }
+}
+
+// secrets are left out, configs end up in log messages
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Auth::Basic { user, .. } => f.debug_struct("Basic").field("user", user).field("password", &secrets::REDACTED).finish(),
+            Auth::Digest { user, .. } => f.debug_struct("Digest").field("user", user).field("password", &secrets::REDACTED).finish(),
+            Auth::Bearer { .. } => f.debug_struct("Bearer").field("token", &secrets::REDACTED).finish(),
+            Auth::OAuth2(oauth2) => f.debug_tuple("OAuth2").field(oauth2).finish(),
+        }
+    }
+}
+
+impl fmt::Debug for OAuth2 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OAuth2")
+            .field("token_url", &self.token_url)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &secrets::REDACTED)
+            .field("refresh_token", &secrets::REDACTED)
+            .finish()
+    }
+}
+
+impl OAuth2 {
+    // a cached access token or a fresh one
+    fn access_token(&self, tls: &TlsOptions) -> Result<String, String> {
+        if let Some((token, expires)) = lock(&ACCESS_TOKENS)?.get(&self.cache_key()) {
+            if Instant::now() + EXPIRY_MARGIN < *expires {
+                return Ok(token.clone());
+            }
+        }
+
+        let refresh_token = self.read_rotated_refresh_token().unwrap_or_else(|| self.refresh_token.clone());
+        let request = tls.client(&self.token_url)?
+            .post(&self.token_url)
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token.as_str()),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+            ]);
+        let response = http_client::send_blocking(request, &format!("Failed to refresh access token at '{}'", self.token_url))?;
+
+        let status = response.status();
+        let body = response.text().map_err(|e| format!("Failed to read access token: {}", http_client::describe(&e)))?;
+        if !status.is_success() {
+            return Err(format!("Failed to refresh access token at '{}': {} {}", self.token_url, status, body));
+        }
+
+        let json_value = json::parse(&body).map_err(|e| format!("Invalid token response of '{}': {}", self.token_url, e))?;
+        let access_token = json_value["access_token"].as_str()
+            .ok_or_else(|| format!("Token response of '{}' holds no access token", self.token_url))?
+            .to_string();
+        let expires_in = json_value["expires_in"].as_u64().unwrap_or(3600);
+        secrets::register(&access_token);
+
+        // some providers hand out a new refresh token with every access token
+        if let Some(rotated) = json_value["refresh_token"].as_str().filter(|rotated| *rotated != refresh_token) {
+            secrets::register(rotated);
+            self.write_rotated_refresh_token(rotated);
+        }
+
+        info!("Refreshed access token of '{}'", self.client_id);
+        lock(&ACCESS_TOKENS)?.insert(self.cache_key(), (access_token.clone(), Instant::now() + Duration::from_secs(expires_in)));
+        Ok(access_token)
+    }
+
+    fn cache_key(&self) -> String {
+        format!("{} {}", self.token_url, self.client_id)
+    }
+
+    // Rotated refresh tokens are kept in ~/.InfoPanel/oauth2, together with a hash of the
+    // configured one, so they are only used until the config changes.
+    fn rotated_path(&self) -> Option<String> {
+        let directory = FileSystemHandler::new().and_then(|handler| handler.create_directory("oauth2")).ok()?;
+        let file_name: String = self.client_id.chars()
+            .map(|character| if character.is_ascii_alphanumeric() || character == '-' { character } else { '_' })
+            .collect();
+        Some(format!("{}/{}.refresh_token", directory, file_name))
+    }
+
+    fn read_rotated_refresh_token(&self) -> Option<String> {
+        let content = fs::read_to_string(self.rotated_path()?).ok()?;
+        let (configured, rotated) = content.trim().split_once('\n')?;
+
+        let rotated = rotated.trim().to_string();
+        secrets::register(&rotated);
+        Some(rotated).filter(|_| hex_hash(MessageDigest::sha256(), &self.refresh_token).is_ok_and(|hash| configured.trim() == hash))
+    }
+
+    fn write_rotated_refresh_token(&self, rotated: &str) {
+        let path = match self.rotated_path() {
+            Some(path) => path,
+            None => return,
+        };
+
+        let configured = match hex_hash(MessageDigest::sha256(), &self.refresh_token) {
+            Ok(configured) => configured,
+            Err(e) => {
+                warn!("Couldn't keep the new refresh token of '{}': {}", self.client_id, e);
+                return;
+            }
+        };
+        let content = format!("{}\n{}\n", configured, rotated);
+        let written = fs::write(&path, content).and_then(|_| restrict_permissions(&path));
+        if let Err(e) = written {
+            warn!("Couldn't keep the new refresh token of '{}': {}", self.client_id, e);
+        }
+    }
+}
+
+impl DigestChallenge {
+    // `Digest realm="…", nonce="…", qop="auth,auth-int", algorithm=SHA-256, opaque="…"`
+    fn parse(header: &str) -> Option<Self> {
+        let parameters = header.trim().strip_prefix("Digest ")
+            .or_else(|| header.trim().strip_prefix("digest "))?;
+        let parameters = parse_parameters(parameters);
+        let parameter = |key: &str| parameters.get(key).cloned();
+
+        // only qop=auth is supported, auth-int would need the body
+        let qop = parameter("qop").map(|qop| {
+            qop.split(',').map(|option| option.trim().to_string()).find(|option| option == "auth")
+        });
+        if qop == Some(None) {
+            return None;
+        }
+
+        // servers may offer several algorithms in separate challenges, e.g. SHA-512-256 and MD5
+        let algorithm = parameter("algorithm").unwrap_or_else(|| "MD5".to_string());
+        message_digest(&algorithm)?;
+
+        Some(DigestChallenge {
+            realm: parameter("realm").unwrap_or_default(),
+            nonce: parameter("nonce")?,
+            opaque: parameter("opaque"),
+            algorithm,
+            qop: qop.flatten(),
+        })
+    }
+
+    // RFC 7616, section 3.4
+    fn authorization(&self, user: &str, password: &str, method: &str, uri: &str, count: u32, cnonce: &str) -> Result<String, String> {
+        let digest = message_digest(&self.algorithm)
+            .ok_or_else(|| format!("Unsupported Digest algorithm '{}'", self.algorithm))?;
+        let h = |text: &str| hex_hash(digest, text);
+
+        let mut ha1 = h(&format!("{}:{}:{}", user, self.realm, password))?;
+        if self.algorithm.to_ascii_uppercase().ends_with("-SESS") {
+            ha1 = h(&format!("{}:{}:{}", ha1, self.nonce, cnonce))?;
+        }
+        let ha2 = h(&format!("{}:{}", method, uri))?;
+        let nc = format!("{:08x}", count);
+
+        let response = match &self.qop {
+            Some(qop) => h(&format!("{}:{}:{}:{}:{}:{}", ha1, self.nonce, nc, cnonce, qop, ha2))?,
+            None => h(&format!("{}:{}:{}", ha1, self.nonce, ha2))?,
+        };
+
+        let mut authorization = format!("Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+                                        user, self.realm, self.nonce, uri, self.algorithm, response);
+        if let Some(qop) = &self.qop {
+            authorization.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+        }
+        if let Some(opaque) = &self.opaque {
+            authorization.push_str(&format!(", opaque=\"{}\"", opaque));
+        }
+        Ok(authorization)
+    }
+}
+
+// MD5 and SHA-256 with their session variants, every algorithm of RFC 7616 except SHA-512-256
+fn message_digest(algorithm: &str) -> Option<MessageDigest> {
+    match algorithm.to_ascii_uppercase().as_str() {
+        "MD5" | "MD5-SESS" => Some(MessageDigest::md5()),
+        "SHA-256" | "SHA-256-SESS" => Some(MessageDigest::sha256()),
+        _default => None,
+    }
+}
+
+// key=value and key="quoted, value" pairs separated by commas
+fn parse_parameters(text: &str) -> HashMap<String, String> {
+    let mut parameters = HashMap::new();
+    let mut rest = text.trim();
+
+    while let Some((key, after_key)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_ascii_lowercase();
+        let after_key = after_key.trim_start();
+
+        let (value, after_value) = match after_key.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (quoted[..end].to_string(), &quoted[end + 1..]),
+                None => (quoted.to_string(), ""),
+            },
+            None => match after_key.find(',') {
+                Some(end) => (after_key[..end].trim().to_string(), &after_key[end..]),
+                None => (after_key.trim().to_string(), ""),
+            },
+        };
+
+        parameters.insert(key, value);
+        rest = after_value.trim_start().trim_start_matches(',');
+    }
+    parameters
+}
+
+fn hex_hash(digest: MessageDigest, text: &str) -> Result<String, String> {
+    hash(digest, text.as_bytes())
+        .map(|bytes| bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+        .map_err(|e| format!("Failed to hash Digest credentials: {}", e))
+}
+
+fn cnonce() -> String {
+    format!("{:016x}", RandomState::new().build_hasher().finish())
+}
+
+fn origin_of(url: &str) -> Result<String, String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+    Ok(url.origin().ascii_serialization())
+}
+
+// path and query of a URL, as the Digest `uri`
+fn request_uri(url: &str) -> Result<String, String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+    Ok(match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    })
+}
+
+fn lock<T>(cell: &'static OnceLock<Mutex<HashMap<String, T>>>) -> Result<std::sync::MutexGuard<'static, HashMap<String, T>>, String> {
+    cell.get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|e| format!("Authentication state unavailable: {}", e))
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &str) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webdav::auth::{DigestChallenge, request_uri};
+
+    #[test]
+    fn digest_md5() {
+        // example of RFC 2617
+        let challenge = DigestChallenge::parse("Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+            nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"").unwrap();
+
+        let authorization = challenge.authorization("Mufasa", "Circle Of Life", "GET", "/dir/index.html", 1, "0a4f113b").unwrap();
+
+        assert!(authorization.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(authorization.contains("qop=auth, nc=00000001, cnonce=\"0a4f113b\""));
+        assert!(authorization.ends_with("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+    }
+
+    #[test]
+    fn digest_sha256() {
+        // example of RFC 7616
+        let challenge = DigestChallenge::parse("Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", algorithm=SHA-256, \
+            nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"").unwrap();
+        assert_eq!(challenge.algorithm, "SHA-256");
+
+        let authorization = challenge.authorization("Mufasa", "Circle of Life", "GET", "/dir/index.html", 1,
+                                                    "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ").unwrap();
+
+        assert!(authorization.contains("response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""));
+
+        // auth-int only can't be answered
+        assert_eq!(DigestChallenge::parse("Digest realm=\"r\", nonce=\"n\", qop=\"auth-int\""), None);
+        assert_eq!(DigestChallenge::parse("Basic realm=\"r\""), None);
+        // no silent fallback to MD5
+        assert_eq!(DigestChallenge::parse("Digest realm=\"r\", nonce=\"n\", algorithm=SHA-512-256"), None);
+    }
+
+    #[test]
+    fn digest_uri() {
+        assert_eq!(request_uri("https://dav.example.org/remote.php/dav/calendars/anna/?export"), Ok("/remote.php/dav/calendars/anna/?export".to_string()));
+    }
+}
@@ -0,0 +1,34 @@
+use icalendar::Property;
+
+use crate::webdav::parsing::unescape_text;
+
+/// ORGANIZER or ATTENDEE of an event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attendee {
+    pub address: String,
+    pub name: String,
+    pub role: String,
+    pub participation_status: String,
+    pub rsvp: bool,
+}
+
+impl Attendee {
+    pub(crate) fn new(property: &Property) -> Self {
+        let param = |key: &str| property.params().get(key)
+            .map(|param| unescape_text(param.value().trim_matches('"')))
+            .unwrap_or_default();
+
+        let value = property.value();
+        let address = match value.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => &value[7..],
+            _ => value,
+        };
+
+        Attendee {
+            address: address.to_string(),
+            name: param("CN"),
+            role: param("ROLE"),
+            participation_status: param("PARTSTAT"),
+            rsvp: param("RSVP").eq_ignore_ascii_case("TRUE"),
+        }
+    }}
@@ -1,33 +1,280 @@
 use std::fmt;
 
-use icalendar::Calendar as ICalendar;
+use chrono::{Duration, NaiveDateTime};
+use chrono_tz::Tz;
+use icalendar::parser::{read_calendar, unfold};
+use log::warn;
 
+use crate::webdav::calendar::recurrence::{Occurrence, RRule};
+use crate::webdav::calendar::tasks::{build_tree, sort_by_priority, Task};
 use crate::webdav::calendar::vevent::VEvent;
-use crate::webdav::calendar::vtimezone::VTimezone;
+use crate::webdav::calendar::vtimezone::{from_utc, now_in, TimeZone, to_display, to_utc, VTimezone};
 use crate::webdav::calendar::vtodo::VTodo;
 
 pub(crate) mod vevent;
 pub(crate) mod vtodo;
 pub(crate) mod vtimezone;
+pub(crate) mod recurrence;
+pub(crate) mod valarm;
+pub(crate) mod attendee;
+pub(crate) mod tasks;
+pub(crate) mod writing;
 
+#[derive(Clone)]
 pub struct Calendar {
     pub name: String,
+    // name and colour the server shows the calendar with, empty if unknown
+    pub display_name: String,
+    pub color: String,
     pub events: Vec<VEvent>,
     pub todos: Vec<VTodo>,
-    pub timezone: Option<VTimezone>,
+    pub timezones: Vec<VTimezone>,
+    // `None` displays times in the zone of the system
+    pub display_zone: Option<Tz>,
+    pub resources: Vec<Resource>,
+}
+
+/// An ics file of a CalDAV collection and the UIDs of the components it holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resource {
+    pub path: String,
+    pub e_tag: String,
+    pub uids: Vec<String>,
 }
 
 impl Calendar {
-    pub(crate) fn new(name: String, icals: Vec<ICalendar>) -> Self {
-        Calendar{
-            name: "".to_string(),
+    pub(crate) fn new(name: String, ics_files: &[String]) -> Self {
+        let mut calendar = Calendar{
+            name,
+            display_name: String::new(),
+            color: String::new(),
             events: vec![],
             todos: vec![],
-            timezone: VTimezone::new()
+            timezones: vec![],
+            display_zone: None,
+            resources: vec![],
+        };
+
+        for ics in ics_files {
+            if let Err(e) = calendar.add_ics(ics) {
+                warn!("Skipping calendar object of '{}': {}", calendar.name, e);
+            }
+        }
+        calendar
+    }
+
+    /// Adds every event, todo and timezone of an iCalendar text.
+    ///
+    /// A text may hold several VCALENDAR objects and each of them several components, e.g. a
+    /// VTIMEZONE, a recurring VEVENT and overrides of single instances of it.
+    pub(crate) fn add_ics(&mut self, ics: &str) -> Result<(), String> {
+        let unfolded = unfold(ics);
+
+        // the parser only reads a single VCALENDAR, so the objects are split up beforehand
+        for object in split_objects(&unfolded) {
+            let icalendar = read_calendar(object)?;
+
+            for timezone in VTimezone::from_icalendar(&icalendar) {
+                if !self.timezones.iter().any(|known| known.tzid == timezone.tzid) {
+                    self.timezones.push(timezone);
+                }
+            }
+            for mut event in VEvent::from_icalendar(&icalendar) {
+                if let Some(rrule) = event.rrule.as_mut() {
+                    self.localise_until(rrule, &event.timezone);
+                }
+                if event.end_timezone != event.timezone && !event.is_all_day {
+                    event.date_end = self.move_into(event.date_end, &event.end_timezone, &event.timezone);
+                    event.end_timezone = event.timezone.clone();
+                }
+                if event.recurrence_id_timezone != event.timezone && !event.is_all_day {
+                    event.recurrence_id = event.recurrence_id
+                        .map(|recurrence_id| self.move_into(recurrence_id, &event.recurrence_id_timezone, &event.timezone));
+                    event.recurrence_id_timezone = event.timezone.clone();
+                }
+                let rdates = self.localise_dates(std::mem::take(&mut event.zoned_rdates), &event.timezone);
+                event.rdates.extend(rdates);
+                let exdates = self.localise_dates(std::mem::take(&mut event.zoned_exdates), &event.timezone);
+                event.exdates.extend(exdates);
+                self.events.push(event);
+            }
+            for mut todo in VTodo::from_icalendar(&icalendar) {
+                let zone = todo.anchor_zone().clone();
+                if let Some(rrule) = todo.rrule.as_mut() {
+                    self.localise_until(rrule, &zone);
+                }
+                if todo.recurrence_id_timezone != zone && !todo.is_all_day {
+                    todo.recurrence_id = todo.recurrence_id
+                        .map(|recurrence_id| self.move_into(recurrence_id, &todo.recurrence_id_timezone, &zone));
+                    todo.recurrence_id_timezone = zone.clone();
+                }
+                let rdates = self.localise_dates(std::mem::take(&mut todo.zoned_rdates), &zone);
+                todo.rdates.extend(rdates);
+                let exdates = self.localise_dates(std::mem::take(&mut todo.zoned_exdates), &zone);
+                todo.exdates.extend(exdates);
+                self.todos.push(todo);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the events, todos and timezones of another calendar.
+    pub(crate) fn merge(&mut self, other: &Calendar) {
+        self.events.extend(other.events.iter().cloned());
+        self.todos.extend(other.todos.iter().cloned());
+        for timezone in &other.timezones {
+            if !self.timezones.iter().any(|known| known.tzid == timezone.tzid) {
+                self.timezones.push(timezone.clone());
+            }
+        }
+        self.resources.extend(other.resources.iter().cloned());
+    }
+
+    /// Adds an ics file downloaded from `path` and remembers its ETag for writing it back.
+    pub(crate) fn add_resource(&mut self, path: &str, e_tag: &str, ics: &str) -> Result<(), String> {
+        let known_events = self.events.len();
+        let known_todos = self.todos.len();
+        self.add_ics(ics)?;
+
+        let mut uids: Vec<String> = self.events[known_events..].iter().map(|event| event.uid.clone())
+            .chain(self.todos[known_todos..].iter().map(|todo| todo.uid.clone()))
+            .collect();
+        uids.sort();
+        uids.dedup();
+
+        self.resources.push(Resource { path: path.to_string(), e_tag: e_tag.to_string(), uids });
+        Ok(())
+    }
+
+    /// The ics file holding the component with `uid`.
+    pub(crate) fn resource_of(&self, uid: &str) -> Option<&Resource> {
+        self.resources.iter().find(|resource| resource.uids.iter().any(|known| known == uid))
+    }
+
+    // Recurrences are expanded on the wall-clock time of DTSTART, but UNTIL of zoned events is
+    // given in UTC.
+    fn localise_until(&self, rrule: &mut RRule, zone: &TimeZone) {
+        if let (true, Some(until)) = (rrule.until_is_utc, rrule.until) {
+            rrule.until = Some(from_utc(until, zone, &self.timezones, self.display_zone));
+            rrule.until_is_utc = false;
+        }
+    }
+
+    // RDATEs and EXDATEs are compared with the instances on the wall-clock time of DTSTART.
+    fn localise_dates(&self, dates: Vec<(NaiveDateTime, TimeZone)>, zone: &TimeZone) -> Vec<NaiveDateTime> {
+        dates.into_iter()
+            .map(|(date, date_zone)| self.move_into(date, &date_zone, zone))
+            .collect()
+    }
+
+    // the wall-clock time of `to` at the wall-clock time `time` of `from`
+    fn move_into(&self, time: NaiveDateTime, from: &TimeZone, to: &TimeZone) -> NaiveDateTime {
+        from_utc(to_utc(time, from, &self.timezones, self.display_zone), to, &self.timezones, self.display_zone)
+    }
+
+    /// Converts a time of one of the calendar's components into the display zone.
+    pub(crate) fn to_display(&self, time: NaiveDateTime, zone: &TimeZone) -> NaiveDateTime {
+        to_display(time, zone, &self.timezones, self.display_zone)
+    }
+
+    /// The current time in the display zone.
+    pub(crate) fn now(&self) -> NaiveDateTime {
+        now_in(self.display_zone)
+    }
+
+    /// Concrete event instances overlapping `[from, to)`, sorted by start.
+    ///
+    /// `from` and `to` as well as the returned times are in the display zone. All-day events
+    /// keep their dates.
+    pub(crate) fn occurrences(&self, from: NaiveDateTime, to: NaiveDateTime) -> Vec<Occurrence<'_>> {
+        // events are expanded in their own zone, the margin covers any offset to the display zone
+        let margin = Duration::days(1);
+
+        let overrides: Vec<&VEvent> = self.events.iter()
+            .filter(|event| event.recurrence_id.is_some())
+            .collect();
+
+        let mut occurrences: Vec<Occurrence> = Vec::new();
+        for event in self.events.iter().filter(|event| event.recurrence_id.is_none()) {
+            occurrences.extend(event.occurrences(&overrides, from - margin, to + margin));
+        }
+
+        // overrides whose master event is missing are shown as they are
+        for event in &overrides {
+            if !self.events.iter().any(|master| master.recurrence_id.is_none() && master.uid == event.uid) {
+                occurrences.push(Occurrence { event, start: event.date_start, end: event.date_start + event.duration() });
+            }
+        }
+
+        let mut occurrences: Vec<Occurrence> = occurrences.into_iter()
+            .map(|occurrence| self.occurrence_to_display(occurrence))
+            .filter(|occurrence| occurrence.overlaps(from, to))
+            .collect();
+
+        occurrences.sort_by_key(|occurrence| occurrence.start);
+        occurrences
+    }
+
+    /// Open todos as a tree of subtasks, sorted by priority on every level.
+    ///
+    /// Due times are in the display zone. Overrides of single instances of recurring todos are
+    /// left out.
+    pub(crate) fn tasks(&self) -> Vec<Task<'_>> {
+        let tasks: Vec<Task> = self.todos.iter()
+            .filter(|todo| todo.recurrence_id.is_none() && !todo.is_completed())
+            .map(|todo| {
+                let due = match todo.due {
+                    Some(due) if !todo.is_all_day => Some(self.to_display(due, &todo.due_timezone)),
+                    due => due,
+                };
+                Task::new(todo, due)
+            })
+            .collect();
+
+        let mut tree = build_tree(tasks);
+        sort_by_priority(&mut tree);
+        tree
+    }
+
+    fn occurrence_to_display<'a>(&self, occurrence: Occurrence<'a>) -> Occurrence<'a> {
+        if occurrence.event.is_all_day {
+            return occurrence;
+        }
+
+        Occurrence {
+            event: occurrence.event,
+            start: self.to_display(occurrence.start, &occurrence.event.timezone),
+            end: self.to_display(occurrence.end, &occurrence.event.timezone),
         }
     }
 }
 
+// Splits a text into its top level VCALENDAR objects. Components outside of a VCALENDAR are
+// kept as an object of their own.
+fn split_objects(ics: &str) -> Vec<&str> {
+    let mut objects: Vec<&str> = Vec::new();
+    let mut depth: usize = 0;
+    let mut start: usize = 0;
+    let mut position: usize = 0;
+
+    for line in ics.split_inclusive('\n') {
+        let trimmed = line.trim();
+
+        if trimmed.starts_with("BEGIN:") {
+            if depth == 0 {
+                start = position;
+            }
+            depth += 1;
+        } else if trimmed.starts_with("END:") && depth > 0 {
+            depth -= 1;
+            if depth == 0 {
+                objects.push(&ics[start..position + line.len()]);
+            }
+        }
+        position += line.len();
+    }
+    objects
+}
 
 impl fmt::Display for Calendar {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -42,4 +289,83 @@ impl fmt::Display for Calendar {
                    .collect::<Vec<String>>()
                    .join(", "))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    use crate::webdav::calendar::Calendar;
+
+    fn date_time(month: u32, day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, month, day).unwrap().and_hms_opt(hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn calendar_with_several_components() {
+        let ics = fs::read_to_string("data/test/vcalendar_multi.ics").unwrap();
+
+        let mut calendar = Calendar::new("chor".to_string(), &[ics]);
+        calendar.display_zone = Some(chrono_tz::Europe::Berlin);
+
+        assert_eq!(calendar.events.len(), 2);
+        assert_eq!(calendar.todos.len(), 1);
+        assert_eq!(calendar.timezones.len(), 1);
+
+        let occurrences: Vec<(String, NaiveDateTime, NaiveDateTime)> = calendar
+            .occurrences(date_time(10, 1, 0, 0), date_time(12, 1, 0, 0))
+            .iter()
+            .map(|occurrence| (occurrence.event.summary.clone(), occurrence.start, occurrence.end))
+            .collect();
+
+        assert_eq!(occurrences, vec![
+            ("Chorprobe".to_string(), date_time(10, 18, 18, 0), date_time(10, 18, 19, 30)),
+            ("Chorprobe (verschoben)".to_string(), date_time(10, 26, 19, 0), date_time(10, 26, 20, 30)),
+            ("Chorprobe".to_string(), date_time(11, 8, 18, 0), date_time(11, 8, 19, 30))]);
+    }
+
+    #[test]
+    fn properties_in_other_zones() {
+        let ics = fs::read_to_string("data/test/vcalendar_zones.ics").unwrap();
+
+        let mut calendar = Calendar::new("team".to_string(), &[ics]);
+        calendar.display_zone = Some(chrono_tz::Europe::Berlin);
+
+        // DTEND and EXDATE are given in UTC, DTSTART in Berlin
+        let occurrences: Vec<(NaiveDateTime, NaiveDateTime)> = calendar
+            .occurrences(date_time(10, 1, 0, 0), date_time(11, 1, 0, 0))
+            .iter()
+            .map(|occurrence| (occurrence.start, occurrence.end))
+            .collect();
+        assert_eq!(occurrences, vec![
+            (date_time(10, 16, 9, 0), date_time(10, 16, 10, 0)),
+            (date_time(10, 30, 9, 0), date_time(10, 30, 10, 0))]);
+
+        // DUE keeps its own zone
+        let todo = &calendar.todos[0];
+        assert_eq!(calendar.to_display(todo.due.unwrap(), &todo.due_timezone), date_time(10, 20, 17, 0));
+    }
+
+    #[test]
+    fn override_in_other_zone() {
+        let ics = fs::read_to_string("data/test/vcalendar_override_utc.ics").unwrap();
+
+        let mut calendar = Calendar::new("team".to_string(), &[ics]);
+        calendar.display_zone = Some(chrono_tz::Europe::Berlin);
+
+        // RDATE and RECURRENCE-ID are given in UTC, DTSTART in Berlin
+        let occurrences: Vec<(String, NaiveDateTime, NaiveDateTime)> = calendar
+            .occurrences(date_time(10, 1, 0, 0), date_time(11, 10, 0, 0))
+            .iter()
+            .map(|occurrence| (occurrence.event.summary.clone(), occurrence.start, occurrence.end))
+            .collect();
+        assert_eq!(occurrences, vec![
+            ("Sprechstunde".to_string(), date_time(10, 16, 9, 0), date_time(10, 16, 10, 0)),
+            ("Sprechstunde".to_string(), date_time(10, 19, 9, 0), date_time(10, 19, 10, 0)),
+            ("Sprechstunde (verschoben)".to_string(), date_time(10, 24, 10, 0), date_time(10, 24, 11, 0)),
+            ("Sprechstunde".to_string(), date_time(10, 30, 9, 0), date_time(10, 30, 10, 0)),
+            ("Sprechstunde".to_string(), date_time(11, 6, 9, 0), date_time(11, 6, 10, 0))]);
+    }
+}
@@ -0,0 +1,522 @@
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+
+use crate::webdav::calendar::vevent::VEvent;
+
+// upper bound of periods walked per rule from the window on, protects against rules that never
+// match
+const MAX_PERIODS: i64 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// Parsed RRULE value (RFC 5545, section 3.3.10).
+///
+/// BYHOUR, BYMINUTE, BYSECOND, BYWEEKNO and BYYEARDAY are not supported, every instance keeps
+/// the time of day of DTSTART.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<NaiveDateTime>,
+    // UNTIL was given in UTC and still has to be moved into the zone of DTSTART
+    pub until_is_utc: bool,
+    pub by_day: Vec<(i32, Weekday)>,
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+    pub by_set_pos: Vec<i32>,
+    pub week_start: Weekday,
+}
+
+/// A concrete instance of an event inside a requested window.
+///
+/// `event` is the master event, or the overriding event if the instance was modified
+/// through a RECURRENCE-ID.
+#[derive(Debug)]
+pub struct Occurrence<'a> {
+    pub event: &'a VEvent,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+impl<'a> Occurrence<'a> {
+    pub(crate) fn overlaps(&self, from: NaiveDateTime, to: NaiveDateTime) -> bool {
+        if self.start == self.end {
+            self.start >= from && self.start < to
+        } else {
+            self.start < to && self.end > from
+        }
+    }
+}
+
+impl RRule {
+    pub(crate) fn parse(value: &str) -> Result<Self, String> {
+        let mut freq: Option<Frequency> = None;
+        let mut interval: u32 = 1;
+        let mut count: Option<u32> = None;
+        let mut until: Option<NaiveDateTime> = None;
+        let mut until_is_utc: bool = false;
+        let mut by_day: Vec<(i32, Weekday)> = Vec::new();
+        let mut by_month_day: Vec<i32> = Vec::new();
+        let mut by_month: Vec<u32> = Vec::new();
+        let mut by_set_pos: Vec<i32> = Vec::new();
+        let mut week_start: Weekday = Weekday::Mon;
+
+        for part in value.split(';') {
+            let (key, val) = match part.split_once('=') {
+                Some(key_value) => key_value,
+                None => continue,
+            };
+
+            match key.trim().to_uppercase().as_str() {
+                "FREQ" => freq = Some(parse_frequency(val)?),
+                "INTERVAL" => interval = parse_number(key, val)?,
+                "COUNT" => count = Some(parse_number(key, val)?),
+                "UNTIL" => {
+                    until = Some(parse_until(val)?);
+                    until_is_utc = val.trim().ends_with('Z');
+                },
+                "BYDAY" => by_day = val.split(',').map(parse_by_day).collect::<Result<_, _>>()?,
+                "BYMONTHDAY" => by_month_day = val.split(',').map(|v| parse_number(key, v)).collect::<Result<_, _>>()?,
+                "BYMONTH" => by_month = val.split(',').map(|v| parse_number(key, v)).collect::<Result<_, _>>()?,
+                "BYSETPOS" => by_set_pos = val.split(',').map(|v| parse_number(key, v)).collect::<Result<_, _>>()?,
+                "WKST" => week_start = parse_weekday(val)?,
+                _default => (),
+            }
+        }
+
+        match freq {
+            Some(freq) => Ok(RRule {
+                freq,
+                interval: interval.max(1),
+                count,
+                until,
+                until_is_utc,
+                by_day,
+                by_month_day,
+                by_month,
+                by_set_pos,
+                week_start,
+            }),
+            None => Err(format!("RRULE '{}' has no FREQ", value)),
+        }
+    }
+
+    // Returns the first moment of the n-th period and all candidate instances inside of it.
+    fn period(&self, dtstart: NaiveDateTime, n: i64) -> Option<(NaiveDateTime, Vec<NaiveDateTime>)> {
+        let step = n.checked_mul(self.interval as i64)?;
+        let time = dtstart.time();
+
+        let (period_start, mut dates): (NaiveDateTime, Vec<NaiveDate>) = match self.freq {
+            Frequency::Hourly => {
+                let instance = dtstart.checked_add_signed(Duration::try_hours(step)?)?;
+                let keep = self.matches_filters(instance.date());
+                return Some((instance, if keep { vec![instance] } else { vec![] }));
+            }
+            Frequency::Daily => {
+                let date = dtstart.date().checked_add_signed(Duration::try_days(step)?)?;
+                let dates = if self.matches_filters(date) { vec![date] } else { vec![] };
+                (date.and_time(NaiveTime::MIN), dates)
+            }
+            Frequency::Weekly => {
+                let offset = (7 + dtstart.weekday().num_days_from_monday() as i64
+                    - self.week_start.num_days_from_monday() as i64) % 7;
+                let week = dtstart.date()
+                    .checked_sub_signed(Duration::try_days(offset)?)?
+                    .checked_add_signed(Duration::try_weeks(step)?)?;
+
+                let dates = (0..7)
+                    .filter_map(|day| week.checked_add_signed(Duration::days(day)))
+                    .filter(|date| {
+                        if self.by_day.is_empty() {
+                            date.weekday() == dtstart.weekday()
+                        } else {
+                            self.by_day.iter().any(|(_, weekday)| *weekday == date.weekday())
+                        }
+                    })
+                    .filter(|date| self.by_month.is_empty() || self.by_month.contains(&date.month()))
+                    .collect();
+                (week.and_time(NaiveTime::MIN), dates)
+            }
+            Frequency::Monthly => {
+                let months = dtstart.year() as i64 * 12 + dtstart.month0() as i64 + step;
+                let year = i32::try_from(months.div_euclid(12)).ok()?;
+                let month = months.rem_euclid(12) as u32 + 1;
+                let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+
+                let dates = if self.by_month.is_empty() || self.by_month.contains(&month) {
+                    self.days_of_month(year, month, dtstart.day())
+                } else {
+                    vec![]
+                };
+                (first.and_time(NaiveTime::MIN), dates)
+            }
+            Frequency::Yearly => {
+                let year = i32::try_from(dtstart.year() as i64 + step).ok()?;
+                let first = NaiveDate::from_ymd_opt(year, 1, 1)?;
+
+                let dates = if !self.by_month.is_empty() {
+                    let mut months = self.by_month.clone();
+                    months.sort();
+                    months.iter()
+                        .flat_map(|month| self.days_of_month(year, *month, dtstart.day()))
+                        .collect()
+                } else if !self.by_day.is_empty() && self.by_month_day.is_empty() {
+                    self.days_of_year(year)
+                } else if !self.by_month_day.is_empty() {
+                    // BYMONTHDAY without BYMONTH is every month
+                    (1..=12)
+                        .flat_map(|month| self.days_of_month(year, month, dtstart.day()))
+                        .collect()
+                } else {
+                    self.days_of_month(year, dtstart.month(), dtstart.day())
+                };
+                (first.and_time(NaiveTime::MIN), dates)
+            }
+        };
+
+        dates.sort();
+        dates.dedup();
+
+        let mut instances: Vec<NaiveDateTime> = dates.iter().map(|date| date.and_time(time)).collect();
+        if !self.by_set_pos.is_empty() {
+            instances = self.select_set_pos(&instances);
+        }
+        Some((period_start, instances))
+    }
+
+    // The first period that can hold instances from `from` on, so old rules aren't walked from
+    // DTSTART. Rules with COUNT are, their instances before the window count as well.
+    fn first_period(&self, dtstart: NaiveDateTime, from: NaiveDateTime) -> i64 {
+        if self.count.is_some() || from <= dtstart {
+            return 0;
+        }
+
+        let elapsed = from - dtstart;
+        let periods = match self.freq {
+            Frequency::Hourly => elapsed.num_hours(),
+            Frequency::Daily => elapsed.num_days(),
+            Frequency::Weekly => elapsed.num_weeks(),
+            Frequency::Monthly => (from.year() as i64 - dtstart.year() as i64) * 12 + from.month() as i64 - dtstart.month() as i64,
+            Frequency::Yearly => from.year() as i64 - dtstart.year() as i64,
+        };
+        // the period before can still reach into the window
+        (periods / self.interval as i64 - 1).max(0)
+    }
+
+    fn matches_filters(&self, date: NaiveDate) -> bool {
+        let last = days_in_month(date.year(), date.month());
+
+        (self.by_month.is_empty() || self.by_month.contains(&date.month())) &&
+            (self.by_month_day.is_empty() || self.by_month_day.iter()
+                .any(|day| resolve_day(*day, last) == Some(date.day()))) &&
+            (self.by_day.is_empty() || self.by_day.iter()
+                .any(|(_, weekday)| *weekday == date.weekday()))
+    }
+
+    fn days_of_month(&self, year: i32, month: u32, default_day: u32) -> Vec<NaiveDate> {
+        let last = days_in_month(year, month);
+
+        let days: Vec<u32> = if !self.by_month_day.is_empty() {
+            self.by_month_day.iter().filter_map(|day| resolve_day(*day, last)).collect()
+        } else if self.by_day.is_empty() {
+            if default_day <= last { vec![default_day] } else { vec![] }
+        } else {
+            (1..=last).collect()
+        };
+
+        days.into_iter()
+            .filter_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+            .filter(|date| self.by_day.is_empty() || self.by_day.iter()
+                .any(|by_day| matches_ordinal_weekday(*by_day, date.weekday(), date.day(), last)))
+            .collect()
+    }
+
+    fn days_of_year(&self, year: i32) -> Vec<NaiveDate> {
+        let first = match NaiveDate::from_ymd_opt(year, 1, 1) {
+            Some(first) => first,
+            None => return vec![],
+        };
+        let last = if first.leap_year() { 366 } else { 365 };
+
+        first.iter_days()
+            .take(last as usize)
+            .filter(|date| self.by_day.iter()
+                .any(|by_day| matches_ordinal_weekday(*by_day, date.weekday(), date.ordinal(), last)))
+            .collect()
+    }
+
+    fn select_set_pos(&self, instances: &[NaiveDateTime]) -> Vec<NaiveDateTime> {
+        let len = instances.len() as i32;
+
+        let mut selected: Vec<NaiveDateTime> = self.by_set_pos.iter()
+            .filter_map(|pos| match *pos {
+                pos if pos > 0 && pos <= len => Some(instances[(pos - 1) as usize]),
+                pos if pos < 0 && -pos <= len => Some(instances[(len + pos) as usize]),
+                _ => None,
+            })
+            .collect();
+        selected.sort();
+        selected.dedup();
+        selected
+    }
+}
+
+/// Start times of all instances beginning in `[from, to)`.
+///
+/// DTSTART is always the first instance. RDATEs are added and EXDATEs removed afterwards.
+/// Expansion is done on wall-clock time, so an instance keeps its local time of day across
+/// daylight saving transitions.
+pub(crate) fn expand(dtstart: NaiveDateTime,
+                     rrule: Option<&RRule>,
+                     rdates: &[NaiveDateTime],
+                     exdates: &[NaiveDateTime],
+                     from: NaiveDateTime,
+                     to: NaiveDateTime) -> Vec<NaiveDateTime> {
+    let mut starts: Vec<NaiveDateTime> = Vec::new();
+
+    if dtstart >= from && dtstart < to {
+        starts.push(dtstart);
+    }
+
+    if let Some(rule) = rrule {
+        let mut count: u32 = 1;
+        let first = rule.first_period(dtstart, from);
+
+        'periods: for n in first..first + MAX_PERIODS {
+            let (period_start, instances) = match rule.period(dtstart, n) {
+                Some(period) => period,
+                None => break,
+            };
+
+            if period_start >= to || rule.until.is_some_and(|until| period_start > until) {
+                break;
+            }
+
+            for instance in instances {
+                if instance <= dtstart {
+                    continue;
+                }
+                if instance >= to || rule.until.is_some_and(|until| instance > until) {
+                    break 'periods;
+                }
+                if rule.count.is_some_and(|limit| count >= limit) {
+                    break 'periods;
+                }
+
+                count += 1;
+                if instance >= from {
+                    starts.push(instance);
+                }
+            }
+        }
+    }
+
+    starts.extend(rdates.iter().filter(|rdate| **rdate >= from && **rdate < to));
+    starts.retain(|start| !exdates.contains(start));
+    starts.sort();
+    starts.dedup();
+    starts
+}
+
+fn parse_frequency(value: &str) -> Result<Frequency, String> {
+    match value.trim().to_uppercase().as_str() {
+        "HOURLY" => Ok(Frequency::Hourly),
+        "DAILY" => Ok(Frequency::Daily),
+        "WEEKLY" => Ok(Frequency::Weekly),
+        "MONTHLY" => Ok(Frequency::Monthly),
+        "YEARLY" => Ok(Frequency::Yearly),
+        _default => Err(format!("Unsupported FREQ '{}'", value)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.trim().parse::<T>().map_err(|_| format!("Invalid {} '{}'", key, value))
+}
+
+fn parse_until(value: &str) -> Result<NaiveDateTime, String> {
+    let value = value.trim().trim_end_matches('Z');
+
+    if let Ok(date_time) = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S") {
+        return Ok(date_time);
+    }
+
+    // a plain date includes the whole day
+    match NaiveDate::parse_from_str(value, "%Y%m%d") {
+        Ok(date) => Ok(date.and_hms_opt(23, 59, 59).unwrap_or_default()),
+        Err(_) => Err(format!("Invalid UNTIL '{}'", value)),
+    }
+}
+
+fn parse_by_day(value: &str) -> Result<(i32, Weekday), String> {
+    let value = value.trim();
+    if value.len() < 2 {
+        return Err(format!("Invalid BYDAY '{}'", value));
+    }
+
+    let (ordinal, weekday) = value.split_at(value.len() - 2);
+    let ordinal: i32 = match ordinal {
+        "" => 0,
+        ordinal => ordinal.trim_start_matches('+').parse()
+            .map_err(|_| format!("Invalid BYDAY '{}'", value))?,
+    };
+
+    Ok((ordinal, parse_weekday(weekday)?))
+}
+
+fn parse_weekday(value: &str) -> Result<Weekday, String> {
+    match value.trim().to_uppercase().as_str() {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _default => Err(format!("Invalid weekday '{}'", value)),
+    }
+}
+
+// positive days count from the start, negative days from the end of the month
+fn resolve_day(day: i32, last: u32) -> Option<u32> {
+    let resolved = if day < 0 { last as i32 + 1 + day } else { day };
+
+    if resolved >= 1 && resolved <= last as i32 {
+        Some(resolved as u32)
+    } else {
+        None
+    }
+}
+
+// `day` is the day inside the period (month or year), `last` the number of days of that period
+fn matches_ordinal_weekday((ordinal, weekday): (i32, Weekday), date_weekday: Weekday, day: u32, last: u32) -> bool {
+    if weekday != date_weekday {
+        return false;
+    }
+
+    match ordinal {
+        0 => true,
+        ordinal if ordinal > 0 => ((day - 1) / 7 + 1) as i32 == ordinal,
+        ordinal => ((last - day) / 7 + 1) as i32 == -ordinal,
+    }
+}
+
+pub(crate) fn days_in_month(year: i32, month: u32) -> u32 {
+    let next = if month == 12 {
+        NaiveDate::from_ymd_opt(year + 1, 1, 1)
+    } else {
+        NaiveDate::from_ymd_opt(year, month + 1, 1)
+    };
+
+    match next.and_then(|next| next.pred_opt()) {
+        Some(last) => last.day(),
+        None => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveDateTime, Weekday};
+
+    use crate::webdav::calendar::recurrence::{expand, Frequency, RRule};
+
+    fn date_time(year: i32, month: u32, day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, min, 0).unwrap()
+    }
+
+    #[test]
+    fn rrule_parsing() {
+        let rrule = RRule::parse("FREQ=MONTHLY;INTERVAL=2;BYDAY=-1SU,2MO;UNTIL=20231231T000000Z;WKST=SU").unwrap();
+
+        assert_eq!(rrule, RRule {
+            freq: Frequency::Monthly,
+            interval: 2,
+            count: None,
+            until: Some(date_time(2023, 12, 31, 0, 0)),
+            until_is_utc: true,
+            by_day: vec![(-1, Weekday::Sun), (2, Weekday::Mon)],
+            by_month_day: vec![],
+            by_month: vec![],
+            by_set_pos: vec![],
+            week_start: Weekday::Sun,
+        });
+        assert!(RRule::parse("INTERVAL=2").is_err());
+    }
+
+    #[test]
+    fn weekly_with_exdate_and_count() {
+        let dtstart = date_time(2023, 10, 2, 9, 0);
+        let rrule = RRule::parse("FREQ=WEEKLY;BYDAY=MO,WE;COUNT=5").unwrap();
+        let exdates = vec![date_time(2023, 10, 4, 9, 0)];
+
+        let starts = expand(dtstart, Some(&rrule), &[], &exdates,
+                            date_time(2023, 1, 1, 0, 0), date_time(2024, 1, 1, 0, 0));
+
+        assert_eq!(starts, vec![
+            date_time(2023, 10, 2, 9, 0),
+            date_time(2023, 10, 9, 9, 0),
+            date_time(2023, 10, 11, 9, 0),
+            date_time(2023, 10, 16, 9, 0)]);
+    }
+
+    #[test]
+    fn monthly_last_sunday_across_dst() {
+        let dtstart = date_time(2023, 1, 29, 10, 30);
+        let rrule = RRule::parse("FREQ=MONTHLY;BYDAY=-1SU").unwrap();
+
+        let starts = expand(dtstart, Some(&rrule), &[], &[],
+                            date_time(2023, 3, 1, 0, 0), date_time(2023, 5, 1, 0, 0));
+
+        assert_eq!(starts, vec![
+            date_time(2023, 3, 26, 10, 30),
+            date_time(2023, 4, 30, 10, 30)]);
+    }
+
+    #[test]
+    fn old_rule_skips_to_window() {
+        let dtstart = date_time(2000, 1, 1, 8, 0);
+        let rrule = RRule::parse("FREQ=HOURLY;INTERVAL=3").unwrap();
+
+        let starts = expand(dtstart, Some(&rrule), &[], &[],
+                            date_time(2023, 10, 12, 0, 0), date_time(2023, 10, 12, 9, 0));
+
+        assert_eq!(starts, vec![
+            date_time(2023, 10, 12, 2, 0),
+            date_time(2023, 10, 12, 5, 0),
+            date_time(2023, 10, 12, 8, 0)]);
+    }
+
+    #[test]
+    fn yearly_month_day_in_every_month() {
+        let dtstart = date_time(2023, 1, 31, 12, 0);
+        let rrule = RRule::parse("FREQ=YEARLY;BYMONTHDAY=-1").unwrap();
+
+        let starts = expand(dtstart, Some(&rrule), &[], &[],
+                            date_time(2023, 2, 1, 0, 0), date_time(2023, 5, 1, 0, 0));
+
+        assert_eq!(starts, vec![
+            date_time(2023, 2, 28, 12, 0),
+            date_time(2023, 3, 31, 12, 0),
+            date_time(2023, 4, 30, 12, 0)]);
+    }
+
+    #[test]
+    fn yearly_birthday_window() {
+        let dtstart = date_time(1984, 2, 29, 0, 0);
+        let rrule = RRule::parse("FREQ=YEARLY").unwrap();
+        let rdates = vec![date_time(2023, 3, 1, 0, 0)];
+
+        let starts = expand(dtstart, Some(&rrule), &rdates, &[],
+                            date_time(2023, 1, 1, 0, 0), date_time(2025, 1, 1, 0, 0));
+
+        assert_eq!(starts, vec![
+            date_time(2023, 3, 1, 0, 0),
+            date_time(2024, 2, 29, 0, 0)]);
+    }
+}
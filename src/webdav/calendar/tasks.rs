@@ -0,0 +1,145 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDateTime};
+
+use crate::webdav::calendar::vtodo::VTodo;
+
+/// An open todo together with its subtasks, as shown in the task table.
+#[derive(Debug)]
+pub struct Task<'a> {
+    pub todo: &'a VTodo,
+    // due time in the display zone
+    pub due: Option<NaiveDateTime>,
+    pub subtasks: Vec<Task<'a>>,
+}
+
+impl<'a> Task<'a> {
+    pub(crate) fn new(todo: &'a VTodo, due: Option<NaiveDateTime>) -> Self {
+        Task { todo, due, subtasks: vec![] }
+    }
+
+    /// Todos due on a date are overdue the day after, all others once their due time passed.
+    pub(crate) fn is_overdue(&self, now: NaiveDateTime) -> bool {
+        match self.due {
+            Some(due) if self.todo.is_all_day => due.date() < now.date(),
+            Some(due) => due < now,
+            None => false,
+        }
+    }
+
+    pub(crate) fn is_due_today(&self, now: NaiveDateTime) -> bool {
+        self.due.is_some_and(|due| due.date() == now.date()) && !self.is_overdue(now)
+    }
+
+    /// Due after today, but within `days` days.
+    pub(crate) fn is_upcoming(&self, now: NaiveDateTime, days: i64) -> bool {
+        self.due.is_some_and(|due| {
+            due.date() > now.date() && due.date() <= now.date() + Duration::days(days)
+        })
+    }
+}
+
+/// Hangs every task below the task its RELATED-TO points at.
+///
+/// Tasks whose parent is unknown, e.g. because it is already completed, and tasks in a cycle of
+/// parent links end up at the top level.
+pub(crate) fn build_tree(tasks: Vec<Task<'_>>) -> Vec<Task<'_>> {
+    let index: HashMap<&str, usize> = tasks.iter()
+        .enumerate()
+        .map(|(i, task)| (task.todo.uid.as_str(), i))
+        .collect();
+
+    let mut parents: Vec<Option<usize>> = tasks.iter()
+        .map(|task| task.todo.related_to.as_deref().and_then(|uid| index.get(uid).copied()))
+        .collect();
+
+    // break cycles by cutting the link of the task they are found from
+    for i in 0..parents.len() {
+        let mut current = parents[i];
+        let mut steps = 0;
+        while let Some(parent) = current {
+            if parent == i || steps > parents.len() {
+                parents[i] = None;
+                break;
+            }
+            current = parents[parent];
+            steps += 1;
+        }
+    }
+
+    let mut slots: Vec<Option<Task>> = tasks.into_iter().map(Some).collect();
+    let roots: Vec<usize> = (0..slots.len()).filter(|&i| parents[i].is_none()).collect();
+
+    roots.into_iter()
+        .filter_map(|root| take_subtree(root, &mut slots, &parents))
+        .collect()
+}
+
+fn take_subtree<'a>(i: usize, slots: &mut [Option<Task<'a>>], parents: &[Option<usize>]) -> Option<Task<'a>> {
+    let mut task = slots[i].take()?;
+    for child in 0..parents.len() {
+        if parents[child] == Some(i) {
+            task.subtasks.extend(take_subtree(child, slots, parents));
+        }
+    }
+    Some(task)
+}
+
+/// Sorts by priority, then by due time, on every level of the tree.
+///
+/// PRIORITY runs from 1 (highest) to 9 (lowest), 0 means undefined and sorts last.
+pub(crate) fn sort_by_priority(tasks: &mut [Task<'_>]) {
+    tasks.sort_by(compare_tasks);
+    for task in tasks.iter_mut() {
+        sort_by_priority(&mut task.subtasks);
+    }
+}
+
+fn compare_tasks(a: &Task, b: &Task) -> Ordering {
+    let rank = |task: &Task| if task.todo.priority == 0 { 10 } else { task.todo.priority };
+
+    rank(a).cmp(&rank(b))
+        .then_with(|| match (a.due, b.due) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        })
+        .then_with(|| a.todo.summary.cmp(&b.todo.summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use chrono::NaiveDate;
+
+    use crate::webdav::calendar::Calendar;
+
+    #[test]
+    fn task_tree() {
+        let mut file = File::open("data/test/vtodo_tree.ics").unwrap();
+        let mut ics = String::new();
+        file.read_to_string(&mut ics).unwrap();
+
+        let calendar = Calendar::new("tasks".to_string(), &[ics]);
+        let tasks = calendar.tasks();
+
+        // the completed todo is hidden, its subtask moves to the top level
+        let summaries: Vec<&str> = tasks.iter().map(|task| task.todo.summary.as_str()).collect();
+        assert_eq!(summaries, vec!["Steuererklärung", "Fenster putzen", "Keller aufräumen", "Lampe aufhängen"]);
+
+        let subtasks: Vec<&str> = tasks[0].subtasks.iter().map(|task| task.todo.summary.as_str()).collect();
+        assert_eq!(subtasks, vec!["Belege sammeln", "Formular ausfüllen"]);
+
+        let now = NaiveDate::from_ymd_opt(2023,10,12).unwrap().and_hms_opt(12,0,0).unwrap();
+        assert!(tasks[0].is_overdue(now));
+        assert!(tasks[1].is_due_today(now));
+        assert!(!tasks[1].is_overdue(now));
+        assert!(tasks[2].is_upcoming(now, 7));
+        assert!(!tasks[3].is_upcoming(now, 7));
+        assert!(tasks[0].subtasks[1].is_due_today(now));
+    }
+}
@@ -0,0 +1,56 @@
+use chrono::{Duration, NaiveDateTime};
+use icalendar::parser::Component as IComponent;
+
+use crate::webdav::parsing::{convert_properties, parse_date, parse_duration, unescape_text};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// Offset to the start of the event, or to its end if `related_to_end` is set.
+    Relative { offset: Duration, related_to_end: bool },
+    Absolute(NaiveDateTime),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VAlarm {
+    pub action: String,
+    pub description: String,
+    pub trigger: Trigger,
+}
+
+impl VAlarm {
+    pub(crate) fn new(component: &IComponent) -> Option<Self> {
+        if component.name != "VALARM" {
+            return None;
+        }
+
+        let mut action: String = String::new();
+        let mut description: String = String::new();
+        let mut trigger: Option<Trigger> = None;
+
+        for property in convert_properties(&component.properties) {
+            match property.key() {
+                "ACTION" => action = property.value().to_string(),
+                "DESCRIPTION" => description = unescape_text(property.value()),
+                "TRIGGER" => {
+                    let is_absolute = property.params().get("VALUE")
+                        .is_some_and(|value| value.value() == "DATE-TIME");
+                    let related_to_end = property.params().get("RELATED")
+                        .is_some_and(|related| related.value() == "END");
+
+                    trigger = if is_absolute {
+                        Some(Trigger::Absolute(parse_date(&property)))
+                    } else {
+                        parse_duration(property.value())
+                            .map(|offset| Trigger::Relative { offset, related_to_end })
+                    };
+                },
+                _default => (),
+            }
+        }
+
+        Some(VAlarm {
+            action,
+            description,
+            trigger: trigger?,
+        })
+    }}
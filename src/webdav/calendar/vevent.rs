@@ -1,11 +1,16 @@
 use std::fmt;
 
-use chrono::NaiveDateTime;
-use icalendar::{Calendar as ICalendar, Component};
+use chrono::{Duration, NaiveDateTime};
+use icalendar::parser::{Calendar as ICalendar, Component as IComponent};
+use log::warn;
 
-use crate::webdav::parsing::parse_date;
+use crate::webdav::calendar::attendee::Attendee;
+use crate::webdav::calendar::recurrence::{expand, Occurrence, RRule};
+use crate::webdav::calendar::valarm::VAlarm;
+use crate::webdav::calendar::vtimezone::TimeZone;
+use crate::webdav::parsing::{convert_properties, is_date, parse_date, parse_date_with_zone, parse_dates_with_zone, parse_duration, split_text_list, unescape_text};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VEvent {
     pub version: String,
     pub cal_scale: String,
@@ -14,15 +19,57 @@ pub struct VEvent {
     pub desc: String,
     pub summary: String,
     pub uid: String,
+    pub location: String,
+    pub categories: Vec<String>,
+    pub status: String,
+    pub transparency: String,
+    pub class: String,
+    pub url: String,
+    pub priority: u8,
 
     pub date_start: NaiveDateTime,
     pub date_end: NaiveDateTime,
     pub date_timestamp: NaiveDateTime,
     pub is_all_day: bool,
+    // zone of DTSTART, the one the event is expanded in
+    pub timezone: TimeZone,
+    // zone of DTEND, the calendar moves the end into the zone of DTSTART
+    pub end_timezone: TimeZone,
+
+    pub rrule: Option<RRule>,
+    // in the zone of DTSTART
+    pub rdates: Vec<NaiveDateTime>,
+    // RDATEs written in another zone, the calendar moves them into `rdates`
+    pub zoned_rdates: Vec<(NaiveDateTime, TimeZone)>,
+    // in the zone of DTSTART
+    pub exdates: Vec<NaiveDateTime>,
+    // EXDATEs written in another zone, e.g. in UTC, the calendar moves them into `exdates`
+    pub zoned_exdates: Vec<(NaiveDateTime, TimeZone)>,
+    pub recurrence_id: Option<NaiveDateTime>,
+    // zone of RECURRENCE-ID, the calendar moves it into the zone of DTSTART
+    pub recurrence_id_timezone: TimeZone,
+
+    pub alarms: Vec<VAlarm>,
+    pub organizer: Option<Attendee>,
+    pub attendees: Vec<Attendee>,
 }
 
 impl VEvent {
+    /// The first VEVENT of a calendar object.
+    #[cfg(test)]
     pub(crate) fn new(icalendar: &ICalendar) -> Option<Self> {
+        VEvent::from_icalendar(icalendar).into_iter().next()
+    }
+
+    /// Every VEVENT of a calendar object, including overrides of single instances.
+    pub(crate) fn from_icalendar(icalendar: &ICalendar) -> Vec<Self> {
+        icalendar.components.iter()
+            .filter(|component| component.name == "VEVENT")
+            .map(|component| VEvent::from_component(icalendar, component))
+            .collect()
+    }
+
+    fn from_component(icalendar: &ICalendar, component: &IComponent) -> Self {
         let mut version: String = String::new();
         let mut cal_scale: String = String::new();
         let mut prodid: String = String::new();
@@ -30,13 +77,32 @@ impl VEvent {
         let mut desc: String = String::new();
         let mut summary: String = String::new();
         let mut uid: String = String::new();
+        let mut location: String = String::new();
+        let mut categories: Vec<String> = Vec::new();
+        let mut status: String = String::new();
+        let mut transparency: String = String::new();
+        let mut class: String = String::new();
+        let mut url: String = String::new();
+        let mut priority: u8 = 0;
 
         let mut date_start: NaiveDateTime = NaiveDateTime::default();
         let mut date_end: NaiveDateTime = NaiveDateTime::default();
         let mut date_timestamp: NaiveDateTime = NaiveDateTime::default();
         let mut is_all_day: bool = false;
+        let mut timezone: TimeZone = TimeZone::Floating;
+        let mut end_timezone: Option<TimeZone> = None;
+        let mut duration: Option<Duration> = None;
+
+        let mut rrule: Option<RRule> = None;
+        let mut all_rdates: Vec<(NaiveDateTime, TimeZone)> = Vec::new();
+        let mut all_exdates: Vec<(NaiveDateTime, TimeZone)> = Vec::new();
+        let mut recurrence_id: Option<NaiveDateTime> = None;
+        let mut recurrence_id_timezone: Option<TimeZone> = None;
 
-        for property in &icalendar.properties {
+        let mut organizer: Option<Attendee> = None;
+        let mut attendees: Vec<Attendee> = Vec::new();
+
+        for property in convert_properties(&icalendar.properties) {
             match property.key() {
                 "VERSION" => version = property.value().to_string(),
                 "CALSCALE" => cal_scale = property.value().to_string(),
@@ -45,43 +111,149 @@ impl VEvent {
             }
         }
 
-        for component in &icalendar.components {
-            match component.as_event() {
-                Some(event) => {
-                    for property in event.properties() {
-                        match property.0.as_str() {
-                            "DESCRIPTION" => desc = property.1.value().to_string(),
-                            "DTSTART" => date_start = parse_date(property.1),
-                            "DTEND" => date_end = parse_date(property.1),
-                            "DTSTAMP" => date_timestamp = parse_date(property.1),
-                            "SUMMARY" => summary = property.1.value().to_string(),
-                            "TRANSP" => (),
-                            "UID" => uid = property.1.value().to_string(),
-                            "URL" => (),
-                            "X-FUNAMBOL-ALLDAY" => is_all_day = property.1.value() == "1",
-                            _default => (),
-                        }
-                    }
+        for property in convert_properties(&component.properties) {
+            match property.key() {
+                "DESCRIPTION" => desc = unescape_text(property.value()),
+                "DTSTART" => {
+                    (date_start, timezone) = parse_date_with_zone(&property);
+                    is_all_day |= is_date(&property);
+                },
+                "DTEND" => {
+                    let (date, zone) = parse_date_with_zone(&property);
+                    date_end = date;
+                    end_timezone = Some(zone);
                 },
-                None => return None
+                "DTSTAMP" => date_timestamp = parse_date(&property),
+                "DURATION" => duration = parse_duration(property.value()),
+                "SUMMARY" => summary = unescape_text(property.value()),
+                "TRANSP" => transparency = property.value().to_string(),
+                "UID" => uid = property.value().to_string(),
+                "URL" => url = property.value().to_string(),
+                "LOCATION" => location = unescape_text(property.value()),
+                "CATEGORIES" => categories.extend(split_text_list(property.value())),
+                "STATUS" => status = property.value().to_string(),
+                "CLASS" => class = property.value().to_string(),
+                "PRIORITY" => priority = property.value().trim().parse().unwrap_or_default(),
+                "ORGANIZER" => organizer = Some(Attendee::new(&property)),
+                "ATTENDEE" => attendees.push(Attendee::new(&property)),
+                // all-day flags of clients that don't use VALUE=DATE
+                "X-FUNAMBOL-ALLDAY" => is_all_day |= property.value() == "1",
+                "X-MICROSOFT-CDO-ALLDAYEVENT" => is_all_day |= property.value() == "TRUE",
+                "RRULE" => match RRule::parse(property.value()) {
+                    Ok(rule) => rrule = Some(rule),
+                    Err(e) => warn!("Ignoring recurrence rule of event: {}", e),
+                },
+                "RDATE" => all_rdates.extend(parse_dates_with_zone(&property)),
+                "EXDATE" => all_exdates.extend(parse_dates_with_zone(&property)),
+                "RECURRENCE-ID" => {
+                    let (date, zone) = parse_date_with_zone(&property);
+                    recurrence_id = Some(date);
+                    recurrence_id_timezone = Some(zone);
+                },
+                _default => (),
             }
         }
 
-        Some(VEvent {
+        // DURATION replaces DTEND
+        if let Some(duration) = duration {
+            if date_end == NaiveDateTime::default() {
+                date_end = date_start + duration;
+            }
+        }
+        let end_timezone = end_timezone.unwrap_or_else(|| timezone.clone());
+        let recurrence_id_timezone = recurrence_id_timezone.unwrap_or_else(|| timezone.clone());
+        let (rdates, zoned_rdates) = split_by_zone(all_rdates, &timezone, is_all_day);
+        let (exdates, zoned_exdates) = split_by_zone(all_exdates, &timezone, is_all_day);
+
+        let alarms: Vec<VAlarm> = component.components.iter()
+            .filter_map(VAlarm::new)
+            .collect();
+
+        VEvent {
             version,
             cal_scale,
             prodid,
             desc,
             summary,
             uid,
+            location,
+            categories,
+            status,
+            transparency,
+            class,
+            url,
+            priority,
             date_start,
             date_end,
             date_timestamp,
-            is_all_day
-        })
+            is_all_day,
+            timezone,
+            end_timezone,
+            rrule,
+            rdates,
+            zoned_rdates,
+            exdates,
+            zoned_exdates,
+            recurrence_id,
+            recurrence_id_timezone,
+            alarms,
+            organizer,
+            attendees
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.status == "CANCELLED"
+    }
+
+    pub(crate) fn duration(&self) -> Duration {
+        if self.date_end > self.date_start {
+            self.date_end - self.date_start
+        } else if self.is_all_day {
+            Duration::days(1)
+        } else {
+            Duration::zero()
+        }
+    }
+
+    /// All instances of this event overlapping `[from, to)`.
+    ///
+    /// `overrides` are events with the same UID and a RECURRENCE-ID. They replace the instance
+    /// they point to, even if they were moved into or out of the window.
+    pub(crate) fn occurrences<'a>(&'a self,
+                                  overrides: &[&'a VEvent],
+                                  from: NaiveDateTime,
+                                  to: NaiveDateTime) -> Vec<Occurrence<'a>> {
+        let duration = self.duration();
+
+        // instances starting before the window can still reach into it
+        let expand_from = from - duration.max(Duration::seconds(1)) + Duration::seconds(1);
+
+        let mut occurrences: Vec<Occurrence> = expand(self.date_start, self.rrule.as_ref(), &self.rdates, &self.exdates, expand_from, to)
+            .into_iter()
+            .filter(|start| !overrides.iter().any(|o| o.uid == self.uid && o.recurrence_id == Some(*start)))
+            .map(|start| Occurrence { event: self, start, end: start + duration })
+            .filter(|occurrence| occurrence.overlaps(from, to))
+            .collect();
+
+        occurrences.extend(overrides.iter()
+            .filter(|o| o.uid == self.uid && o.recurrence_id.is_some())
+            .map(|o| Occurrence { event: o, start: o.date_start, end: o.date_start + o.duration() })
+            .filter(|occurrence| occurrence.overlaps(from, to)));
+
+        occurrences.sort_by_key(|occurrence| occurrence.start);
+        occurrences
     }
 }
 
+/// Splits RDATEs or EXDATEs into those in the zone of DTSTART and those written in another zone.
+/// Dates of all-day events have no zone.
+pub(crate) fn split_by_zone(dates: Vec<(NaiveDateTime, TimeZone)>, zone: &TimeZone, is_all_day: bool) -> (Vec<NaiveDateTime>, Vec<(NaiveDateTime, TimeZone)>) {
+    let (local, zoned): (Vec<_>, Vec<_>) = dates.into_iter()
+        .partition(|(_, date_zone)| is_all_day || date_zone == zone);
+    (local.into_iter().map(|(date, _)| date).collect(), zoned)
+}
+
 impl PartialEq for VEvent {
     fn eq(&self, other: &Self) -> bool {
         self.version == other.version &&
@@ -90,22 +262,43 @@ impl PartialEq for VEvent {
             self.desc == other.desc &&
             self.summary == other.summary &&
             self.uid == other.uid &&
+            self.location == other.location &&
+            self.categories == other.categories &&
+            self.status == other.status &&
+            self.transparency == other.transparency &&
+            self.class == other.class &&
+            self.url == other.url &&
+            self.priority == other.priority &&
             self.date_start == other.date_start &&
             self.date_end == other.date_end &&
             self.date_timestamp == other.date_timestamp &&
-            self.is_all_day == other.is_all_day
+            self.is_all_day == other.is_all_day &&
+            self.timezone == other.timezone &&
+            self.end_timezone == other.end_timezone &&
+            self.rrule == other.rrule &&
+            self.rdates == other.rdates &&
+            self.zoned_rdates == other.zoned_rdates &&
+            self.exdates == other.exdates &&
+            self.zoned_exdates == other.zoned_exdates &&
+            self.recurrence_id == other.recurrence_id &&
+            self.recurrence_id_timezone == other.recurrence_id_timezone &&
+            self.alarms == other.alarms &&
+            self.organizer == other.organizer &&
+            self.attendees == other.attendees
     }
 }
 
 impl fmt::Display for VEvent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[version: {}, cal_scale: [{}], prodid: {}, desc: {}, summary: {}, uid:{}, date_start: {}, date_end: {}, date_timestamp: {}, is_all_day: {}]",
+        write!(f, "[version: {}, cal_scale: [{}], prodid: {}, desc: {}, summary: {}, uid:{}, location: {}, status: {}, date_start: {}, date_end: {}, date_timestamp: {}, is_all_day: {}]",
                self.version,
                self.cal_scale,
                self.prodid,
                self.desc,
                self.summary,
                self.uid,
+               self.location,
+               self.status,
                self.date_start,
                self.date_end,
                self.date_timestamp,
@@ -118,14 +311,16 @@ mod tests {
     use std::fs::File;
     use std::io::Read;
 
-    use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+    use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
     use icalendar::parser::{read_calendar as read_icalendar, unfold};
 
+    use crate::webdav::calendar::valarm::Trigger;
     use crate::webdav::calendar::vevent::VEvent;
+    use crate::webdav::calendar::vtimezone::TimeZone;
 
     #[test]
     fn create_event() {
-        let mut file = File::open(format!("data/test/vevent.ics")).unwrap();
+        let mut file = File::open("data/test/vevent.ics").unwrap();
         let mut ics = String::new();
         file.read_to_string(&mut ics).unwrap();
 
@@ -134,7 +329,7 @@ mod tests {
 
         match result {
             Ok(icalendar) => {
-                let option = VEvent::new(&icalendar.into());
+                let option = VEvent::new(&icalendar);
 
                 match option {
                     Some(vevent) => {
@@ -143,25 +338,99 @@ mod tests {
                             cal_scale: "GREGORIAN".to_string(),
                             prodid: "-//hacksw/handcal//NONSGML v1.0//EN".to_string(),
                             desc: "".to_string(),
-                            summary: "Restmülltonne\\, Biotonne\\, Altpapiertonne\\, Gelber Sack".to_string(),
+                            summary: "Restmülltonne, Biotonne, Altpapiertonne, Gelber Sack".to_string(),
                             uid: "082c600294b2948e371fee12ae989ff5@eaw-rtk.de".to_string(),
+                            location: "".to_string(),
+                            categories: vec![],
+                            status: "".to_string(),
+                            transparency: "TRANSPARENT".to_string(),
+                            class: "".to_string(),
+                            url: "urqlstreetid=000".to_string(),
+                            priority: 0,
                             date_start: NaiveDateTime::new(
-                                NaiveDate::from_ymd_opt(2022,07,26).unwrap(),
+                                NaiveDate::from_ymd_opt(2022,7,26).unwrap(),
                                 NaiveTime::default()),
                             date_end: NaiveDateTime::new(
-                                NaiveDate::from_ymd_opt(2022,07,27).unwrap(),
+                                NaiveDate::from_ymd_opt(2022,7,27).unwrap(),
                                 NaiveTime::default()),
                             date_timestamp: NaiveDateTime::new(
-                                NaiveDate::from_ymd_opt(2022,08,22).unwrap(),
-                                NaiveTime::from_hms_opt(18,10,09).unwrap()),
+                                NaiveDate::from_ymd_opt(2022,8,22).unwrap(),
+                                NaiveTime::from_hms_opt(18,10,9).unwrap()),
                             is_all_day: true,
+                            timezone: TimeZone::Floating,
+                            end_timezone: TimeZone::Floating,
+                            rrule: None,
+                            rdates: vec![],
+                            zoned_rdates: vec![],
+                            exdates: vec![],
+                            zoned_exdates: vec![],
+                            recurrence_id: None,
+                            recurrence_id_timezone: TimeZone::Floating,
+                            alarms: vec![],
+                            organizer: None,
+                            attendees: vec![],
                         };
                         assert_eq!(vevent, vevent_expected);
                     }
-                    None => assert!(false)
+                    None => panic!("No VEVENT in the calendar")
                 }
             },
-            Err(_) => assert!(false)
+            Err(e) => panic!("Invalid calendar: {:?}", e)
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn event_occurrences() {
+        let mut file = File::open("data/test/vevent_recurring.ics").unwrap();
+        let mut ics = String::new();
+        file.read_to_string(&mut ics).unwrap();
+
+        let unfolded = unfold(&ics);
+        let vevent = VEvent::new(&read_icalendar(&unfolded).unwrap()).unwrap();
+
+        let from = NaiveDate::from_ymd_opt(2023,10,1).unwrap().and_time(NaiveTime::default());
+        let to = NaiveDate::from_ymd_opt(2023,11,1).unwrap().and_time(NaiveTime::default());
+        let starts: Vec<NaiveDateTime> = vevent.occurrences(&[], from, to).iter()
+            .map(|occurrence| occurrence.start)
+            .collect();
+
+        assert!(vevent.rrule.is_some());
+        assert_eq!(starts, vec![
+            NaiveDate::from_ymd_opt(2023,10,2).unwrap().and_hms_opt(7,0,0).unwrap(),
+            NaiveDate::from_ymd_opt(2023,10,16).unwrap().and_hms_opt(7,0,0).unwrap(),
+            NaiveDate::from_ymd_opt(2023,10,23).unwrap().and_hms_opt(7,0,0).unwrap()]);
+    }
+
+    #[test]
+    fn event_details() {
+        let mut file = File::open("data/test/vevent_meeting.ics").unwrap();
+        let mut ics = String::new();
+        file.read_to_string(&mut ics).unwrap();
+
+        let unfolded = unfold(&ics);
+        let vevent = VEvent::new(&read_icalendar(&unfolded).unwrap()).unwrap();
+
+        assert_eq!(vevent.summary, "Planning, Q4");
+        assert_eq!(vevent.location, "Room 2; Building A");
+        assert_eq!(vevent.categories, vec!["Work", "Planning", "Team"]);
+        assert!(vevent.is_all_day);
+        assert_eq!(vevent.status, "TENTATIVE");
+        assert_eq!(vevent.transparency, "OPAQUE");
+        assert_eq!(vevent.class, "PRIVATE");
+        assert_eq!(vevent.priority, 5);
+
+        let organizer = vevent.organizer.as_ref().unwrap();
+        assert_eq!(organizer.name, "Jane Doe");
+        assert_eq!(organizer.address, "jane@example.com");
+
+        assert_eq!(vevent.attendees.len(), 2);
+        assert_eq!(vevent.attendees[0].participation_status, "DECLINED");
+        assert!(vevent.attendees[0].rsvp);
+        assert_eq!(vevent.attendees[1].name, "");
+        assert_eq!(vevent.attendees[1].address, "anna@example.com");
+
+        assert_eq!(vevent.alarms.len(), 1);
+        assert_eq!(vevent.alarms[0].trigger,
+                   Trigger::Relative { offset: Duration::minutes(-15), related_to_end: false });
+    }
+}
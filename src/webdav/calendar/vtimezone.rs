@@ -1,79 +1,379 @@
 use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 
-use chrono::NaiveDateTime;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone as ChronoTimeZone};
+use chrono_tz::Tz;
+use icalendar::parser::{Calendar as ICalendar, Component as IComponent, read_calendar as read_icalendar, unfold};
+use log::warn;
 
-#[derive(Debug)]
+use crate::webdav::calendar::recurrence::{expand, RRule};
+use crate::webdav::parsing::{convert_properties, parse_date, parse_dates};
+
+/// Zone a DATE-TIME value was written in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeZone {
+    /// No zone at all, the value is the same wall-clock time everywhere. Also used for dates.
+    Floating,
+    Utc,
+    /// Value of a TZID parameter.
+    Id(String),
+}
+
+/// STANDARD or DAYLIGHT sub-component of a VTIMEZONE.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observance {
+    pub name: String,
+    pub is_daylight: bool,
+    pub offset_from: i32,
+    pub offset_to: i32,
+    pub date_start: NaiveDateTime,
+    pub rrule: Option<RRule>,
+    pub rdates: Vec<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct VTimezone {
-    pub version: String,
-    pub cal_scale: String,
-    pub prodid: String,
-    
     pub tzid: String,
-    
-    pub dl_tz_offset_from: String,
-    pub dl_tz_offset_to: String,
-    pub dl_tz_name: String,
-    pub dl_date_start: NaiveDateTime,
-    pub dl_rrule_freq: String,
-    pub dl_rrule_by_month: String,
-    pub dl_rrule_by_day: String,
-
-    pub df_tz_offset_from: String,
-    pub df_tz_offset_to: String,
-    pub df_tz_name: String,
-    pub df_date_start: NaiveDateTime,
-    pub df_rrule_freq: String,
-    pub df_rrule_by_month: String,
-    pub df_rrule_by_day: String
+    pub observances: Vec<Observance>,
+    // onsets of the observances and their offsets until TRANSITIONS_UNTIL_YEAR, sorted
+    transitions: Vec<(NaiveDateTime, i32)>,
 }
 
 impl VTimezone {
-    pub(crate) fn new() -> Option<Self> {
-        None
-    }
-    /*
-    pub(crate) fn new(icalendar: &ICalendar) -> Option<Self> {
-        let mut version: String = String::new();
-        let mut cal_scale: String = String::new();
-        let mut prodid: String = String::new();
-
-        let mut tzid: String = String::new();
-
-        let mut dl_tz_offset_from: String = String::new();
-        let mut dl_tz_offset_to: String = String::new();
-        let mut dl_tz_name: String = String::new();
-        let mut dl_date_start: NaiveDateTime = NaiveDateTime::default();
-        let mut dl_rrule_freq: String = String::new();
-        let mut dl_rrule_by_month: String = String::new();
-        let mut dl_rrule_by_day: String = String::new();
-
-        let mut df_tz_offset_from: String = String::new();
-        let mut df_tz_offset_to: String = String::new();
-        let mut df_tz_name: String = String::new();
-        let mut df_date_start: NaiveDateTime = NaiveDateTime::default();
-        let mut df_rrule_freq: String = String::new();
-        let mut df_rrule_by_month: String = String::new();
-        let mut df_rrule_by_day: String = String::new();
-
-        for property in &icalendar.properties {
-            match property.key() {
-                "VERSION" => version = property.value().to_string(),
-                "CALSCALE" => cal_scale = property.value().to_string(),
-                "PRODID" => prodid = property.value().to_string(),
-                _default => (),
+    pub(crate) fn new(component: &IComponent) -> Option<Self> {
+        if component.name != "VTIMEZONE" {
+            return None;
+        }
+
+        let tzid = component.find_prop("TZID")?.val.to_string();
+        let mut observances: Vec<Observance> = Vec::new();
+
+        for sub_component in &component.components {
+            let is_daylight = match sub_component.name.as_str() {
+                "DAYLIGHT" => true,
+                "STANDARD" => false,
+                _default => continue,
+            };
+
+            let mut observance = Observance {
+                name: String::new(),
+                is_daylight,
+                offset_from: 0,
+                offset_to: 0,
+                date_start: NaiveDateTime::default(),
+                rrule: None,
+                rdates: Vec::new(),
+            };
+
+            for property in convert_properties(&sub_component.properties) {
+                match property.key() {
+                    "TZNAME" => observance.name = property.value().to_string(),
+                    "TZOFFSETFROM" => observance.offset_from = parse_offset(property.value())?,
+                    "TZOFFSETTO" => observance.offset_to = parse_offset(property.value())?,
+                    "DTSTART" => observance.date_start = parse_date(&property),
+                    "RRULE" => match RRule::parse(property.value()) {
+                        Ok(rule) => observance.rrule = Some(rule),
+                        Err(e) => warn!("Ignoring recurrence rule of timezone '{}': {}", tzid, e),
+                    },
+                    "RDATE" => observance.rdates.extend(parse_dates(&property)),
+                    _default => (),
+                }
             }
+            observances.push(observance);
+        }
+
+        if observances.is_empty() {
+            return None;
         }
 
-        //todo
-        //iCalendar only has events and todos, but not timezones.
-            
-        None
+        let transitions = transitions(&observances);
+        Some(VTimezone { tzid, observances, transitions })
+    }
+
+    /// All VTIMEZONE components of a calendar.
+    pub(crate) fn from_icalendar(icalendar: &ICalendar) -> Vec<Self> {
+        icalendar.components.iter()
+            .filter_map(VTimezone::new)
+            .collect()
+    }
+
+    /// Parses the text of a `calendar-timezone` property, which is a VCALENDAR holding a VTIMEZONE.
+    pub(crate) fn from_ics(ics: &str) -> Vec<Self> {
+        // the value is indented when it is embedded in a PROPFIND response
+        let trimmed: String = ics.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        let unfolded = unfold(&trimmed);
+        match read_icalendar(&unfolded) {
+            Ok(icalendar) => VTimezone::from_icalendar(&icalendar),
+            Err(_) => vec![],
+        }
+    }
+
+    /// UTC offset in seconds that applies at the given wall-clock time of this zone.
+    pub(crate) fn offset_at(&self, local: NaiveDateTime) -> i32 {
+        let latest = if local < transitions_until() {
+            let count = self.transitions.partition_point(|(onset, _)| *onset <= local);
+            count.checked_sub(1).map(|index| self.transitions[index].1)
+        } else {
+            // later than the kept transitions
+            self.observances.iter()
+                .filter_map(|observance| last_onset(observance, local + Duration::seconds(1)).map(|onset| (onset, observance.offset_to)))
+                .max_by_key(|(onset, _)| *onset)
+                .map(|(_, offset)| offset)
+        };
+
+        match latest {
+            Some(offset) => offset,
+            // before the first onset the zone uses the offset it came from
+            None => self.observances.iter()
+                .min_by_key(|observance| observance.date_start)
+                .map_or(0, |observance| observance.offset_from),
+        }
     }
-     */
 }
 
 impl fmt::Display for VTimezone {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[]")
+        write!(f, "[tzid: {}, observances: [{}]]",
+               self.tzid,
+               self.observances.iter()
+                   .map(|observance| format!("{} {} -> {} from {}",
+                                             observance.name,
+                                             observance.offset_from,
+                                             observance.offset_to,
+                                             observance.date_start))
+                   .collect::<Vec<String>>()
+                   .join(", "))
+    }
+}
+
+// Transitions are expanded once up to this year, later ones when they are asked for.
+const TRANSITIONS_UNTIL_YEAR: i32 = 2100;
+
+fn transitions_until() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(TRANSITIONS_UNTIL_YEAR, 1, 1).unwrap_or_default().and_time(NaiveTime::MIN)
+}
+
+fn transitions(observances: &[Observance]) -> Vec<(NaiveDateTime, i32)> {
+    let until = transitions_until();
+
+    let mut transitions: Vec<(NaiveDateTime, i32)> = observances.iter()
+        .flat_map(|observance| expand(observance.date_start, observance.rrule.as_ref(), &observance.rdates, &[],
+                                      observance.date_start, until)
+            .into_iter()
+            .map(|onset| (onset, observance.offset_to)))
+        .collect();
+    transitions.sort_by_key(|(onset, _)| *onset);
+    transitions
+}
+
+// the last onset of an observance before `to`
+fn last_onset(observance: &Observance, to: NaiveDateTime) -> Option<NaiveDateTime> {
+    expand(observance.date_start, observance.rrule.as_ref(), &observance.rdates, &[], observance.date_start, to)
+        .last()
+        .copied()
+}
+
+/// Converts a wall-clock time of `zone` into UTC.
+///
+/// TZIDs are looked up in the VTIMEZONE definitions first and fall back to the IANA database.
+/// Unknown zones are treated as floating, i.e. as if they were written in the display zone.
+pub(crate) fn to_utc(time: NaiveDateTime, zone: &TimeZone, timezones: &[VTimezone], display_zone: Option<Tz>) -> NaiveDateTime {
+    match zone {
+        TimeZone::Utc => time,
+        TimeZone::Floating => local_to_utc(time, display_zone),
+        TimeZone::Id(tzid) => {
+            if let Some(vtimezone) = timezones.iter().find(|vtimezone| vtimezone.tzid == *tzid) {
+                return time - Duration::seconds(vtimezone.offset_at(time) as i64);
+            }
+
+            match iana_zone(tzid) {
+                Some(tz) => local_to_utc(time, Some(tz)),
+                None => {
+                    warn!("Unknown timezone '{}', treating time as floating", tzid);
+                    local_to_utc(time, display_zone)
+                }
+            }
+        }
+    }
+}
+
+/// Converts a UTC time into the wall-clock time of `zone`.
+pub(crate) fn from_utc(time: NaiveDateTime, zone: &TimeZone, timezones: &[VTimezone], display_zone: Option<Tz>) -> NaiveDateTime {
+    match zone {
+        TimeZone::Utc => time,
+        TimeZone::Floating => utc_to_local(time, display_zone),
+        TimeZone::Id(tzid) => {
+            if let Some(vtimezone) = timezones.iter().find(|vtimezone| vtimezone.tzid == *tzid) {
+                // the offset is looked up by wall-clock time, so it is guessed first
+                let guess = time + Duration::seconds(vtimezone.offset_at(time) as i64);
+                return time + Duration::seconds(vtimezone.offset_at(guess) as i64);
+            }
+
+            match iana_zone(tzid) {
+                Some(tz) => utc_to_local(time, Some(tz)),
+                None => utc_to_local(time, display_zone),
+            }
+        }
+    }
+}
+
+/// Converts a time of `zone` into the wall-clock time of the display zone.
+pub(crate) fn to_display(time: NaiveDateTime, zone: &TimeZone, timezones: &[VTimezone], display_zone: Option<Tz>) -> NaiveDateTime {
+    match zone {
+        TimeZone::Floating => time,
+        _ => utc_to_local(to_utc(time, zone, timezones, display_zone), display_zone),
+    }
+}
+
+/// The current wall-clock time of the display zone.
+pub(crate) fn now_in(display_zone: Option<Tz>) -> NaiveDateTime {
+    utc_to_local(chrono::Utc::now().naive_utc(), display_zone)
+}
+
+// `None` stands for the zone of the system
+fn local_to_utc(time: NaiveDateTime, zone: Option<Tz>) -> NaiveDateTime {
+    // times inside a DST gap don't exist, they are moved forward by the gap
+    let shifted = time + Duration::hours(1);
+
+    match zone {
+        Some(tz) => tz.from_local_datetime(&time).earliest()
+            .map(|date_time| date_time.naive_utc())
+            .or_else(|| tz.from_local_datetime(&shifted).earliest()
+                .map(|date_time| date_time.naive_utc() - Duration::hours(1)))
+            .unwrap_or(time),
+        None => Local.from_local_datetime(&time).earliest()
+            .map(|date_time| date_time.naive_utc())
+            .or_else(|| Local.from_local_datetime(&shifted).earliest()
+                .map(|date_time| date_time.naive_utc() - Duration::hours(1)))
+            .unwrap_or(time),
+    }
+}
+
+fn utc_to_local(time: NaiveDateTime, zone: Option<Tz>) -> NaiveDateTime {
+    match zone {
+        Some(tz) => tz.from_utc_datetime(&time).naive_local(),
+        None => Local.from_utc_datetime(&time).naive_local(),
     }
-}
\ No newline at end of file
+}
+
+// Some clients prefix the IANA name, e.g. "/mozilla.org/20050126_1/Europe/Berlin".
+fn iana_zone(tzid: &str) -> Option<Tz> {
+    if let Ok(tz) = tzid.parse::<Tz>() {
+        return Some(tz);
+    }
+
+    let segments: Vec<&str> = tzid.split('/').filter(|segment| !segment.is_empty()).collect();
+    (1..segments.len())
+        .find_map(|start| segments[start..].join("/").parse::<Tz>().ok())
+}
+
+// "+0200", "-0130" or "+023000" into seconds
+fn parse_offset(value: &str) -> Option<i32> {
+    let value = value.trim();
+    let sign = match value.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+
+    // slicing by byte needs plain digits
+    let digits = &value[1..];
+    if (digits.len() != 4 && digits.len() != 6) || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i32 = digits[0..2].parse().ok()?;
+    let minutes: i32 = digits[2..4].parse().ok()?;
+    let seconds: i32 = if digits.len() == 6 { digits[4..6].parse().ok()? } else { 0 };
+
+    Some(sign * (hours * 3600 + minutes * 60 + seconds))
+}
+
+/// Reads the zone the panel displays times in from the `timezone` key of a config file.
+/// Without it the zone of the system is used.
+pub(crate) fn read_display_zone(path_config: &str) -> Option<Tz> {
+    let file = File::open(path_config).ok()?;
+
+    let reader = BufReader::new(file);
+    for line in reader.lines().map_while(Result::ok) {
+        if let Some(("timezone", value)) = line.split_once('=') {
+            return match value.trim().parse::<Tz>() {
+                Ok(tz) => Some(tz),
+                Err(_) => {
+                    warn!("Unknown display timezone '{}' in '{}', using the system zone", value, path_config);
+                    None
+                }
+            };
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use chrono::{NaiveDate, NaiveDateTime};
+    use chrono_tz::Tz;
+
+    use crate::webdav::calendar::vtimezone::{parse_offset, TimeZone, to_display, to_utc, VTimezone};
+
+    fn date_time(year: i32, month: u32, day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, min, 0).unwrap()
+    }
+
+    fn berlin() -> VTimezone {
+        let mut file = File::open("data/test/vtimezone.ics").unwrap();
+        let mut ics = String::new();
+        file.read_to_string(&mut ics).unwrap();
+
+        VTimezone::from_ics(&ics).pop().unwrap()
+    }
+
+    #[test]
+    fn timezone_parsing() {
+        let vtimezone = berlin();
+
+        assert_eq!(vtimezone.tzid, "Europe/Berlin");
+        assert_eq!(vtimezone.observances.len(), 2);
+        assert_eq!(vtimezone.offset_at(date_time(2023, 7, 1, 12, 0)), 7200);
+        assert_eq!(vtimezone.offset_at(date_time(2023, 12, 1, 12, 0)), 3600);
+        assert_eq!(vtimezone.offset_at(date_time(2023, 3, 26, 3, 30)), 7200);
+        assert_eq!(vtimezone.offset_at(date_time(1960, 7, 1, 12, 0)), 3600);
+        // after the kept transitions
+        assert_eq!(vtimezone.offset_at(date_time(2150, 7, 1, 12, 0)), 7200);
+        assert_eq!(vtimezone.offset_at(date_time(2150, 12, 1, 12, 0)), 3600);
+    }
+
+    #[test]
+    fn offset_parsing() {
+        assert_eq!(parse_offset("+0200"), Some(7200));
+        assert_eq!(parse_offset("-013015"), Some(-5415));
+        assert_eq!(parse_offset("+02"), None);
+        // multibyte characters mustn't be sliced
+        assert_eq!(parse_offset("+0ä0"), None);
+    }
+
+    #[test]
+    fn zoned_time_conversion() {
+        let timezones = vec![berlin()];
+        let london: Option<Tz> = Some(chrono_tz::Europe::London);
+        let zone = TimeZone::Id("Europe/Berlin".to_string());
+
+        assert_eq!(to_utc(date_time(2023, 10, 20, 9, 0), &zone, &timezones, london), date_time(2023, 10, 20, 7, 0));
+        assert_eq!(to_display(date_time(2023, 10, 20, 9, 0), &zone, &timezones, london), date_time(2023, 10, 20, 8, 0));
+        assert_eq!(to_display(date_time(2023, 10, 20, 9, 0), &TimeZone::Utc, &[], london), date_time(2023, 10, 20, 10, 0));
+        assert_eq!(to_display(date_time(2023, 10, 20, 9, 0), &TimeZone::Floating, &[], london), date_time(2023, 10, 20, 9, 0));
+
+        // falls back to the IANA database when no VTIMEZONE is given
+        let prefixed = TimeZone::Id("/mozilla.org/20050126_1/Europe/Berlin".to_string());
+        assert_eq!(to_utc(date_time(2024, 1, 5, 9, 0), &prefixed, &[], london), date_time(2024, 1, 5, 8, 0));
+    }
+}
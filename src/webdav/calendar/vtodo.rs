@@ -1,46 +1,104 @@
 use std::fmt;
 
 use chrono::NaiveDateTime;
-use icalendar::{Calendar as ICalendar, Component};
+use icalendar::parser::{Calendar as ICalendar, Component as IComponent};
+use log::warn;
 
-use crate::webdav::parsing::parse_date;
+use crate::webdav::calendar::recurrence::RRule;
+use crate::webdav::calendar::vevent::split_by_zone;
+use crate::webdav::calendar::vtimezone::TimeZone;
+use crate::webdav::parsing::{convert_properties, is_date, parse_date, parse_date_with_zone, parse_dates_with_zone, split_text_list, unescape_text};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct VTodo {
     pub version: String,
     pub cal_scale: String,
     pub prodid: String,
 
     pub summary: String,
+    pub description: String,
     pub status: String,
     pub completed: NaiveDateTime,
     pub percent_complete: u8,
+    pub priority: u8,
+    pub categories: Vec<String>,
     pub sequence: String,
     pub uid: String,
+    // UID of the parent todo
+    pub related_to: Option<String>,
 
     pub date_timestamp: NaiveDateTime,
     pub created: NaiveDateTime,
     pub last_modified: NaiveDateTime,
+
+    pub date_start: Option<NaiveDateTime>,
+    pub due: Option<NaiveDateTime>,
+    pub is_all_day: bool,
+    // zone of DTSTART
+    pub timezone: TimeZone,
+    // zone of DUE
+    pub due_timezone: TimeZone,
+    pub rrule: Option<RRule>,
+    // in the zone the todo recurs in, see `anchor_zone`
+    pub rdates: Vec<NaiveDateTime>,
+    // RDATEs written in another zone, the calendar moves them into `rdates`
+    pub zoned_rdates: Vec<(NaiveDateTime, TimeZone)>,
+    // in the zone the todo recurs in, see `anchor_zone`
+    pub exdates: Vec<NaiveDateTime>,
+    // EXDATEs written in another zone, e.g. in UTC, the calendar moves them into `exdates`
+    pub zoned_exdates: Vec<(NaiveDateTime, TimeZone)>,
+    pub recurrence_id: Option<NaiveDateTime>,
+    // zone of RECURRENCE-ID, the calendar moves it into the zone the todo recurs in
+    pub recurrence_id_timezone: TimeZone,
 }
 
 impl VTodo {
+    /// The first VTODO of a calendar object.
+    #[cfg(test)]
     pub(crate) fn new(icalendar: &ICalendar) -> Option<Self> {
+        VTodo::from_icalendar(icalendar).into_iter().next()
+    }
+
+    /// Every VTODO of a calendar object, including overrides of single instances.
+    pub(crate) fn from_icalendar(icalendar: &ICalendar) -> Vec<Self> {
+        icalendar.components.iter()
+            .filter(|component| component.name == "VTODO")
+            .map(|component| VTodo::from_component(icalendar, component))
+            .collect()
+    }
+
+    fn from_component(icalendar: &ICalendar, component: &IComponent) -> Self {
         let mut version: String = String::new();
         let mut cal_scale: String = String::new();
         let mut prodid: String = String::new();
 
         let mut summary: String = String::new();
+        let mut description: String = String::new();
         let mut status: String = String::new();
         let mut completed: NaiveDateTime = NaiveDateTime::default();
         let mut percent_complete: u8 = 0;
+        let mut priority: u8 = 0;
+        let mut categories: Vec<String> = Vec::new();
         let mut sequence: String = String::new();
         let mut uid: String = String::new();
+        let mut related_to: Option<String> = None;
 
         let mut date_timestamp: NaiveDateTime = NaiveDateTime::default();
         let mut created: NaiveDateTime = NaiveDateTime::default();
         let mut last_modified: NaiveDateTime = NaiveDateTime::default();
 
-        for property in &icalendar.properties {
+        let mut date_start: Option<NaiveDateTime> = None;
+        let mut due: Option<NaiveDateTime> = None;
+        let mut is_all_day: bool = false;
+        let mut timezone: TimeZone = TimeZone::Floating;
+        let mut due_timezone: TimeZone = TimeZone::Floating;
+        let mut rrule: Option<RRule> = None;
+        let mut all_rdates: Vec<(NaiveDateTime, TimeZone)> = Vec::new();
+        let mut all_exdates: Vec<(NaiveDateTime, TimeZone)> = Vec::new();
+        let mut recurrence_id: Option<NaiveDateTime> = None;
+        let mut recurrence_id_timezone: Option<TimeZone> = None;
+
+        for property in convert_properties(&icalendar.properties) {
             match property.key() {
                 "VERSION" => version = property.value().to_string(),
                 "CALSCALE" => cal_scale = property.value().to_string(),
@@ -49,42 +107,106 @@ impl VTodo {
             }
         }
 
-        for component in &icalendar.components {
-            match component.as_todo() {
-                Some(event) => {
-                    for property in event.properties() {
-                        match property.0.as_str() {
-                            "DTSTAMP" => date_timestamp = parse_date(property.1),
-                            "UID" => uid = property.1.value().to_string(),
-                            "SEQUENCE" => sequence = property.1.value().to_string(),
-                            "CREATED" => created = parse_date(property.1),
-                            "LAST-MODIFIED" => last_modified = parse_date(property.1),
-                            "SUMMARY" => summary = property.1.value().to_string(),
-                            "STATUS" => status = property.1.value().to_string(),
-                            "COMPLETED" => completed = parse_date(property.1),
-                            "PERCENT-COMPLETE" => percent_complete = property.1.value().to_string().parse::<u8>().unwrap(),
-                            _default => (),
-                        }
+        for property in convert_properties(&component.properties) {
+            match property.key() {
+                "DTSTAMP" => date_timestamp = parse_date(&property),
+                "UID" => uid = property.value().to_string(),
+                "SEQUENCE" => sequence = property.value().to_string(),
+                "CREATED" => created = parse_date(&property),
+                "LAST-MODIFIED" => last_modified = parse_date(&property),
+                "SUMMARY" => summary = unescape_text(property.value()),
+                "DESCRIPTION" => description = unescape_text(property.value()),
+                "STATUS" => status = property.value().to_string(),
+                "COMPLETED" => completed = parse_date(&property),
+                "PERCENT-COMPLETE" => match property.value().trim().parse::<u8>() {
+                    Ok(percent) => percent_complete = percent.min(100),
+                    Err(e) => warn!("Ignoring PERCENT-COMPLETE '{}' of todo: {}", property.value(), e),
+                },
+                "PRIORITY" => match property.value().trim().parse::<u8>() {
+                    Ok(value) if value <= 9 => priority = value,
+                    _ => warn!("Ignoring PRIORITY '{}' of todo", property.value()),
+                },
+                "CATEGORIES" => categories.extend(split_text_list(property.value())),
+                "RELATED-TO" => {
+                    // children and siblings are linked from the other side
+                    let is_parent = property.params().get("RELTYPE")
+                        .is_none_or(|reltype| reltype.value().eq_ignore_ascii_case("PARENT"));
+                    if is_parent {
+                        related_to = Some(property.value().to_string());
                     }
                 },
-                None => return None
+                "DTSTART" => {
+                    let (date, zone) = parse_date_with_zone(&property);
+                    date_start = Some(date);
+                    timezone = zone;
+                    is_all_day = is_date(&property);
+                },
+                "DUE" => {
+                    let (date, zone) = parse_date_with_zone(&property);
+                    due = Some(date);
+                    due_timezone = zone;
+                    is_all_day = is_date(&property);
+                },
+                "RRULE" => match RRule::parse(property.value()) {
+                    Ok(rule) => rrule = Some(rule),
+                    Err(e) => warn!("Ignoring recurrence rule of todo: {}", e),
+                },
+                "RDATE" => all_rdates.extend(parse_dates_with_zone(&property)),
+                "EXDATE" => all_exdates.extend(parse_dates_with_zone(&property)),
+                "RECURRENCE-ID" => {
+                    let (date, zone) = parse_date_with_zone(&property);
+                    recurrence_id = Some(date);
+                    recurrence_id_timezone = Some(zone);
+                },
+                _default => (),
             }
         }
 
-        Some(VTodo{
+        // recurring todos are anchored on DTSTART, or on DUE if they have no start
+        let anchor_zone = if date_start.is_some() { &timezone } else { &due_timezone };
+        let recurrence_id_timezone = recurrence_id_timezone.unwrap_or_else(|| anchor_zone.clone());
+        let (rdates, zoned_rdates) = split_by_zone(all_rdates, anchor_zone, is_all_day);
+        let (exdates, zoned_exdates) = split_by_zone(all_exdates, anchor_zone, is_all_day);
+
+        VTodo {
             version,
             cal_scale,
             prodid,
             summary,
+            description,
             status,
             completed,
             percent_complete,
+            priority,
+            categories,
             sequence,
             uid,
+            related_to,
             date_timestamp,
             created,
             last_modified,
-        })
+            date_start,
+            due,
+            is_all_day,
+            timezone,
+            due_timezone,
+            rrule,
+            rdates,
+            zoned_rdates,
+            exdates,
+            zoned_exdates,
+            recurrence_id,
+            recurrence_id_timezone,
+        }
+    }
+
+    /// The zone the todo recurs in, that of DTSTART or of DUE without a start.
+    pub(crate) fn anchor_zone(&self) -> &TimeZone {
+        if self.date_start.is_some() { &self.timezone } else { &self.due_timezone }
+    }
+
+    pub(crate) fn is_completed(&self) -> bool {
+        self.status == "COMPLETED" || self.status == "CANCELLED" || self.percent_complete == 100
     }
 }
 
@@ -94,20 +216,36 @@ impl PartialEq for VTodo {
                 self.cal_scale == other.cal_scale &&
                 self.prodid == other.prodid &&
                 self.summary == other.summary &&
+                self.description == other.description &&
                 self.status == other.status &&
                 self.completed == other.completed &&
                 self.percent_complete == other.percent_complete &&
+                self.priority == other.priority &&
+                self.categories == other.categories &&
                 self.sequence == other.sequence &&
                 self.uid == other.uid &&
+                self.related_to == other.related_to &&
                 self.date_timestamp == other.date_timestamp &&
                 self.created == other.created &&
-                self.last_modified == other.last_modified
+                self.last_modified == other.last_modified &&
+                self.date_start == other.date_start &&
+                self.due == other.due &&
+                self.is_all_day == other.is_all_day &&
+                self.timezone == other.timezone &&
+                self.due_timezone == other.due_timezone &&
+                self.rrule == other.rrule &&
+                self.rdates == other.rdates &&
+                self.zoned_rdates == other.zoned_rdates &&
+                self.exdates == other.exdates &&
+                self.zoned_exdates == other.zoned_exdates &&
+                self.recurrence_id == other.recurrence_id &&
+                self.recurrence_id_timezone == other.recurrence_id_timezone
     }
 }
 
 impl fmt::Display for VTodo {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "[version: {}, cal_scale: [{}], prodid: {}, summary: {}, status: {}, completed:{}, percent_complete: {}, sequence: {}, uid {}, date_timestamp {}, created {}, last_modified {}]",
+        write!(f, "[version: {}, cal_scale: [{}], prodid: {}, summary: {}, status: {}, completed:{}, percent_complete: {}, priority: {}, sequence: {}, uid {}, date_timestamp {}, created {}, last_modified {}]",
                self.version,
                self.cal_scale,
                self.prodid,
@@ -115,6 +253,7 @@ impl fmt::Display for VTodo {
                self.status,
                self.completed,
                self.percent_complete,
+               self.priority,
                self.sequence,
                self.uid,
                self.date_timestamp,
@@ -131,11 +270,12 @@ mod tests {
     use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
     use icalendar::parser::{read_calendar as read_icalendar, unfold};
 
+    use crate::webdav::calendar::vtimezone::TimeZone;
     use crate::webdav::calendar::vtodo::VTodo;
 
     #[test]
     fn create_todo() {
-        let mut file = File::open(format!("data/test/vtodo.ics")).unwrap();
+        let mut file = File::open("data/test/vtodo.ics").unwrap();
         let mut ics = String::new();
         file.read_to_string(&mut ics).unwrap();
 
@@ -144,7 +284,7 @@ mod tests {
 
         match result {
             Ok(icalendar) => {
-                let option = VTodo::new(&icalendar.into());
+                let option = VTodo::new(&icalendar);
 
                 match option {
                     Some(vtodo) => {
@@ -153,29 +293,45 @@ mod tests {
                             cal_scale: "".to_string(),
                             prodid: "+//IDN bitfire.at//ical4android (org.dmfs.tasks)".to_string(),
                             summary: "Küche Sockelleisten".to_string(),
+                            description: "".to_string(),
                             status: "COMPLETED".to_string(),
                             completed: NaiveDateTime::new(
-                                NaiveDate::from_ymd_opt(2023,09,01).unwrap(),
+                                NaiveDate::from_ymd_opt(2023,9,1).unwrap(),
                                 NaiveTime::from_hms_opt(11,33,29).unwrap()),
                             percent_complete: 100,
+                            priority: 0,
+                            categories: vec![],
                             sequence: "1".to_string(),
                             uid: "9bb62d0c-0d01-4232-8b36-ac712e948cbf".to_string(),
+                            related_to: None,
                             date_timestamp: NaiveDateTime::new(
-                                NaiveDate::from_ymd_opt(2023,09,01).unwrap(),
+                                NaiveDate::from_ymd_opt(2023,9,1).unwrap(),
                                 NaiveTime::from_hms_opt(11,33,53).unwrap()),
                             created: NaiveDateTime::new(
-                                NaiveDate::from_ymd_opt(2023,05,14).unwrap(),
-                                NaiveTime::from_hms_opt(20,35,05).unwrap()),
+                                NaiveDate::from_ymd_opt(2023,5,14).unwrap(),
+                                NaiveTime::from_hms_opt(20,35,5).unwrap()),
                             last_modified: NaiveDateTime::new(
-                                NaiveDate::from_ymd_opt(2023,09,01).unwrap(),
-                                NaiveTime::from_hms_opt(11,33,29).unwrap())
+                                NaiveDate::from_ymd_opt(2023,9,1).unwrap(),
+                                NaiveTime::from_hms_opt(11,33,29).unwrap()),
+                            date_start: None,
+                            due: None,
+                            is_all_day: false,
+                            timezone: TimeZone::Floating,
+                            due_timezone: TimeZone::Floating,
+                            rrule: None,
+                            rdates: vec![],
+                            zoned_rdates: vec![],
+                            exdates: vec![],
+                            zoned_exdates: vec![],
+                            recurrence_id: None,
+                            recurrence_id_timezone: TimeZone::Floating,
                         };
                         assert_eq!(vtodo, vtodo_expected);
                     },
-                    None => assert!(false)
+                    None => panic!("No VTODO in the calendar")
                 }
             },
-            Err(_) => assert!(false)
+            Err(e) => panic!("Invalid calendar: {:?}", e)
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,303 @@
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use icalendar::parser::unfold;
+
+use crate::webdav::parsing::escape_text;
+
+// RFC 5545 lines should not be longer than 75 octets, excluding the line break
+const MAX_LINE_LENGTH: usize = 75;
+
+const PRODID: &str = "-//InfoPanel//info_panel//EN";
+
+/// A todo or event entered on the panel.
+///
+/// Times are UTC, dates of all-day entries are kept as they are.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NewEntry {
+    Todo {
+        summary: String,
+        description: String,
+        due: Option<When>,
+    },
+    Event {
+        summary: String,
+        description: String,
+        location: String,
+        start: When,
+        end: When,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum When {
+    Date(NaiveDate),
+    Utc(NaiveDateTime),
+}
+
+impl When {
+    // content line of a date or date-time property
+    fn to_line(self, name: &str) -> String {
+        match self {
+            When::Date(date) => format!("{};VALUE=DATE:{}", name, date.format("%Y%m%d")),
+            When::Utc(time) => format!("{}:{}", name, format_utc(time.and_utc())),
+        }
+    }
+}
+
+/// A UID unique enough for entries created on the panel, e.g. 20231012T093000Z-3f2a...@info-panel.
+pub(crate) fn generate_uid(now: DateTime<Utc>) -> String {
+    let random = RandomState::new().hash_one(now.timestamp_nanos_opt().unwrap_or_default());
+    format!("{}-{:016x}@info-panel", format_utc(now), random)
+}
+
+/// Serialises a new entry into a VCALENDAR object holding a single VTODO or VEVENT.
+pub(crate) fn serialise_entry(entry: &NewEntry, uid: &str, now: DateTime<Utc>) -> String {
+    let timestamp = format_utc(now);
+    let mut lines: Vec<String> = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:{}", PRODID),
+    ];
+
+    let (component, summary, description) = match entry {
+        NewEntry::Todo { summary, description, .. } => ("VTODO", summary, description),
+        NewEntry::Event { summary, description, .. } => ("VEVENT", summary, description),
+    };
+
+    lines.push(format!("BEGIN:{}", component));
+    lines.push(format!("UID:{}", uid));
+    lines.push(format!("DTSTAMP:{}", timestamp));
+    lines.push(format!("CREATED:{}", timestamp));
+    lines.push(format!("LAST-MODIFIED:{}", timestamp));
+    lines.push(format!("SUMMARY:{}", escape_text(summary)));
+    if !description.is_empty() {
+        lines.push(format!("DESCRIPTION:{}", escape_text(description)));
+    }
+
+    match entry {
+        NewEntry::Todo { due, .. } => {
+            lines.push("STATUS:NEEDS-ACTION".to_string());
+            if let Some(due) = due {
+                lines.push(due.to_line("DUE"));
+            }
+        },
+        NewEntry::Event { location, start, end, .. } => {
+            if !location.is_empty() {
+                lines.push(format!("LOCATION:{}", escape_text(location)));
+            }
+            lines.push(start.to_line("DTSTART"));
+            lines.push(end.to_line("DTEND"));
+        },
+    }
+
+    lines.push(format!("END:{}", component));
+    lines.push("END:VCALENDAR".to_string());
+    fold_lines(&lines)
+}
+
+/// Marks the todo with `uid` as completed and returns the changed iCalendar text.
+///
+/// Sets STATUS, COMPLETED, PERCENT-COMPLETE, LAST-MODIFIED and DTSTAMP and bumps SEQUENCE. All
+/// other lines, including unknown properties and other components, are kept as they are.
+/// Recurring todos are completed as a whole.
+pub(crate) fn complete_todo(ics: &str, uid: &str, now: DateTime<Utc>) -> Result<String, String> {
+    let unfolded = unfold(ics);
+    let lines: Vec<&str> = unfolded.lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    let (begin, end) = find_master(&lines, "VTODO", uid)
+        .ok_or_else(|| format!("No todo with UID '{}' in calendar object", uid))?;
+
+    let replaced = ["STATUS", "COMPLETED", "PERCENT-COMPLETE", "LAST-MODIFIED", "DTSTAMP", "SEQUENCE"];
+    let mut sequence: u32 = 0;
+    let mut todo: Vec<String> = Vec::new();
+    let mut depth: usize = 0;
+
+    for line in &lines[begin + 1..end] {
+        let name = property_name(line);
+        if name == "BEGIN" {
+            depth += 1;
+        } else if name == "END" {
+            depth -= 1;
+        } else if depth == 0 && replaced.contains(&name.as_str()) {
+            if name == "SEQUENCE" {
+                sequence = property_value(line).trim().parse().unwrap_or_default();
+            }
+            continue;
+        }
+        todo.push(line.to_string());
+    }
+
+    let timestamp = format_utc(now);
+    let changed = [
+        format!("DTSTAMP:{}", timestamp),
+        format!("LAST-MODIFIED:{}", timestamp),
+        format!("SEQUENCE:{}", sequence + 1),
+        "STATUS:COMPLETED".to_string(),
+        format!("COMPLETED:{}", timestamp),
+        "PERCENT-COMPLETE:100".to_string(),
+    ];
+
+    // properties go before nested components like VALARM
+    let position = todo.iter()
+        .position(|line| property_name(line) == "BEGIN")
+        .unwrap_or(todo.len());
+    todo.splice(position..position, changed);
+
+    let mut output: Vec<String> = lines[..=begin].iter().map(|line| line.to_string()).collect();
+    output.extend(todo);
+    output.extend(lines[end..].iter().map(|line| line.to_string()));
+
+    Ok(fold_lines(&output))
+}
+
+/// Joins content lines with CRLF and folds those longer than 75 octets.
+pub(crate) fn fold_lines(lines: &[String]) -> String {
+    let mut ics = String::new();
+    for line in lines {
+        let mut length = 0;
+        for character in line.chars() {
+            // continuation lines start with a space, which counts towards their length
+            if length + character.len_utf8() > MAX_LINE_LENGTH {
+                ics.push_str("\r\n ");
+                length = 1;
+            }
+            ics.push(character);
+            length += character.len_utf8();
+        }
+        ics.push_str("\r\n");
+    }
+    ics
+}
+
+/// Formats a time as UTC DATE-TIME, e.g. 20231012T093000Z.
+pub(crate) fn format_utc(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+// Index of the BEGIN and END line of the component with the given UID that isn't an override of
+// a single instance.
+fn find_master(lines: &[&str], component: &str, uid: &str) -> Option<(usize, usize)> {
+    let begin_line = format!("BEGIN:{}", component);
+    let end_line = format!("END:{}", component);
+
+    let mut i = 0;
+    while i < lines.len() {
+        if !lines[i].eq_ignore_ascii_case(&begin_line) {
+            i += 1;
+            continue;
+        }
+
+        let begin = i;
+        let mut depth: usize = 0;
+        let mut found_uid = false;
+        let mut is_override = false;
+
+        i += 1;
+        while i < lines.len() && !(depth == 0 && lines[i].eq_ignore_ascii_case(&end_line)) {
+            match property_name(lines[i]).as_str() {
+                "BEGIN" => depth += 1,
+                "END" => depth = depth.saturating_sub(1),
+                "UID" if depth == 0 => found_uid = property_value(lines[i]) == uid,
+                "RECURRENCE-ID" if depth == 0 => is_override = true,
+                _default => (),
+            }
+            i += 1;
+        }
+
+        if found_uid && !is_override && i < lines.len() {
+            return Some((begin, i));
+        }
+        i += 1;
+    }
+    None
+}
+
+// Name of a content line, i.e. everything before the first parameter or the value.
+fn property_name(line: &str) -> String {
+    line.split([';', ':']).next().unwrap_or_default().trim().to_ascii_uppercase()
+}
+
+// Value of a content line. Colons in quoted parameter values don't end the parameters.
+fn property_value(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, character) in line.char_indices() {
+        match character {
+            '"' => quoted = !quoted,
+            ':' if !quoted => return &line[i + 1..],
+            _default => (),
+        }
+    }
+    ""
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use chrono::{NaiveDate, TimeZone, Utc};
+
+    use crate::webdav::calendar::Calendar;
+    use crate::webdav::calendar::writing::{complete_todo, generate_uid, NewEntry, serialise_entry, When};
+
+    #[test]
+    fn todo_completion() {
+        let mut file = File::open("data/test/vtodo_tree.ics").unwrap();
+        let mut ics = String::new();
+        file.read_to_string(&mut ics).unwrap();
+
+        let now = Utc.with_ymd_and_hms(2023, 10, 12, 9, 30, 0).unwrap();
+        let completed = complete_todo(&ics, "windows", now).unwrap();
+
+        assert!(completed.lines().all(|line| line.len() <= 76));
+        assert!(completed.contains("UID:windows\r\nSUMMARY:Fenster putzen\r\nPRIORITY:5\r\nDUE:20231012T180000\r\n\
+                                    DTSTAMP:20231012T093000Z\r\nLAST-MODIFIED:20231012T093000Z\r\nSEQUENCE:1\r\n\
+                                    STATUS:COMPLETED\r\nCOMPLETED:20231012T093000Z\r\nPERCENT-COMPLETE:100\r\nEND:VTODO"));
+
+        let calendar = Calendar::new("tasks".to_string(), &[completed]);
+        assert_eq!(calendar.todos.len(), 7);
+        assert!(calendar.tasks().iter().all(|task| task.todo.uid != "windows"));
+
+        assert!(complete_todo(&ics, "unknown", now).is_err());
+    }
+
+    #[test]
+    fn entry_serialisation() {
+        let now = Utc.with_ymd_and_hms(2023, 10, 12, 9, 30, 0).unwrap();
+        let uid = generate_uid(now);
+        assert!(uid.starts_with("20231012T093000Z-"));
+        assert_ne!(uid, generate_uid(now));
+
+        let todo = NewEntry::Todo {
+            summary: "Milch, Butter; Brot".to_string(),
+            description: "".to_string(),
+            due: Some(When::Date(NaiveDate::from_ymd_opt(2023, 10, 13).unwrap())),
+        };
+        let event = NewEntry::Event {
+            summary: "Zahnarzt".to_string(),
+            description: "Kontrolle\nmit Röntgen".to_string(),
+            location: "Praxis Dr. Müller".to_string(),
+            start: When::Utc(NaiveDate::from_ymd_opt(2023, 10, 20).unwrap().and_hms_opt(8, 0, 0).unwrap()),
+            end: When::Utc(NaiveDate::from_ymd_opt(2023, 10, 20).unwrap().and_hms_opt(8, 30, 0).unwrap()),
+        };
+
+        let todo_ics = serialise_entry(&todo, "todo-1", now);
+        assert!(todo_ics.contains("SUMMARY:Milch\\, Butter\\; Brot\r\n"));
+        assert!(todo_ics.contains("DUE;VALUE=DATE:20231013\r\n"));
+
+        let event_ics = serialise_entry(&event, "event-1", now);
+        assert!(event_ics.contains("DTSTART:20231020T080000Z\r\nDTEND:20231020T083000Z\r\n"));
+
+        let calendar = Calendar::new("new".to_string(), &[todo_ics, event_ics]);
+        assert_eq!(calendar.todos[0].summary, "Milch, Butter; Brot");
+        assert_eq!(calendar.todos[0].due, Some(NaiveDate::from_ymd_opt(2023, 10, 13).unwrap().and_hms_opt(0, 0, 0).unwrap()));
+        assert_eq!(calendar.events[0].uid, "event-1");
+        assert_eq!(calendar.events[0].desc, "Kontrolle\nmit Röntgen");
+        assert_eq!(calendar.events[0].location, "Praxis Dr. Müller");
+    }
+}
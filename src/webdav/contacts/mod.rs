@@ -0,0 +1,171 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use chrono::{Duration, NaiveDate};
+use log::{error, warn};
+
+use crate::offline_cache::OfflineCache;
+use crate::webdav::connection::Connection;
+use crate::webdav::contacts::vcard::VCard;
+
+pub mod vcard;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnniversaryKind {
+    Birthday,
+    Anniversary,
+}
+
+/// The next time a birthday or anniversary of a contact comes round.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anniversary {
+    pub name: String,
+    pub kind: AnniversaryKind,
+    pub date: NaiveDate,
+    /// Years completed on `date`, if the year is known.
+    pub age: Option<i32>,
+}
+
+/// Every vCard of an address book.
+///
+/// Address books live next to the calendars on the same server, e.g.
+/// `https://cloud.example.org/remote.php/dav/addressbooks/users/<user>`, which is configured
+/// with `addressbook_url=`.
+pub(crate) fn read_address_book(conf: &str, address_book: &str) -> Result<Vec<VCard>, String> {
+    let downloaded = download_address_book(conf, address_book);
+
+    // the last download is used while the server can't be reached
+    let vcf = match OfflineCache::shared() {
+        Some(cache) => cache.keep_or_load(&format!("address_book_{}", address_book), downloaded)?,
+        None => {
+            warn!("No offline cache");
+            downloaded?
+        }
+    };
+    Ok(VCard::from_vcf(&vcf))
+}
+
+// every vCard of an address book in one text, an error if any can't be downloaded
+fn download_address_book(conf: &str, address_book: &str) -> Result<String, String> {
+    let path_config = format!("data/{}", conf);
+    let mut connection = Connection::new(&path_config)?;
+    connection.url = read_address_book_url(&path_config)
+        .ok_or_else(|| format!("No address book URL configured in '{}'", path_config))?;
+
+    let mut vcf = String::new();
+    for response in connection.get_responses(&format!("//{}", address_book))? {
+        if !response.href.ends_with("vcf") {
+            continue;
+        }
+
+        let path = format!("//{}/{}", address_book, response.ical_file);
+        // a partial address book mustn't replace the last complete one
+        let card = connection.get_ics_file(&path)
+            .map_err(|e| format!("Error downloading '{}': {}", response.ical_file, e))?;
+        vcf.push_str(card.trim_end());
+        vcf.push_str("\r\n");
+    }
+    Ok(vcf)
+}
+
+/// Every vCard of the address books listed with `addressbooks=`. Address books that can't be
+/// read are logged and skipped.
+pub(crate) fn read_contacts(conf: &str) -> Vec<VCard> {
+    let mut cards: Vec<VCard> = Vec::new();
+    for address_book in read_address_book_names(&format!("data/{}", conf)) {
+        match read_address_book(conf, &address_book) {
+            Ok(address_book_cards) => cards.extend(address_book_cards),
+            Err(e) => error!("Skipping address book '{}': {}", address_book, e),
+        }
+    }
+    cards
+}
+
+/// Birthdays and anniversaries from `from` on for `days` days, soonest first.
+pub(crate) fn upcoming(cards: &[VCard], from: NaiveDate, days: i64) -> Vec<Anniversary> {
+    let until = from + Duration::days(days);
+
+    let mut anniversaries: Vec<Anniversary> = Vec::new();
+    for card in cards {
+        let dates = [(AnniversaryKind::Birthday, card.birthday), (AnniversaryKind::Anniversary, card.anniversary)];
+
+        for (kind, partial_date) in dates {
+            let partial_date = match partial_date {
+                Some(partial_date) => partial_date,
+                None => continue,
+            };
+
+            match partial_date.next_occurrence(from) {
+                Some(date) if date < until => anniversaries.push(Anniversary {
+                    name: card.name.clone(),
+                    kind,
+                    date,
+                    age: partial_date.years_on(date).filter(|age| *age > 0),
+                }),
+                _default => (),
+            }
+        }
+    }
+
+    anniversaries.sort_by(|a, b| a.date.cmp(&b.date).then_with(|| a.name.cmp(&b.name)));
+    anniversaries
+}
+
+fn read_address_book_url(path_config: &str) -> Option<String> {
+    let file = File::open(path_config).ok()?;
+
+    let reader = BufReader::new(file);
+    for line in reader.lines().map_while(Result::ok) {
+        if let Some(("addressbook_url", value)) = line.split_once('=') {
+            return Some(value.trim().to_string()).filter(|url| !url.is_empty());
+        }
+    }
+    None
+}
+
+/// The address books configured with `addressbooks=`.
+pub(crate) fn read_address_book_names(path_config: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    if let Ok(file) = File::open(path_config) {
+        let reader = BufReader::new(file);
+        for line in reader.lines().map_while(Result::ok) {
+            if let Some(("addressbooks", value)) = line.split_once('=') {
+                names.extend(value.split(',')
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty()));
+            }
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::NaiveDate;
+
+    use crate::webdav::contacts::{Anniversary, AnniversaryKind, upcoming};
+    use crate::webdav::contacts::vcard::VCard;
+
+    #[test]
+    fn upcoming_anniversaries() {
+        let cards = VCard::from_vcf(&fs::read_to_string("data/test/contacts.vcf").unwrap());
+        let date = |month: u32, day: u32| NaiveDate::from_ymd_opt(2023, month, day).unwrap();
+
+        let anniversaries = upcoming(&cards, date(10, 14), 7);
+
+        assert_eq!(anniversaries, vec![
+            Anniversary { name: "Anna Schmidt".to_string(), kind: AnniversaryKind::Birthday, date: date(10, 14), age: Some(38) },
+            Anniversary { name: "Max Müller".to_string(), kind: AnniversaryKind::Birthday, date: date(10, 16), age: None },
+            Anniversary { name: "Anna Schmidt".to_string(), kind: AnniversaryKind::Anniversary, date: date(10, 20), age: Some(13) },
+        ]);
+
+        // the birthday of yesterday comes round next year
+        let next_year = upcoming(&cards, date(10, 14), 366);
+        assert_eq!(next_year.last().unwrap().name, "Oma");
+        assert_eq!(next_year.last().unwrap().date, NaiveDate::from_ymd_opt(2024, 10, 13).unwrap());
+        assert!(next_year.iter().any(|anniversary| anniversary.name == "Leap Year" && anniversary.date == NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()));
+    }
+}
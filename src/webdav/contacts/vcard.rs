@@ -0,0 +1,191 @@
+use chrono::{Datelike, NaiveDate};
+
+use crate::webdav::parsing::unescape_text;
+
+/// A date of a vCard, where the year may be unknown, e.g. `--1016` or `1604-10-16` with
+/// `X-APPLE-OMIT-YEAR=1604`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PartialDate {
+    pub year: Option<i32>,
+    pub month: u32,
+    pub day: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VCard {
+    pub uid: String,
+    pub name: String,
+    pub birthday: Option<PartialDate>,
+    pub anniversary: Option<PartialDate>,
+}
+
+impl VCard {
+    /// Every vCard of a text, e.g. of a .vcf file holding a whole address book.
+    pub(crate) fn from_vcf(vcf: &str) -> Vec<Self> {
+        let unfolded = vcf.replace("\r\n ", "").replace("\r\n\t", "").replace("\n ", "").replace("\n\t", "");
+
+        let mut cards: Vec<VCard> = Vec::new();
+        let mut current: Option<VCard> = None;
+        let mut structured_name = String::new();
+
+        for line in unfolded.lines().map(|line| line.trim_end_matches('\r')) {
+            let (name, params, value) = match split_line(line) {
+                Some(parts) => parts,
+                None => continue,
+            };
+
+            match name.as_str() {
+                "BEGIN" if value.eq_ignore_ascii_case("VCARD") => {
+                    current = Some(VCard { uid: String::new(), name: String::new(), birthday: None, anniversary: None });
+                    structured_name.clear();
+                },
+                "END" if value.eq_ignore_ascii_case("VCARD") => {
+                    if let Some(mut card) = current.take() {
+                        // FN is required, but some clients only write N
+                        if card.name.is_empty() {
+                            card.name = structured_name.clone();
+                        }
+                        cards.push(card);
+                    }
+                },
+                _default => if let Some(card) = current.as_mut() {
+                    match name.as_str() {
+                        "UID" => card.uid = value.to_string(),
+                        "FN" => card.name = unescape_text(value),
+                        "N" => structured_name = format_structured_name(value),
+                        "BDAY" => card.birthday = parse_partial_date(value, params),
+                        // Apple writes anniversaries as X-ANNIVERSARY
+                        "ANNIVERSARY" | "X-ANNIVERSARY" => card.anniversary = parse_partial_date(value, params),
+                        _default => (),
+                    }
+                },
+            }
+        }
+        cards
+    }
+}
+
+impl PartialDate {
+    /// The first date on or after `from` the date recurs on. 29 February is celebrated on
+    /// 28 February in other years.
+    pub(crate) fn next_occurrence(&self, from: NaiveDate) -> Option<NaiveDate> {
+        let in_year = |year: i32| NaiveDate::from_ymd_opt(year, self.month, self.day)
+            .or_else(|| NaiveDate::from_ymd_opt(year, self.month, self.day - 1));
+
+        let this_year = in_year(from.year())?;
+        if this_year >= from {
+            Some(this_year)
+        } else {
+            in_year(from.year() + 1)
+        }
+    }
+
+    /// The number of years since the date on `date`, if the year is known.
+    pub(crate) fn years_on(&self, date: NaiveDate) -> Option<i32> {
+        self.year.map(|year| date.year() - year)
+    }
+}
+
+// name in upper case, parameters and value of a content line, e.g. "item1.BDAY;VALUE=date:--1016"
+fn split_line(line: &str) -> Option<(String, &str, &str)> {
+    let (head, value) = line.split_once(':')?;
+    let (name, params) = head.split_once(';').unwrap_or((head, ""));
+
+    // the group prefix of grouped properties doesn't matter here
+    let name = name.rsplit('.').next().unwrap_or(name);
+    Some((name.trim().to_ascii_uppercase(), params, value.trim()))
+}
+
+// "Schmidt;Anna;;;" becomes "Anna Schmidt"
+fn format_structured_name(value: &str) -> String {
+    let parts: Vec<String> = value.split(';').map(unescape_text).collect();
+    let given = parts.get(1).map(|part| part.as_str()).unwrap_or_default();
+    let family = parts.first().map(|part| part.as_str()).unwrap_or_default();
+
+    format!("{} {}", given, family).trim().to_string()
+}
+
+/// Reads `1985-10-14`, `19851014`, `--1016`, `--10-16` and date-times like
+/// `1985-10-14T00:00:00Z`.
+pub(crate) fn parse_partial_date(value: &str, params: &str) -> Option<PartialDate> {
+    let value = value.split('T').next().unwrap_or(value).trim();
+    // slicing by byte needs plain digits
+    if !value.chars().all(|character| character.is_ascii_digit() || character == '-') {
+        return None;
+    }
+
+    let (year, month_day) = match value.strip_prefix("--") {
+        Some(month_day) => (None, month_day.replace('-', "")),
+        None => {
+            let digits = value.replace('-', "");
+            if digits.len() != 8 {
+                return None;
+            }
+            (Some(digits[..4].parse::<i32>().ok()?), digits[4..].to_string())
+        }
+    };
+
+    if month_day.len() != 4 {
+        return None;
+    }
+    let month = month_day[..2].parse::<u32>().ok()?;
+    let day = month_day[2..].parse::<u32>().ok()?;
+
+    // leap year 2000 accepts 29 February
+    NaiveDate::from_ymd_opt(2000, month, day)?;
+
+    // Apple stores unknown years as 1604
+    let omitted_year = params.split(';')
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.eq_ignore_ascii_case("X-APPLE-OMIT-YEAR"))
+        .and_then(|(_, year)| year.parse::<i32>().ok());
+    let year = year.filter(|year| Some(*year) != omitted_year);
+
+    Some(PartialDate { year, month, day })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use chrono::NaiveDate;
+
+    use crate::webdav::contacts::vcard::{parse_partial_date, PartialDate, VCard};
+
+    #[test]
+    fn vcard_parsing() {
+        let mut file = File::open("data/test/contacts.vcf").unwrap();
+        let mut vcf = String::new();
+        file.read_to_string(&mut vcf).unwrap();
+
+        let cards = VCard::from_vcf(&vcf);
+
+        assert_eq!(cards.len(), 5);
+        assert_eq!(cards[0], VCard {
+            uid: "anna".to_string(),
+            name: "Anna Schmidt".to_string(),
+            birthday: Some(PartialDate { year: Some(1985), month: 10, day: 14 }),
+            anniversary: Some(PartialDate { year: Some(2010), month: 10, day: 20 }),
+        });
+        assert_eq!(cards[1].birthday, Some(PartialDate { year: None, month: 10, day: 16 }));
+        assert_eq!(cards[2].birthday, Some(PartialDate { year: None, month: 10, day: 13 }));
+        assert_eq!(cards[3].name, "Leap Year");
+        assert_eq!(cards[4].birthday, None);
+    }
+
+    #[test]
+    fn partial_dates() {
+        assert_eq!(parse_partial_date("--10-16", ""), Some(PartialDate { year: None, month: 10, day: 16 }));
+        assert_eq!(parse_partial_date("1985-10-14T00:00:00Z", ""), Some(PartialDate { year: Some(1985), month: 10, day: 14 }));
+        assert_eq!(parse_partial_date("--1332", ""), None);
+        // multibyte characters mustn't be sliced
+        assert_eq!(parse_partial_date("--1ä6", ""), None);
+        assert_eq!(parse_partial_date("19ä5101", ""), None);
+
+        let leap = PartialDate { year: Some(2000), month: 2, day: 29 };
+        let from = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+        assert_eq!(leap.next_occurrence(from), NaiveDate::from_ymd_opt(2023, 2, 28));
+        assert_eq!(leap.years_on(NaiveDate::from_ymd_opt(2023, 2, 28).unwrap()), Some(23));
+    }
+}
@@ -1,87 +1,281 @@
-use icalendar::Calendar as ICalendar;
-use icalendar::parser::{read_calendar as read_icalendar, unfold};
-use log::error;
-use reqwest::Error as ReqwestError;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+
+use crate::offline_cache::OfflineCache;
 use crate::webdav::calendar::Calendar;
-use crate::webdav::calendar::vevent::VEvent;
-use crate::webdav::calendar::vtimezone::VTimezone;
-use crate::webdav::calendar::vtodo::VTodo;
-use crate::webdav::connection::Connection;
+use crate::webdav::calendar::vtimezone::{read_display_zone, VTimezone};
+use crate::webdav::calendar::writing;
+use crate::webdav::calendar::writing::NewEntry;
+use crate::webdav::connection::{Connection, Precondition, PutOutcome};
+use crate::webdav::parsing::unescape_xml;
 use crate::webdav::response::prop::Prop;
 
 pub mod parsing;
 pub mod response;
+mod auth;
 mod connection;
+pub mod contacts;
 pub mod calendar;
+mod tls;
+
+/// Reads the connection of a config, which resolves its secrets before the first request needs them.
+pub(crate) fn resolve_secrets(conf: &str) -> Result<(), String> {
+    Connection::new(&format!("data/{}", conf)).map(|_| ())
+}
 
 pub(crate) fn read_calendar(conf: &str, calendar_name: &str) -> Option<Calendar>{
     // connection
     let path_config = format!("data/{}",conf);
-    let connection: Connection = match Connection::new(&path_config) {
-        Ok(connection) => connection,
+    let downloaded = Connection::new(&path_config)
+        .map_err(|e| format!("Error creating connection to calendar based on config '{}': {}", path_config, e))
+        .and_then(|connection| download_calendar(&connection, calendar_name));
+
+    // the last download is used while the server can't be reached
+    let snapshot = match OfflineCache::shared() {
+        Some(cache) => cache.keep_or_load(&format!("calendar_{}", calendar_name), downloaded),
+        None => {
+            warn!("No offline cache");
+            downloaded
+        }
+    };
+    let snapshot = match snapshot.and_then(|snapshot| json::parse(&snapshot).map_err(|e| e.to_string())) {
+        Ok(snapshot) => snapshot,
         Err(e) => {
-            error!("Error creating connection to calendar based on config '{}': {}", path_config, e);
+            error!("Couldn't read calendar '{}': {}", calendar_name, e);
             return None;
         }
     };
 
-    let mut icals: Vec<ICalendar> = Vec::new();
+    // put every component of the ics files in event, todo or timezone list
+    let mut calendar = Calendar::new(calendar_name.to_string(), &[]);
+    for file in snapshot["files"].members() {
+        let path = file["path"].as_str().unwrap_or_default();
+        if let Err(e) = calendar.add_resource(path, file["e_tag"].as_str().unwrap_or_default(), file["ics"].as_str().unwrap_or_default()) {
+            error!("Skipping '{}' of calendar '{}': {}", path, calendar_name, e);
+        }
+    }
+
+    // timezones of the ics files take precedence over the calendar's default timezone
+    for timezone in VTimezone::from_ics(snapshot["calendar_timezone"].as_str().unwrap_or_default()) {
+        if !calendar.timezones.iter().any(|known| known.tzid == timezone.tzid) {
+            calendar.timezones.push(timezone);
+        }
+    }
+    calendar.display_zone = read_display_zone(&path_config);
+    calendar.display_name = snapshot["display_name"].as_str().unwrap_or_default().to_string();
+    calendar.color = snapshot["color"].as_str().unwrap_or_default().to_string();
+
+    Some(calendar)
+}
+
+// Downloads the properties and every ics file of a calendar, as JSON so it can be kept in the
+// offline cache as it is. Fails if any file can't be downloaded.
+fn download_calendar(connection: &Connection, calendar_name: &str) -> Result<String, String> {
+    let mut snapshot = json::object!{
+        display_name: "",
+        color: "",
+        calendar_timezone: "",
+        files: [],
+    };
 
     // iterate responses from xml
-    let responses: Vec<response::Response> = connection.get_responses(&format!("//{}",calendar_name)).unwrap();
+    let responses: Vec<response::Response> = connection.get_responses(&format!("//{}",calendar_name))?;
     for response in responses {
 
         // response links an ics file
         if response.href.ends_with("ics") {
-            let ics_string: Result<String, ReqwestError> = connection.get_ics_file(&format!("//{}/{}", calendar_name, response.ical_file));
+            let path = format!("//{}/{}", calendar_name, response.ical_file);
+            // a partial calendar mustn't replace the last complete one
+            let ics_string = connection.get_ics_file(&path)
+                .map_err(|e| format!("Error downloading '{}': {}", response.ical_file, e))?;
 
-            match ics_string {
-                Ok(ics_string) => {
-                    let unfolded = unfold(&ics_string);
-                    let result = read_icalendar(&unfolded);
-
-                    if result.is_ok() {
-                        // ICalender has two calendar classes. A parser and an actual class.
-                        // into() turns the parser class into the actual one.
-                        icals.push(result.unwrap().into());
-                    }
-
-                }
-                Err(_) => ()
-            }
+            snapshot["files"].push(json::object!{
+                path: path,
+                e_tag: unescape_xml(&response.prop.e_tag),
+                ics: ics_string,
+            }).map_err(|e| e.to_string())?;
         } else { // is main response
             let prop: Prop = response.prop;
-            let string_timezone = prop.calendar_timezone;
-            
-            
+            snapshot["calendar_timezone"] = prop.calendar_timezone.into();
+            snapshot["display_name"] = prop.displayname.into();
+            snapshot["color"] = prop.calendar_color.into();
         }
     }
+    Ok(snapshot.dump())
+}
 
-    // put icals in event or todo list
-    let mut events: Vec<VEvent> = Vec::new();
-    let mut todos: Vec<VTodo> = Vec::new();
+/// The calendar configured with `tasks=` whose todos are shown in the task table.
+pub(crate) fn read_tasks(conf: &str) -> Result<Calendar, String> {
+    let path_config = format!("data/{}", conf);
+    let calendar_name = read_task_calendar_name(&path_config)
+        .ok_or_else(|| format!("No task calendar configured in '{}'", path_config))?;
 
-    for ical in icals {
-        let event = VEvent::new(&ical);
-        match event {
-            Some(event) => events.push(event),
-            None => ()
+    read_calendar(conf, &calendar_name)
+        .ok_or_else(|| format!("Couldn't read task calendar '{}'", calendar_name))
+}
+
+/// The calendar configured with `tasks=`, if any.
+pub(crate) fn read_task_calendar_name(path_config: &str) -> Option<String> {
+    let file = File::open(path_config).ok()?;
+
+    let reader = BufReader::new(file);
+    for line in reader.lines().map_while(Result::ok) {
+        if let Some(("tasks", value)) = line.split_once('=') {
+            return Some(value.trim().to_string()).filter(|name| !name.is_empty());
         }
+    }
+    None
+}
 
-        let todo = VTodo::new(&ical);
-        match todo {
-            Some(todo) => todos.push(todo),
-            None => ()
+/// Calendars new entries may be added to, i.e. those listed with `writable=` and the task calendar.
+pub(crate) fn read_writable_calendars(conf: &str) -> Vec<String> {
+    let path_config = format!("data/{}", conf);
+    let mut calendars: Vec<String> = Vec::new();
+
+    if let Ok(file) = File::open(&path_config) {
+        let reader = BufReader::new(file);
+        for line in reader.lines().map_while(Result::ok) {
+            if let Some(("writable", value)) = line.split_once('=') {
+                calendars.extend(value.split(',')
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| !name.is_empty()));
+            }
         }
     }
 
-    Some(
-        Calendar{
-            name: calendar_name.to_string(),
-            events,
-            todos,
-            timezone: VTimezone::new()
+    if let Some(tasks) = read_task_calendar_name(&path_config) {
+        if !calendars.contains(&tasks) {
+            calendars.push(tasks);
         }
-    )
-}
\ No newline at end of file
+    }
+    calendars
+}
+
+/// Creates a new todo or event in a calendar and returns its UID.
+///
+/// The file is only written if it doesn't exist yet, so nothing on the server is overwritten.
+pub(crate) fn add_entry(conf: &str, calendar_name: &str, entry: &NewEntry) -> Result<String, String> {
+    if !read_writable_calendars(conf).iter().any(|name| name == calendar_name) {
+        return Err(format!("Calendar '{}' isn't writable", calendar_name));
+    }
+
+    let connection = Connection::new(&format!("data/{}", conf))?;
+
+    let now = Utc::now();
+    let uid = writing::generate_uid(now);
+    let path = format!("//{}/{}.ics", calendar_name, uid);
+    let ics = writing::serialise_entry(entry, &uid, now);
+
+    match connection.put_ics_file(&path, &ics, &Precondition::IfNoneMatch)? {
+        PutOutcome::Stored(_) => {
+            info!("Added '{}' to calendar '{}'", uid, calendar_name);
+            Ok(uid)
+        },
+        PutOutcome::Conflict => Err(format!("'{}' already exists on the server", path)),
+    }
+}
+
+/// Outcome of completing a todo shown on the panel.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Completion {
+    /// Completed now or already before.
+    Completed,
+    /// The file changed on the server since the todo was shown, nothing was written.
+    Conflict,
+}
+
+// what completing a todo takes, given the file as it is on the server now
+#[derive(Debug, PartialEq)]
+enum CompletionStep {
+    Done(Completion),
+    /// The completed file, to be written if the ETag still matches.
+    Write(String),
+}
+
+/// Marks a todo as completed and writes it back to the server.
+///
+/// `e_tag` is the ETag of `path` the todo was shown with. A todo completed on the server already
+/// is done. Otherwise, if the file changed since it was shown, or changes while writing it,
+/// nothing is written and the user has to look at the current version first.
+pub(crate) fn complete_todo(conf: &str, uid: &str, path: &str, e_tag: &str) -> Result<Completion, String> {
+    let path_config = format!("data/{}", conf);
+    let calendar_name = read_task_calendar_name(&path_config)
+        .ok_or_else(|| format!("No task calendar configured in '{}'", path_config))?;
+
+    // only files of the task calendar may be written
+    if !path.starts_with(&format!("//{}/", calendar_name)) || path.contains("..") || !path.ends_with(".ics") {
+        return Err(format!("'{}' isn't a file of task calendar '{}'", path, calendar_name));
+    }
+    if e_tag.is_empty() {
+        return Err(format!("No ETag of '{}' given, not overwriting it blindly", path));
+    }
+
+    let connection = Connection::new(&path_config)?;
+    let (ics, current_e_tag) = connection.get_ics_file_with_e_tag(path)?;
+    let completed = match completion_step(&ics, uid, path, e_tag, &current_e_tag, Utc::now())? {
+        CompletionStep::Done(completion) => return Ok(completion),
+        CompletionStep::Write(completed) => completed,
+    };
+
+    match connection.put_ics_file(path, &completed, &Precondition::IfMatch(current_e_tag))? {
+        PutOutcome::Stored(_) => {
+            info!("Completed todo '{}' in '{}'", uid, path);
+            Ok(Completion::Completed)
+        },
+        PutOutcome::Conflict => {
+            warn!("'{}' changed on the server while completing '{}'", path, uid);
+            Ok(Completion::Conflict)
+        },
+    }
+}
+
+fn completion_step(ics: &str, uid: &str, path: &str, e_tag: &str, current_e_tag: &str, now: DateTime<Utc>) -> Result<CompletionStep, String> {
+    let calendar = Calendar::new(String::new(), &[ics.to_string()]);
+    match calendar.todos.iter().find(|todo| todo.uid == uid && todo.recurrence_id.is_none()) {
+        None => return Err(format!("Todo '{}' doesn't exist in '{}'", uid, path)),
+        // e.g. tapped twice before the panel showed the completion
+        Some(todo) if todo.is_completed() => {
+            info!("Todo '{}' is already completed", todo.summary);
+            return Ok(CompletionStep::Done(Completion::Completed));
+        },
+        Some(_) => (),
+    }
+
+    if current_e_tag != e_tag {
+        warn!("'{}' changed on the server since it was shown, not completing '{}'", path, uid);
+        return Ok(CompletionStep::Done(Completion::Conflict));
+    }
+    writing::complete_todo(ics, uid, now).map(CompletionStep::Write)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::webdav::{Completion, completion_step, CompletionStep};
+
+    #[test]
+    fn todo_completed_twice() {
+        let ics = fs::read_to_string("data/test/vtodo_tree.ics").unwrap();
+        let now = Utc.with_ymd_and_hms(2023, 10, 12, 9, 30, 0).unwrap();
+        let path = "//tasks/tree.ics";
+
+        let completed = match completion_step(&ics, "windows", path, "\"1\"", "\"1\"", now).unwrap() {
+            CompletionStep::Write(completed) => completed,
+            step => panic!("Expected the todo to be written, got {:?}", step),
+        };
+
+        // the panel still shows the todo with the ETag before the completion
+        assert_eq!(completion_step(&completed, "windows", path, "\"1\"", "\"2\"", now),
+                   Ok(CompletionStep::Done(Completion::Completed)));
+        // an open todo changed by someone else is a conflict
+        assert_eq!(completion_step(&ics, "windows", path, "\"1\"", "\"2\"", now),
+                   Ok(CompletionStep::Done(Completion::Conflict)));
+        assert!(completion_step(&ics, "unknown", path, "\"1\"", "\"1\"", now).is_err());
+    }
+}
@@ -2,13 +2,15 @@ extern crate chrono;
 
 use std::borrow::Cow;
 
-use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
 use icalendar::Property;
+use icalendar::parser::Property as IProperty;
 use quick_xml::events::{BytesEnd, BytesStart, Event as QuickXmlEvent};
 use quick_xml::reader::Reader;
 
 use response::prop::Prop;
 
+use crate::webdav::calendar::vtimezone::TimeZone;
 use crate::webdav::response;
 
 pub fn extract_response_xml (string: &str) -> Result<Vec<Cow<str>>, String> {
@@ -94,7 +96,7 @@ pub fn parse_prop(string: &str) -> Result<Prop, String> {
     let displayname_start = BytesStart::new("d:displayname");
     let displayname_end = displayname_start.to_end().into_owned();
 
-    let calendar_timezone_start = BytesStart::new("cal:webdav-timezone");
+    let calendar_timezone_start = BytesStart::new("cal:calendar-timezone");
     let calendar_timezone_end = calendar_timezone_start.to_end().into_owned();
 
     let last_modified_start = BytesStart::new("d:getlastmodified");
@@ -117,6 +119,7 @@ pub fn parse_prop(string: &str) -> Result<Prop, String> {
         content_length: 0,
         e_tag: String::from(""),
         content_type: String::from(""),
+        calendar_color: String::from(""),
     };
 
     loop {
@@ -154,6 +157,11 @@ pub fn parse_prop(string: &str) -> Result<Prop, String> {
                         let inner_xml = reader.read_text(content_type_end.name()).unwrap();
                         prop.content_type = inner_xml.into_owned();
                     },
+                    // the prefix of the Apple namespace differs between servers
+                    _ if e.local_name().as_ref() == b"calendar-color" => {
+                        let inner_xml = reader.read_text(e.to_end().name()).unwrap();
+                        prop.calendar_color = inner_xml.trim().to_string();
+                    },
                     _ => (),
                 }
             }
@@ -163,17 +171,170 @@ pub fn parse_prop(string: &str) -> Result<Prop, String> {
     Ok(prop)
 }
 
+// ETags are read as they are written in the XML, i.e. with escaped quotes
+pub fn unescape_xml(text: &str) -> String {
+    match quick_xml::escape::unescape(text) {
+        Ok(unescaped) => unescaped.into_owned(),
+        Err(_) => text.to_string(),
+    }
+}
+
+// Converts the properties of a parsed component. Unlike icalendar's own components this keeps
+// properties that occur several times, like ATTENDEE or EXDATE.
+pub fn convert_properties(properties: &[IProperty]) -> Vec<Property> {
+    properties.iter()
+        .map(|property| Property::from(property.clone()))
+        .collect()
+}
+
 pub fn parse_date(property: &Property) -> NaiveDateTime {
+    parse_date_with_zone(property).0
+}
+
+// Returns the wall-clock time as written and the zone it was written in.
+// Dates (VALUE=DATE) are returned at midnight without a zone.
+pub fn parse_date_with_zone(property: &Property) -> (NaiveDateTime, TimeZone) {
+    parse_date_value(property.value(), property)
+}
+
+// EXDATE and RDATE may hold several comma separated values
+pub fn parse_dates(property: &Property) -> Vec<NaiveDateTime> {
+    parse_dates_with_zone(property).into_iter().map(|(date, _)| date).collect()
+}
+
+pub fn parse_dates_with_zone(property: &Property) -> Vec<(NaiveDateTime, TimeZone)> {
+    property.value()
+        .split(',')
+        .filter(|value| !value.trim().is_empty())
+        .map(|value| parse_date_value(value.trim(), property))
+        .collect()
+}
+
+fn parse_date_value(value: &str, property: &Property) -> (NaiveDateTime, TimeZone) {
+    let value = value.trim();
+
+    if is_date(property) || !value.contains('T') {
+        return (NaiveDateTime::new(parse_ymd(value), NaiveTime::default()), TimeZone::Floating)
+    }
 
-    //if it has parameters, its just a date
-    if property.params().len() > 0 {
+    if value.ends_with('Z') {
+        return (parse_ymd_hms(value), TimeZone::Utc)
+    }
 
-        return NaiveDateTime::new(
-            parse_ymd(property.value()),
-            NaiveTime::default())
+    let date_time = parse_ymd_hms(value);
+    match property.params().get("TZID") {
+        Some(tzid) => (date_time, TimeZone::Id(tzid.value().trim_matches('"').to_string())),
+        None => (date_time, TimeZone::Floating),
     }
+}
 
-    parse_ymd_hms(property.value())
+// dates carry VALUE=DATE, but some clients leave it out
+pub fn is_date(property: &Property) -> bool {
+    property.params().get("VALUE").is_some_and(|param| param.value() == "DATE") ||
+        !property.value().contains('T')
+}
+
+// resolves the escapes of TEXT values, e.g. "\\," into ","
+pub fn unescape_text(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+// escapes a TEXT value for writing, the reverse of `unescape_text`
+pub fn escape_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            },
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            _default => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// splits a comma separated TEXT list like CATEGORIES, escaped commas are part of the values
+pub fn split_text_list(value: &str) -> Vec<String> {
+    let mut values: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut escaped = false;
+
+    for c in value.chars() {
+        if c == ',' && !escaped {
+            values.push(unescape_text(&current));
+            current.clear();
+            continue;
+        }
+        escaped = c == '\\' && !escaped;
+        current.push(c);
+    }
+    values.push(unescape_text(&current));
+
+    values.into_iter()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+// parses durations like "P1D", "PT1H30M" or "-P1W"
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+
+    let chars = value.strip_prefix('P')?.chars();
+    let mut duration = Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in chars {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' => in_time = true,
+            unit => {
+                let amount: i64 = number.parse().ok()?;
+                number.clear();
+
+                // values too large for a duration are invalid
+                let part = match (unit, in_time) {
+                    ('W', false) => Duration::try_weeks(amount)?,
+                    ('D', false) => Duration::try_days(amount)?,
+                    ('H', true) => Duration::try_hours(amount)?,
+                    ('M', true) => Duration::try_minutes(amount)?,
+                    ('S', true) => Duration::try_seconds(amount)?,
+                    _ => return None,
+                };
+                duration = duration.checked_add(&part)?;
+            }
+        }
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(if negative { -duration } else { duration })
 }
 
 fn parse_ymd(date: &str) -> NaiveDate {
@@ -191,7 +352,7 @@ fn parse_ymd(date: &str) -> NaiveDate {
 }
 
 fn parse_ymd_hms(string: &str) -> NaiveDateTime {
-    match NaiveDateTime::parse_from_str(string, "%Y%m%dT%H%M%S%.fZ") {
+    match NaiveDateTime::parse_from_str(string.trim_end_matches('Z'), "%Y%m%dT%H%M%S%.f") {
         Ok(date) => {
             date
         }
@@ -211,8 +372,9 @@ mod tests {
     use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
     use icalendar::Property;
 
+    use crate::webdav::calendar::vtimezone::TimeZone;
     use crate::webdav::parsing;
-    use crate::webdav::parsing::{parse_date, parse_prop};
+    use crate::webdav::parsing::{parse_date, parse_date_with_zone, parse_duration, parse_prop};
     use crate::webdav::response::prop::Prop;
 
     fn get_xml(name: &str) -> String {
@@ -350,11 +512,24 @@ mod tests {
             last_modified: "Mon, 22 Aug 2022 18:10:09 GMT".parse().unwrap(),
             content_length: 465,
             e_tag: "&quot;a86c24c6146b1965dff7da97f2e433cf&quot;".parse().unwrap(),
-            content_type: "text/calendar; charset=utf-8; component=vevent".parse().unwrap() };
+            content_type: "text/calendar; charset=utf-8; component=vevent".parse().unwrap(),
+            calendar_color: String::from("") };
 
         assert_eq!(prop, expected_prop);
     }
 
+    #[test]
+    fn calendar_prop_parsing() {
+        let xml = get_xml("calendar.xml");
+        let xml_responses: Vec<Cow<str>> = parsing::extract_response_xml(&xml).unwrap();
+        let propstat = parsing::extract_propstat_xml(xml_responses[0].as_ref()).unwrap();
+
+        let prop: Prop = parse_prop(propstat.as_ref()).unwrap();
+
+        assert_eq!(prop.displayname, "Abfall");
+        assert_eq!(prop.calendar_color, "#D09E6D");
+    }
+
     #[test]
     fn date_parsing() {
         let input_vec: Vec<Property> = vec![
@@ -377,4 +552,35 @@ mod tests {
 
         assert_eq!(output_vec, expected_output_vec);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn duration_parsing() {
+        assert_eq!(parse_duration("PT1H30M"), Some(chrono::Duration::minutes(90)));
+        assert_eq!(parse_duration("-P1W"), Some(chrono::Duration::weeks(-1)));
+        assert_eq!(parse_duration("P1"), None);
+        // too large for a duration
+        assert_eq!(parse_duration("P9223372036854775807W"), None);
+        assert_eq!(parse_duration("P100000000000DT2562047788015H"), None);
+    }
+
+    #[test]
+    fn zoned_date_parsing() {
+        let input_vec: Vec<Property> = vec![
+            Property::from_str("DTSTART;TZID=Europe/Berlin:20231020T090000").unwrap(),
+            Property::from_str("DTSTART:20231020T090000").unwrap(),
+            Property::from_str("DTSTAMP:20231020T090000Z").unwrap(),
+            Property::from_str("DTSTART;VALUE=DATE:20231020").unwrap()];
+
+        let output_vec: Vec<(NaiveDateTime, TimeZone)> = input_vec.iter()
+            .map(parse_date_with_zone)
+            .collect();
+
+        let date_time = NaiveDate::from_ymd_opt(2023, 10, 20).unwrap().and_hms_opt(9, 0, 0).unwrap();
+        let date = NaiveDate::from_ymd_opt(2023, 10, 20).unwrap().and_time(NaiveTime::default());
+        assert_eq!(output_vec, vec![
+            (date_time, TimeZone::Id("Europe/Berlin".to_string())),
+            (date_time, TimeZone::Floating),
+            (date_time, TimeZone::Utc),
+            (date, TimeZone::Floating)]);
+    }
+}
@@ -7,6 +7,7 @@ pub struct Prop {
     pub content_length: u32,
     pub e_tag: String,
     pub content_type: String,
+    pub calendar_color: String,
 }
 
 impl PartialEq for Prop {
@@ -17,6 +18,7 @@ impl PartialEq for Prop {
             self.last_modified == other.last_modified &&
             self.content_length == other.content_length &&
             self.e_tag == other.e_tag &&
-            self.content_type == other.content_type
+            self.content_type == other.content_type &&
+            self.calendar_color == other.calendar_color
     }
 }
\ No newline at end of file
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, OnceLock};
+
+use log::info;
+use openssl::hash::MessageDigest;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use reqwest::{Certificate, Identity, Url};
+use reqwest::blocking::Client;
+
+use crate::http_client;
+use crate::secrets;
+
+// clients of every TLS setup in use, building one costs a handshake with pinned certificates
+static CLIENTS: OnceLock<Mutex<HashMap<String, Client>>> = OnceLock::new();
+
+/// TLS settings for self-hosted WebDAV servers.
+#[derive(Clone, Default, PartialEq)]
+pub struct TlsOptions {
+    /// PKCS#12 file, or PEM file of the certificate if `client_key` is set.
+    pub client_certificate: Option<String>,
+    pub client_certificate_password: Option<String>,
+    /// PEM file of the key of `client_certificate`.
+    pub client_key: Option<String>,
+    /// PEM file with the CAs to trust instead of those of the system.
+    pub ca_bundle: Option<String>,
+    /// SHA-256 fingerprint of a self-signed server certificate, the only certificate trusted then.
+    pub pinned_fingerprint: Option<String>,
+}
+
+// the password is left out, configs end up in log messages
+impl fmt::Debug for TlsOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TlsOptions")
+            .field("client_certificate", &self.client_certificate)
+            .field("client_certificate_password", &self.client_certificate_password.as_ref().map(|_| secrets::REDACTED))
+            .field("client_key", &self.client_key)
+            .field("ca_bundle", &self.ca_bundle)
+            .field("pinned_fingerprint", &self.pinned_fingerprint)
+            .finish()
+    }
+}
+
+impl TlsOptions {
+    pub(crate) fn new(values: &HashMap<String, String>) -> Result<Self, String> {
+        let value = |key: &str| values.get(key).map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+
+        Ok(TlsOptions {
+            client_certificate: value("client_certificate"),
+            client_certificate_password: value("client_certificate_password").map(|password| secrets::resolve(&password)).transpose()?,
+            client_key: value("client_key"),
+            ca_bundle: value("ca_bundle"),
+            pinned_fingerprint: value("pinned_fingerprint").map(|fingerprint| normalise_fingerprint(&fingerprint)).transpose()?,
+        })
+    }
+
+    /// The client for requests to `url`, the shared one without any settings.
+    pub(crate) fn client(&self, url: &str) -> Result<Client, String> {
+        if *self == TlsOptions::default() {
+            return Ok(http_client::blocking_client().clone());
+        }
+
+        let url = Url::parse(url).map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+        let key = format!("{} {:?} {:?} {:?} {:?} {:?}", url.origin().ascii_serialization(), self.client_certificate,
+                          self.client_certificate_password, self.client_key, self.ca_bundle, self.pinned_fingerprint);
+
+        let mut clients = CLIENTS.get_or_init(|| Mutex::new(HashMap::new())).lock()
+            .map_err(|e| format!("TLS clients unavailable: {}", e))?;
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+
+        let client = self.build_client(&url)?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    fn build_client(&self, url: &Url) -> Result<Client, String> {
+        let mut builder = http_client::blocking_client_builder();
+
+        if let Some(path) = &self.client_certificate {
+            let certificate = fs::read(path).map_err(|e| format!("Failed to read client certificate '{}': {}", path, e))?;
+            let identity = match &self.client_key {
+                Some(key_path) => {
+                    let key = fs::read(key_path).map_err(|e| format!("Failed to read client key '{}': {}", key_path, e))?;
+                    Identity::from_pkcs8_pem(&certificate, &key)
+                },
+                None => Identity::from_pkcs12_der(&certificate, self.client_certificate_password.as_deref().unwrap_or_default()),
+            };
+            builder = builder.identity(identity.map_err(|e| format!("Invalid client certificate '{}': {}", path, http_client::describe(&e)))?);
+        }
+
+        if let Some(fingerprint) = &self.pinned_fingerprint {
+            // only the pinned certificate is trusted, whatever name it was issued for
+            let certificate = fetch_pinned_certificate(url, fingerprint)?;
+            builder = builder
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(certificate)
+                .danger_accept_invalid_hostnames(true);
+        } else if let Some(path) = &self.ca_bundle {
+            let bundle = fs::read(path).map_err(|e| format!("Failed to read CA bundle '{}': {}", path, e))?;
+            let certificates = Certificate::from_pem_bundle(&bundle)
+                .map_err(|e| format!("Invalid CA bundle '{}': {}", path, http_client::describe(&e)))?;
+            if certificates.is_empty() {
+                return Err(format!("CA bundle '{}' holds no certificate", path));
+            }
+
+            builder = builder.tls_built_in_root_certs(false);
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+
+        builder.build().map_err(|e| format!("Failed to create HTTP client: {}", http_client::describe(&e)))
+    }
+}
+
+/// Lower case hex without separators, e.g. of "SHA256:AB:CD:…" as printed by
+/// `openssl x509 -noout -fingerprint -sha256`.
+pub(crate) fn normalise_fingerprint(fingerprint: &str) -> Result<String, String> {
+    let fingerprint = fingerprint.trim();
+    let hex: String = fingerprint.split_once('=').map_or(fingerprint, |(_, hex)| hex)
+        .trim_start_matches("sha256:")
+        .trim_start_matches("SHA256:")
+        .chars()
+        .filter(|character| *character != ':' && !character.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    if hex.len() != 64 || !hex.chars().all(|character| character.is_ascii_hexdigit()) {
+        return Err(format!("Expected a SHA-256 fingerprint, got '{}'", fingerprint));
+    }
+    Ok(hex)
+}
+
+// Connects to the server and returns its certificate if it has the pinned fingerprint.
+fn fetch_pinned_certificate(url: &Url, fingerprint: &str) -> Result<Certificate, String> {
+    let host = url.host_str().ok_or_else(|| format!("'{}' has no host", url))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let error = |e: &dyn std::error::Error| format!("Failed to fetch the certificate of '{}:{}': {}", host, port, http_client::describe(e));
+
+    let address = (host, port).to_socket_addrs().map_err(|e| error(&e))?
+        .next()
+        .ok_or_else(|| format!("'{}' has no address", host))?;
+    let stream = TcpStream::connect_timeout(&address, http_client::policy().connect_timeout).map_err(|e| error(&e))?;
+
+    // the certificate is checked by its fingerprint below
+    let mut connector = SslConnector::builder(SslMethod::tls()).map_err(|e| error(&e))?;
+    connector.set_verify(SslVerifyMode::NONE);
+    let tls_stream = connector.build().configure().map_err(|e| error(&e))?
+        .verify_hostname(false)
+        .connect(host, stream)
+        .map_err(|e| error(&e))?;
+
+    let certificate = tls_stream.ssl().peer_certificate()
+        .ok_or_else(|| format!("'{}:{}' sent no certificate", host, port))?;
+    let digest = certificate.digest(MessageDigest::sha256()).map_err(|e| error(&e))?;
+    let actual: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    if actual != fingerprint {
+        return Err(format!("Certificate of '{}:{}' has fingerprint {}, but {} is pinned", host, port, actual, fingerprint));
+    }
+
+    info!("Certificate of '{}:{}' matches the pinned fingerprint", host, port);
+    let pem = certificate.to_pem().map_err(|e| error(&e))?;
+    Certificate::from_pem(&pem).map_err(|e| error(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::webdav::tls::normalise_fingerprint;
+
+    #[test]
+    fn fingerprint_formats() {
+        let hex = "3f0c5d6e2a7b8c9d0e1f2a3b4c5d6e7f8091a2b3c4d5e6f708192a3b4c5d6e7f";
+
+        assert_eq!(normalise_fingerprint("SHA256 Fingerprint=3F:0C:5D:6E:2A:7B:8C:9D:0E:1F:2A:3B:4C:5D:6E:7F:80:91:A2:B3:C4:D5:E6:F7:08:19:2A:3B:4C:5D:6E:7F"), Ok(hex.to_string()));
+        assert_eq!(normalise_fingerprint(&format!("sha256:{}", hex)), Ok(hex.to_string()));
+        assert!(normalise_fingerprint("3F:0C").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono_tz::Tz;
+
+use crate::assets;
+use crate::webdav::calendar::vtimezone::{TimeZone, to_utc};
+use crate::webdav::calendar::writing::{NewEntry, When};
+use crate::website::escape_html;
+
+// events entered without an end last an hour
+const DEFAULT_EVENT_LENGTH: i64 = 60;
+
+/// The quick-add form with a choice of `calendars` and an optional message above it.
+pub(crate) fn render_form(calendars: &[String], message: &str) -> Result<String, String> {
+    let html_content = assets::read_to_string("templates/add.html")?;
+
+    let options: String = calendars.iter()
+        .map(|name| format!("<option value=\"{0}\">{0}</option>", escape_html(name)))
+        .collect();
+
+    Ok(html_content
+        .replace("#calendar_options", &options)
+        .replace("#message", &escape_html(message)))
+}
+
+/// Reads the calendar and the entry from the fields of the form or the API request.
+///
+/// Fields are `kind` (todo or event), `calendar`, `summary`, `description`, `location`, `date`
+/// (YYYY-MM-DD), `time` and `end_time` (HH:MM). Without a time todos are due and events last
+/// all day. Times are wall-clock times of the display zone. A time without a date and an end
+/// without a start or equal to it are rejected.
+pub(crate) fn parse_entry(fields: &HashMap<String, String>, display_zone: Option<Tz>) -> Result<(String, NewEntry), String> {
+    let field = |key: &str| fields.get(key).map(|value| value.trim()).unwrap_or_default();
+
+    let calendar = field("calendar");
+    if calendar.is_empty() {
+        return Err("No calendar chosen".to_string());
+    }
+
+    let summary = field("summary");
+    if summary.is_empty() {
+        return Err("The title is missing".to_string());
+    }
+
+    let date = match field("date") {
+        "" => None,
+        date => Some(NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|e| format!("Invalid date '{}': {}", date, e))?),
+    };
+    let time = parse_time(field("time"))?;
+    let end_time = parse_time(field("end_time"))?;
+
+    let utc = |date: NaiveDate, time: NaiveTime| {
+        When::Utc(to_utc(date.and_time(time), &TimeZone::Floating, &[], display_zone))
+    };
+
+    let entry = match field("kind") {
+        "todo" | "" if date.is_none() && time.is_some() => return Err("A due time needs a date".to_string()),
+        "todo" | "" => NewEntry::Todo {
+            summary: summary.to_string(),
+            description: field("description").to_string(),
+            due: match (date, time) {
+                (Some(date), Some(time)) => Some(utc(date, time)),
+                (Some(date), None) => Some(When::Date(date)),
+                (None, _) => None,
+            },
+        },
+        "event" => {
+            let date = date.ok_or("An event needs a date")?;
+            if time.is_none() && end_time.is_some() {
+                return Err("An end time needs a start time".to_string());
+            }
+            if time.is_some() && end_time == time {
+                return Err("The end has to differ from the start".to_string());
+            }
+            let (start, end) = match time {
+                Some(time) => {
+                    let start: NaiveDateTime = date.and_time(time);
+                    let end = match end_time {
+                        Some(end_time) if end_time > time => date.and_time(end_time),
+                        // ends after midnight
+                        Some(end_time) => (date + Duration::days(1)).and_time(end_time),
+                        None => start + Duration::minutes(DEFAULT_EVENT_LENGTH),
+                    };
+                    (utc(start.date(), start.time()), utc(end.date(), end.time()))
+                },
+                // the end of all-day events is exclusive
+                None => (When::Date(date), When::Date(date + Duration::days(1))),
+            };
+
+            NewEntry::Event {
+                summary: summary.to_string(),
+                description: field("description").to_string(),
+                location: field("location").to_string(),
+                start,
+                end,
+            }
+        },
+        kind => return Err(format!("Unknown kind of entry '{}'", kind)),
+    };
+
+    Ok((calendar.to_string(), entry))
+}
+
+fn parse_time(value: &str) -> Result<Option<NaiveTime>, String> {
+    match value {
+        "" => Ok(None),
+        value => NaiveTime::parse_from_str(value, "%H:%M")
+            .map(Some)
+            .map_err(|e| format!("Invalid time '{}': {}", value, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::NaiveDate;
+    use chrono_tz::Tz;
+
+    use crate::webdav::calendar::writing::{NewEntry, When};
+    use crate::website::add::parse_entry;
+
+    fn fields(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn entry_parsing() {
+        let berlin: Tz = "Europe/Berlin".parse().unwrap();
+
+        let (calendar, todo) = parse_entry(&fields(&[
+            ("calendar", "family"), ("summary", " Milch kaufen "), ("date", "2023-10-13")]), Some(berlin)).unwrap();
+        assert_eq!(calendar, "family");
+        assert_eq!(todo, NewEntry::Todo {
+            summary: "Milch kaufen".to_string(),
+            description: "".to_string(),
+            due: Some(When::Date(NaiveDate::from_ymd_opt(2023, 10, 13).unwrap())),
+        });
+
+        let (_, event) = parse_entry(&fields(&[
+            ("kind", "event"), ("calendar", "family"), ("summary", "Zahnarzt"),
+            ("date", "2023-10-20"), ("time", "10:00"), ("end_time", "10:30")]), Some(berlin)).unwrap();
+        match event {
+            NewEntry::Event { start, end, .. } => {
+                let date = NaiveDate::from_ymd_opt(2023, 10, 20).unwrap();
+                assert_eq!(start, When::Utc(date.and_hms_opt(8, 0, 0).unwrap()));
+                assert_eq!(end, When::Utc(date.and_hms_opt(8, 30, 0).unwrap()));
+            },
+            _ => panic!("expected an event"),
+        }
+
+        assert!(parse_entry(&fields(&[("calendar", "family"), ("summary", "")]), None).is_err());
+        assert!(parse_entry(&fields(&[("kind", "event"), ("calendar", "family"), ("summary", "Party")]), None).is_err());
+        assert!(parse_entry(&fields(&[("calendar", "family"), ("summary", "x"), ("time", "25:00")]), None).is_err());
+        // a due time without a date would be lost
+        assert!(parse_entry(&fields(&[("calendar", "family"), ("summary", "x"), ("time", "10:00")]), None).is_err());
+        // an end at the start isn't an event lasting until the next day
+        assert!(parse_entry(&fields(&[("kind", "event"), ("calendar", "family"), ("summary", "x"),
+            ("date", "2023-10-20"), ("time", "10:00"), ("end_time", "10:00")]), None).is_err());
+        assert!(parse_entry(&fields(&[("kind", "event"), ("calendar", "family"), ("summary", "x"),
+            ("date", "2023-10-20"), ("end_time", "10:00")]), None).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use chrono::{Datelike, NaiveDate};
+
+use crate::agenda::{AgendaDay, AgendaEntry};
+use crate::locale::Locale;
+use crate::website::escape_html;
+
+/// Table rows of the agenda, a heading per day followed by its entries.
+///
+/// Entries carry the colour, grey and pattern of their calendar as CSS variables and classes,
+/// styles.css decides which of them are shown.
+pub(crate) fn render_agenda(agenda: &[AgendaDay], locale: Locale) -> String {
+    let mut rows = String::new();
+
+    for day in agenda {
+        rows.push_str(&format!("<tr class=\"agendaDay\"><td colspan=\"2\">{}, {}</td></tr>\n",
+                               locale.weekday(day.date.weekday()),
+                               locale.day_and_month(day.date)));
+
+        if day.entries.is_empty() {
+            rows.push_str(&format!("<tr class=\"agendaEmpty\"><td colspan=\"2\">{}</td></tr>\n", locale.text("Keine Termine")));
+        }
+        for entry in &day.entries {
+            rows.push_str(&render_entry(entry));
+        }
+    }
+    rows
+}
+
+fn render_entry(entry: &AgendaEntry) -> String {
+    let location = if entry.location.is_empty() {
+        String::new()
+    } else {
+        format!(" <span class=\"entryLocation\">{}</span>", escape_html(&entry.location))
+    };
+    let span = if entry.span_days > 1 {
+        format!(" <span class=\"entrySpan\">({}/{})</span>", entry.day_of_span, entry.span_days)
+    } else {
+        String::new()
+    };
+
+    format!("<tr class=\"agendaEntry pattern-{}\" style=\"--calendar-color: {}; --calendar-grey: {}\">\
+            <td class=\"entryTime\">{}</td>\
+            <td class=\"entrySummary\"><span class=\"calendarMarker\" title=\"{}\"></span>{}{}{}</td></tr>\n",
+            entry.style.pattern,
+            entry.style.color,
+            entry.style.grey,
+            escape_html(&entry.time),
+            escape_html(&entry.style.label),
+            escape_html(&entry.summary),
+            span,
+            location)
+}
+
+/// Formats a date the way the panel shows it, e.g. "Donnerstag" and "12.10.2023".
+pub(crate) fn format_today(date: NaiveDate, locale: Locale) -> (&'static str, String) {
+    (locale.weekday(date.weekday()), locale.date(date))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::agenda::{AgendaDay, AgendaEntry, CalendarStyle};
+    use crate::locale::Locale;
+    use crate::website::agenda::render_agenda;
+
+    #[test]
+    fn agenda_rows() {
+        let date = NaiveDate::from_ymd_opt(2023,10,12).unwrap();
+        let agenda = vec![
+            AgendaDay { date, entries: vec![AgendaEntry {
+                summary: "Eltern & Kinder".to_string(),
+                location: "Schule".to_string(),
+                start: date.and_hms_opt(18,0,0).unwrap(),
+                end: date.and_hms_opt(19,0,0).unwrap(),
+                is_all_day: false,
+                time: "18:00 - 19:00".to_string(),
+                day_of_span: 1,
+                span_days: 1,
+                style: CalendarStyle::new("Familie", "#43A047", 1),
+            }]},
+            AgendaDay { date: date.succ_opt().unwrap(), entries: vec![] },
+        ];
+
+        let rows = render_agenda(&agenda, Locale::German);
+
+        assert!(rows.starts_with("<tr class=\"agendaDay\"><td colspan=\"2\">Donnerstag, 12.10.</td></tr>\n"));
+        assert!(rows.contains("<tr class=\"agendaEntry pattern-striped\" style=\"--calendar-color: #43A047; --calendar-grey: #7A7A7A\">"));
+        assert!(rows.contains("Eltern &amp; Kinder <span class=\"entryLocation\">Schule</span>"));
+        assert!(rows.ends_with("<tr class=\"agendaEmpty\"><td colspan=\"2\">Keine Termine</td></tr>\n"));
+        assert!(render_agenda(&agenda, Locale::English).starts_with("<tr class=\"agendaDay\"><td colspan=\"2\">Thursday, 12/10</td></tr>\n"));
+    }
+}
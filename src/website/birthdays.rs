@@ -0,0 +1,60 @@
+use chrono::NaiveDate;
+
+use crate::locale::Locale;
+use crate::webdav::contacts::{Anniversary, AnniversaryKind};
+use crate::website::escape_html;
+
+/// Table rows of the "birthdays this week" widget, e.g. "heute  Anna Schmidt (38)".
+pub(crate) fn render_birthdays(anniversaries: &[Anniversary], today: NaiveDate, locale: Locale) -> String {
+    if anniversaries.is_empty() {
+        return format!("<tr class=\"birthdayEmpty\"><td colspan=\"2\">{}</td></tr>\n", locale.text("Keine Geburtstage"));
+    }
+
+    let mut rows = String::new();
+    for anniversary in anniversaries {
+        let age = match anniversary.age {
+            Some(age) => format!(" ({})", age),
+            None => String::new(),
+        };
+        let (class, marker) = match anniversary.kind {
+            AnniversaryKind::Birthday => ("birthday", ""),
+            AnniversaryKind::Anniversary => ("anniversary", locale.text("Jahrestag: ")),
+        };
+
+        rows.push_str(&format!("<tr class=\"{}\"><td class=\"birthdayDate\">{}</td><td class=\"birthdayName\">{}{}{}</td></tr>\n",
+                               class,
+                               locale.relative_day(anniversary.date, today),
+                               marker,
+                               escape_html(&anniversary.name),
+                               age));
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use crate::locale::Locale;
+    use crate::webdav::contacts::{Anniversary, AnniversaryKind};
+    use crate::website::birthdays::render_birthdays;
+
+    #[test]
+    fn birthday_rows() {
+        let today = NaiveDate::from_ymd_opt(2023, 10, 14).unwrap();
+        let anniversaries = vec![
+            Anniversary { name: "Anna & Tom".to_string(), kind: AnniversaryKind::Anniversary, date: today, age: Some(13) },
+            Anniversary { name: "Max".to_string(), kind: AnniversaryKind::Birthday, date: today.succ_opt().unwrap(), age: None },
+            Anniversary { name: "Oma".to_string(), kind: AnniversaryKind::Birthday, date: NaiveDate::from_ymd_opt(2023, 10, 18).unwrap(), age: Some(80) },
+        ];
+
+        let rows = render_birthdays(&anniversaries, today, Locale::German);
+
+        assert!(rows.starts_with("<tr class=\"anniversary\"><td class=\"birthdayDate\">heute</td><td class=\"birthdayName\">Jahrestag: Anna &amp; Tom (13)</td></tr>\n"));
+        assert!(rows.contains("<td class=\"birthdayDate\">morgen</td><td class=\"birthdayName\">Max</td>"));
+        assert!(rows.contains("<td class=\"birthdayDate\">18.10.</td><td class=\"birthdayName\">Oma (80)</td>"));
+
+        assert!(render_birthdays(&[], today, Locale::German).contains("Keine Geburtstage"));
+        assert!(render_birthdays(&anniversaries, today, Locale::English).contains("<td class=\"birthdayDate\">today</td><td class=\"birthdayName\">Anniversary: Anna &amp; Tom (13)</td>"));
+    }
+}
@@ -0,0 +1,22 @@
+pub mod add;
+pub mod agenda;
+pub mod birthdays;
+pub mod offline;
+pub mod status;
+pub mod tasks;
+
+/// Escapes text for use in HTML content and quoted attribute values.
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _default => escaped.push(character),
+        }
+    }
+    escaped
+}
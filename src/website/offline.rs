@@ -0,0 +1,29 @@
+use chrono::{DateTime, Local, Utc};
+use chrono_tz::Tz;
+
+/// The "offline since …" notice shown above the panel while a source can't be reached and its
+/// last result is shown instead. Empty while everything is online.
+pub(crate) fn render_offline(offline_since: Option<DateTime<Utc>>, display_zone: Option<Tz>) -> String {
+    let since = match (offline_since, display_zone) {
+        (None, _) => return String::new(),
+        (Some(since), Some(zone)) => since.with_timezone(&zone).format("%d.%m. %H:%M").to_string(),
+        (Some(since), None) => since.with_timezone(&Local).format("%d.%m. %H:%M").to_string(),
+    };
+
+    format!("<div id=\"offline\">Offline seit {}</div>", since)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use crate::website::offline::render_offline;
+
+    #[test]
+    fn offline_notice() {
+        let since = Utc.with_ymd_and_hms(2023, 10, 12, 12, 30, 0).unwrap();
+
+        assert_eq!(render_offline(Some(since), Some(chrono_tz::Europe::Berlin)), "<div id=\"offline\">Offline seit 12.10. 14:30</div>");
+        assert_eq!(render_offline(None, Some(chrono_tz::Europe::Berlin)), "");
+    }
+}
@@ -0,0 +1,132 @@
+use chrono::{DateTime, Duration, Local, Utc};
+use chrono_tz::Tz;
+
+use crate::assets;
+use crate::health::{is_failing, SourceHealth};
+use crate::quota::QuotaTracker;
+use crate::website::escape_html;
+
+/// The status page with the given summary and table rows of the sources and quotas.
+pub(crate) fn render_status(summary: &str, source_rows: &str, quota_rows: &str) -> Result<String, String> {
+    let html_content = assets::read_to_string("templates/status.html")?;
+
+    Ok(html_content
+        .replace("#summary", summary)
+        .replace("#sources", source_rows)
+        .replace("#quota", quota_rows))
+}
+
+/// Version and uptime of the panel.
+pub(crate) fn render_summary(started: DateTime<Utc>, now: DateTime<Utc>, display_zone: Option<Tz>) -> String {
+    format!("Version {}, läuft seit {} ({})", env!("CARGO_PKG_VERSION"), format_time(started, display_zone), format_duration(now - started))
+}
+
+/// A table row with the last success and error of a source, when it is read next and how old
+/// the result shown is. Failing sources are marked.
+pub(crate) fn render_source(name: &str, health: &SourceHealth, now: DateTime<Utc>, display_zone: Option<Tz>) -> String {
+    let time = |time: Option<DateTime<Utc>>, otherwise: &str| match time {
+        Some(time) => format_time(time, display_zone),
+        None => otherwise.to_string(),
+    };
+    let last_error = match &health.last_error {
+        Some((at, message)) => format!("{}: {}", format_time(*at, display_zone), escape_html(message)),
+        None => "-".to_string(),
+    };
+    let cache_age = match health.cached_at {
+        Some(cached_at) => format_duration(now - cached_at),
+        None => "-".to_string(),
+    };
+
+    format!("<tr{}><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            if is_failing(health) { " class=\"failing\"" } else { "" },
+            escape_html(name),
+            time(health.last_success, "nie"),
+            last_error,
+            time(health.next_refresh, "beim Aufruf"),
+            cache_age)
+}
+
+/// A table row with the calls made to a provider so far and its current refresh interval.
+pub(crate) fn render_quota(quota: &QuotaTracker, now: DateTime<Utc>) -> String {
+    let usage = |calls: u32, limit: Option<u32>| match limit {
+        Some(limit) => format!("{} / {}", calls, limit),
+        None => calls.to_string(),
+    };
+    let last_refresh = match quota.last_refresh() {
+        Some(last_refresh) => format!("vor {} min", (now - last_refresh).num_minutes()),
+        None => "nie".to_string(),
+    };
+
+    format!("<tr><td>{}</td><td>{}</td><td>{}</td><td>alle {} min</td><td>{}</td></tr>\n",
+            escape_html(&quota.provider),
+            usage(quota.calls_today(now), quota.budget.calls_per_day),
+            usage(quota.calls_this_minute(now), quota.budget.calls_per_minute),
+            quota.refresh_interval(now).as_secs() / 60,
+            last_refresh)
+}
+
+fn format_time(time: DateTime<Utc>, display_zone: Option<Tz>) -> String {
+    match display_zone {
+        Some(zone) => time.with_timezone(&zone).format("%d.%m. %H:%M").to_string(),
+        None => time.with_timezone(&Local).format("%d.%m. %H:%M").to_string(),
+    }
+}
+
+// e.g. "2 Tage 3 h", "3 h 5 min" or "5 min"
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.num_minutes().max(0);
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+    match days {
+        0 if hours == 0 => format!("{} min", minutes),
+        0 => format!("{} h {} min", hours, minutes),
+        1 => format!("1 Tag {} h", hours),
+        _default => format!("{} Tage {} h", days, hours),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::health::SourceHealth;
+    use crate::quota::{Budget, QuotaTracker};
+    use crate::website::status::{render_quota, render_source, render_summary};
+
+    #[test]
+    fn quota_row() {
+        let budget = Budget { calls_per_minute: None, calls_per_day: Some(1000), refresh: Duration::from_secs(600), calls_per_refresh: 2 };
+        let mut quota = QuotaTracker::new("openweather", budget, None);
+        let now = Utc.with_ymd_and_hms(2023, 10, 12, 12, 0, 0).unwrap();
+
+        assert_eq!(render_quota(&quota, now), "<tr><td>openweather</td><td>0 / 1000</td><td>0</td><td>alle 10 min</td><td>nie</td></tr>\n");
+
+        quota.record_call(now);
+        quota.record_call(now);
+        quota.record_refresh(now);
+        assert_eq!(render_quota(&quota, now + chrono::Duration::minutes(3)),
+                   "<tr><td>openweather</td><td>2 / 1000</td><td>0</td><td>alle 10 min</td><td>vor 3 min</td></tr>\n");
+    }
+
+    #[test]
+    fn source_row() {
+        let now = Utc.with_ymd_and_hms(2023, 10, 12, 12, 30, 0).unwrap();
+        let zone = Some(chrono_tz::Europe::Berlin);
+        let health = SourceHealth {
+            last_success: Some(now - chrono::Duration::minutes(90)),
+            last_error: Some((now, "Server <down>".to_string())),
+            next_refresh: None,
+            cached_at: Some(now - chrono::Duration::minutes(90)),
+        };
+
+        assert_eq!(render_source("calendar_family", &health, now, zone),
+                   "<tr class=\"failing\"><td>calendar_family</td><td>12.10. 13:00</td><td>12.10. 14:30: Server &lt;down&gt;</td><td>beim Aufruf</td><td>1 h 30 min</td></tr>\n");
+        assert_eq!(render_source("feed_school", &SourceHealth::default(), now, zone),
+                   "<tr><td>feed_school</td><td>nie</td><td>-</td><td>beim Aufruf</td><td>-</td></tr>\n");
+
+        assert_eq!(render_summary(now - chrono::Duration::hours(50), now, zone),
+                   format!("Version {}, läuft seit 10.10. 12:30 (2 Tage 2 h)", env!("CARGO_PKG_VERSION")));
+    }
+}
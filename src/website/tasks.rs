@@ -0,0 +1,89 @@
+use chrono::NaiveDateTime;
+
+use crate::locale::Locale;
+use crate::webdav::calendar::Calendar;
+use crate::webdav::calendar::tasks::Task;
+use crate::website::escape_html;
+
+// days ahead a todo counts as upcoming
+const UPCOMING_DAYS: i64 = 7;
+
+/// Table rows of the open todos of a calendar, subtasks indented below their parent.
+///
+/// Every row has a button completing the todo through `/todos/complete`.
+pub(crate) fn render_tasks(calendar: &Calendar, locale: Locale) -> String {
+    let now = calendar.now();
+    let mut rows = String::new();
+    for task in calendar.tasks() {
+        render_task(calendar, &task, 0, now, locale, &mut rows);
+    }
+    rows
+}
+
+fn render_task(calendar: &Calendar, task: &Task, depth: usize, now: NaiveDateTime, locale: Locale, rows: &mut String) {
+    let class = if task.is_overdue(now) {
+        "task overdue"
+    } else if task.is_due_today(now) {
+        "task dueToday"
+    } else if task.is_upcoming(now, UPCOMING_DAYS) {
+        "task upcoming"
+    } else {
+        "task"
+    };
+
+    let due = match task.due {
+        Some(due) if task.todo.is_all_day => locale.day_and_month(due.date()),
+        Some(due) => locale.day_and_time(due),
+        None => String::new(),
+    };
+
+    let (path, e_tag) = match calendar.resource_of(&task.todo.uid) {
+        Some(resource) => (resource.path.as_str(), resource.e_tag.as_str()),
+        None => ("", ""),
+    };
+
+    rows.push_str(&format!(
+        "<tr class=\"{}\"><td class=\"taskSummary\" style=\"padding-left: {}em\">{}</td><td class=\"taskDue\">{}</td>\
+        <td><form method=\"post\" action=\"/todos/complete\">\
+        <input type=\"hidden\" name=\"uid\" value=\"{}\">\
+        <input type=\"hidden\" name=\"path\" value=\"{}\">\
+        <input type=\"hidden\" name=\"e_tag\" value=\"{}\">\
+        <button class=\"taskDone\" type=\"submit\">&#10003;</button></form></td></tr>\n",
+        class,
+        depth,
+        escape_html(&task.todo.summary),
+        due,
+        escape_html(&task.todo.uid),
+        escape_html(path),
+        escape_html(e_tag)));
+
+    for subtask in &task.subtasks {
+        render_task(calendar, subtask, depth + 1, now, locale, rows);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
+    use crate::locale::Locale;
+    use crate::webdav::calendar::Calendar;
+    use crate::website::tasks::render_tasks;
+
+    #[test]
+    fn task_rows() {
+        let mut file = File::open("data/test/vtodo_tree.ics").unwrap();
+        let mut ics = String::new();
+        file.read_to_string(&mut ics).unwrap();
+
+        let mut calendar = Calendar::new("tasks".to_string(), &[]);
+        calendar.add_resource("//tasks/tree.ics", "\"1234\"", &ics).unwrap();
+        let rows = render_tasks(&calendar, Locale::German);
+
+        assert_eq!(rows.lines().count(), 6);
+        assert!(rows.contains("<input type=\"hidden\" name=\"path\" value=\"//tasks/tree.ics\">"));
+        assert!(rows.contains("<input type=\"hidden\" name=\"e_tag\" value=\"&quot;1234&quot;\">"));
+        assert!(rows.contains("padding-left: 1em\">Belege sammeln</td>"));
+    }
+}